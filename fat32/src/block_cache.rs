@@ -1,7 +1,8 @@
-use super::{BlockDevice, BLOCK_SZ};
-use alloc::collections::VecDeque;
+use super::{println, BlockDevice, BlockError, BLOCK_SZ};
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::sync::Arc;
-use lazy_static::*;
+use alloc::vec;
+use alloc::vec::Vec;
 use spin::RwLock;
 
 pub struct BlockCache {
@@ -13,9 +14,21 @@ pub struct BlockCache {
 
 impl BlockCache {
     /// Load a new BlockCache from disk.
-    pub fn new(block_id: usize, block_device: Arc<dyn BlockDevice>) -> Self {
+    pub fn new(block_id: usize, block_device: Arc<dyn BlockDevice>) -> Result<Self, BlockError> {
         let mut cache = [0u8; BLOCK_SZ];
-        block_device.read_block(block_id, &mut cache);
+        block_device.read_block(block_id, &mut cache)?;
+        Ok(Self {
+            cache,
+            block_id,
+            block_device,
+            modified: false,
+        })
+    }
+
+    /// 用已经从磁盘读出的数据构造BlockCache
+    pub fn from_data(block_id: usize, data: &[u8], block_device: Arc<dyn BlockDevice>) -> Self {
+        let mut cache = [0u8; BLOCK_SZ];
+        cache.copy_from_slice(data);
         Self {
             cache,
             block_id,
@@ -57,95 +70,275 @@ impl BlockCache {
         f(self.get_mut(offset))
     }
 
-    pub fn sync(&mut self) {
+    /// 写回脏块，失败时保留脏标记以便之后重试
+    pub fn sync(&mut self) -> Result<(), BlockError> {
         if self.modified {
+            self.block_device.write_block(self.block_id, &self.cache)?;
             self.modified = false;
-            self.block_device.write_block(self.block_id, &self.cache);
         }
+        Ok(())
+    }
+
+    /// 取出脏块的内容并清除脏标记，由调用者负责写回
+    fn take_dirty(&mut self) -> Option<DirtyBlock> {
+        if !self.modified {
+            return None;
+        }
+        self.modified = false;
+        Some(DirtyBlock {
+            block_id: self.block_id,
+            cache: None,
+            block_device: Arc::clone(&self.block_device),
+            data: self.cache,
+        })
     }
 }
 
+struct DirtyBlock {
+    block_id: usize,
+    cache: Option<Arc<RwLock<BlockCache>>>, // 写回失败时用于恢复脏标记
+    block_device: Arc<dyn BlockDevice>,
+    data: [u8; BLOCK_SZ],
+}
+
 impl Drop for BlockCache {
     fn drop(&mut self) {
-        self.sync()
+        // 换出时写回失败无法再上报，数据只能丢弃
+        if self.sync().is_err() {
+            println!("[fs]: failed to write back block {}", self.block_id);
+        }
     }
 }
 
-const BLOCK_CACHE_SIZE: usize = 10;
+/// 默认的缓存容量(块数)，可在FAT32Manager::open时指定
+pub const DEFAULT_CACHE_CAPACITY: usize = 128;
+/// 哈希桶的数量
+const CACHE_BUCKETS: usize = 64;
+/// 顺序读时最多预读的块数
+pub const READ_AHEAD_BLOCKS: usize = 32;
+
+/// 缓存的统计信息
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CacheStats {
+    pub hits: usize,       // 命中次数
+    pub misses: usize,     // 未命中次数
+    pub read_ahead: usize, // 预读的块数
+    pub cached: usize,     // 当前缓存的块数
+    pub capacity: usize,   // 容量
+}
+
+/// 缓存块的键：(设备标识, 块号)
+type CacheKey = (usize, usize);
+
+/// 以Arc指向的设备对象地址作为设备标识
+fn device_id(block_device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(block_device) as *const u8 as usize
+}
+
+struct CacheEntry {
+    key: CacheKey,
+    stamp: u64, // 最近一次访问的时间戳
+    cache: Arc<RwLock<BlockCache>>,
+}
+
+/// 按(设备, 块号)哈希的LRU缓存
+/// 所有块都被占用时容量临时增长，不会panic
 pub struct BlockCacheManager {
-    start_sec: usize,
-    queue: VecDeque<(usize, Arc<RwLock<BlockCache>>)>,
+    capacity: usize,
+    buckets: Vec<Vec<CacheEntry>>,
+    lru: BTreeMap<u64, CacheKey>, // 访问时间戳 -> 键，最早访问的在前
+    clock: u64,
+    len: usize,
+    last_block: CacheKey, // 最近访问的块，用于判断顺序读
+    stats: CacheStats,
 }
 
 impl BlockCacheManager {
-    pub fn new() -> Self {
+    pub fn new(capacity: usize) -> Self {
         Self {
-            start_sec: 0,
-            queue: VecDeque::new(),
+            capacity: capacity.max(1),
+            buckets: (0..CACHE_BUCKETS).map(|_| Vec::new()).collect(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            len: 0,
+            last_block: (0, usize::MAX),
+            stats: CacheStats::default(),
         }
     }
 
-    pub fn set_start_sec(&mut self, new_start_sec: usize) {
-        self.start_sec = new_start_sec;
+    /// 设置容量，超出的未被占用的块会被换出
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        while self.len > self.capacity && self.evict_one() {}
     }
 
-    pub fn get_start_sec(&self) -> usize {
-        self.start_sec
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            cached: self.len,
+            capacity: self.capacity,
+            ..self.stats
+        }
+    }
+
+    fn bucket(key: CacheKey) -> usize {
+        key.1 % CACHE_BUCKETS
+    }
+
+    fn find(&self, key: CacheKey) -> Option<&CacheEntry> {
+        self.buckets[Self::bucket(key)]
+            .iter()
+            .find(|entry| entry.key == key)
+    }
+
+    fn contains(&self, key: CacheKey) -> bool {
+        self.find(key).is_some()
+    }
+
+    /// 查找缓存块并更新其访问时间
+    fn touch(&mut self, key: CacheKey) -> Option<Arc<RwLock<BlockCache>>> {
+        self.clock += 1;
+        let clock = self.clock;
+        let entry = self.buckets[Self::bucket(key)]
+            .iter_mut()
+            .find(|entry| entry.key == key)?;
+        let old_stamp = entry.stamp;
+        entry.stamp = clock;
+        let cache = Arc::clone(&entry.cache);
+        self.lru.remove(&old_stamp);
+        self.lru.insert(clock, key);
+        Some(cache)
+    }
+
+    fn insert(&mut self, key: CacheKey, cache: Arc<RwLock<BlockCache>>) {
+        self.clock += 1;
+        self.buckets[Self::bucket(key)].push(CacheEntry {
+            key,
+            stamp: self.clock,
+            cache,
+        });
+        self.lru.insert(self.clock, key);
+        self.len += 1;
     }
 
-    pub fn read_block_cache(&self, block_id: usize) -> Option<Arc<RwLock<BlockCache>>> {
-        if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
-            Some(Arc::clone(&pair.1))
-        } else {
-            None
+    /// 换出最久未访问且未被占用的块，全部被占用时返回false
+    fn evict_one(&mut self) -> bool {
+        let victim = self.lru.iter().find_map(|(stamp, key)| {
+            let entry = self.find(*key).unwrap();
+            if Arc::strong_count(&entry.cache) == 1 {
+                Some((*stamp, *key))
+            } else {
+                None
+            }
+        });
+        match victim {
+            Some((stamp, key)) => {
+                self.lru.remove(&stamp);
+                let bucket = &mut self.buckets[Self::bucket(key)];
+                let idx = bucket.iter().position(|e| e.key == key).unwrap();
+                bucket.swap_remove(idx); // drop时写回
+                self.len -= 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 为新块腾出空间，prefetch为true时不允许超出容量
+    fn make_room(&mut self, prefetch: bool) -> bool {
+        while self.len >= self.capacity {
+            if !self.evict_one() {
+                return !prefetch;
+            }
         }
+        true
     }
 
     pub fn get_block_cache(
         &mut self,
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
-    ) -> Arc<RwLock<BlockCache>> {
-        if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
-            Arc::clone(&pair.1)
-        } else {
-            // substitute
-            if self.queue.len() == BLOCK_CACHE_SIZE {
-                // from front to tail
-                if let Some((idx, _)) = self
-                    .queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
-                {
-                    self.queue.drain(idx..=idx);
-                } else {
-                    panic!("Run out of BlockCache!");
-                }
-            }
-            // load block into mem and push back
-            let block_cache = Arc::new(RwLock::new(BlockCache::new(
-                block_id,
-                Arc::clone(&block_device),
-            )));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
-            block_cache
+    ) -> Result<Arc<RwLock<BlockCache>>, BlockError> {
+        let key = (device_id(&block_device), block_id);
+        self.last_block = key;
+        if let Some(cache) = self.touch(key) {
+            self.stats.hits += 1;
+            return Ok(cache);
         }
+        self.stats.misses += 1;
+        // load block into mem，读取失败的块不进入缓存
+        let block_cache = Arc::new(RwLock::new(BlockCache::new(
+            block_id,
+            Arc::clone(&block_device),
+        )?));
+        self.make_room(false);
+        self.insert(key, Arc::clone(&block_cache));
+        Ok(block_cache)
     }
 
-    pub fn drop_all(&mut self) {
-        self.queue.clear();
+    /// 是否为未缓存的顺序访问，此时应当预读
+    pub fn need_read_ahead(&self, block_id: usize, block_device: &Arc<dyn BlockDevice>) -> bool {
+        let key = (device_id(block_device), block_id);
+        self.last_block == (key.0, block_id.wrapping_sub(1)) && !self.contains(key)
+    }
+
+    /// 尝试为n个新块腾出空间，返回实际可以放入的块数
+    fn reserve(&mut self, n: usize) -> usize {
+        while self.len + n > self.capacity && self.evict_one() {}
+        n.min(self.capacity.saturating_sub(self.len))
+    }
+
+    /// 预读[start, start + count)中未缓存的块，连续的块用一次多块读取完成
+    /// 缓存已满且无法换出或者读取出错时放弃
+    pub fn prefetch(&mut self, start: usize, count: usize, block_device: Arc<dyn BlockDevice>) {
+        let dev = device_id(&block_device);
+        let end = start + count;
+        let mut block_id = start;
+        while block_id < end {
+            if self.contains((dev, block_id)) {
+                block_id += 1;
+                continue;
+            }
+            let mut run = 1;
+            while block_id + run < end && !self.contains((dev, block_id + run)) {
+                run += 1;
+            }
+            let n = self.reserve(run);
+            if n == 0 {
+                return;
+            }
+            let mut data = vec![0u8; n * BLOCK_SZ];
+            if block_device.read_blocks(block_id, &mut data).is_err() {
+                return;
+            }
+            for (i, chunk) in data.chunks(BLOCK_SZ).enumerate() {
+                let block_cache =
+                    BlockCache::from_data(block_id + i, chunk, Arc::clone(&block_device));
+                self.insert((dev, block_id + i), Arc::new(RwLock::new(block_cache)));
+            }
+            self.stats.read_ahead += n;
+            if n < run {
+                return;
+            }
+            block_id += n;
+        }
     }
-}
 
-lazy_static! {
-    pub static ref DATA_BLOCK_CACHE_MANAGER: RwLock<BlockCacheManager> =
-        RwLock::new(BlockCacheManager::new());
+    /// 返回block_id满足条件的缓存块
+    pub fn collect_if(&self, f: impl Fn(usize) -> bool) -> Vec<Arc<RwLock<BlockCache>>> {
+        self.buckets
+            .iter()
+            .flatten()
+            .filter(|entry| f(entry.key.1))
+            .map(|entry| Arc::clone(&entry.cache))
+            .collect()
+    }
 }
 
-lazy_static! {
-    pub static ref INFO_CACHE_MANAGER: RwLock<BlockCacheManager> =
-        RwLock::new(BlockCacheManager::new());
+/// 一个卷的块缓存，目录项(FAT、FSInfo、目录)与文件数据分开缓存
+/// 由FAT32Manager持有，同时挂载的多个卷互不影响
+pub struct VolumeCache {
+    info: RwLock<BlockCacheManager>,
+    data: RwLock<BlockCacheManager>,
 }
 
 #[derive(PartialEq, Copy, Clone, Debug)]
@@ -154,56 +347,116 @@ pub enum CacheMode {
     WRITE,
 }
 
-pub fn get_block_cache(
-    block_id: usize,
-    block_device: Arc<dyn BlockDevice>,
-    rw_mode: CacheMode,
-) -> Arc<RwLock<BlockCache>> {
-    let phy_blk_id = DATA_BLOCK_CACHE_MANAGER.read().get_start_sec() + block_id;
-    if rw_mode == CacheMode::READ {
-        // make sure the blk is in cache
-        DATA_BLOCK_CACHE_MANAGER
-            .write()
-            .get_block_cache(phy_blk_id, block_device);
-        DATA_BLOCK_CACHE_MANAGER
-            .read()
-            .read_block_cache(phy_blk_id)
-            .unwrap()
-    } else {
-        DATA_BLOCK_CACHE_MANAGER
-            .write()
-            .get_block_cache(phy_blk_id, block_device)
+impl VolumeCache {
+    /// capacity为两个缓存各自的容量(块数)
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            info: RwLock::new(BlockCacheManager::new(capacity)),
+            data: RwLock::new(BlockCacheManager::new(capacity)),
+        }
     }
-}
 
-pub fn get_info_cache(
-    block_id: usize,
-    block_device: Arc<dyn BlockDevice>,
-    rw_mode: CacheMode,
-) -> Arc<RwLock<BlockCache>> {
-    let phy_blk_id = INFO_CACHE_MANAGER.read().get_start_sec() + block_id;
-    if rw_mode == CacheMode::READ {
-        // make sure the blk is in cache
-        INFO_CACHE_MANAGER
-            .write()
-            .get_block_cache(phy_blk_id, block_device);
-        INFO_CACHE_MANAGER
-            .read()
-            .read_block_cache(phy_blk_id)
-            .unwrap()
-    } else {
-        INFO_CACHE_MANAGER
-            .write()
-            .get_block_cache(phy_blk_id, block_device)
+    pub fn get_block_cache(
+        &self,
+        block_id: usize,
+        block_device: Arc<dyn BlockDevice>,
+        rw_mode: CacheMode,
+    ) -> Result<Arc<RwLock<BlockCache>>, BlockError> {
+        let _ = rw_mode; // 读写都需要先将块载入缓存
+        self.data.write().get_block_cache(block_id, block_device)
     }
-}
 
-pub fn set_start_sec(start_sec: usize) {
-    INFO_CACHE_MANAGER.write().set_start_sec(start_sec);
-    DATA_BLOCK_CACHE_MANAGER.write().set_start_sec(start_sec);
-}
+    pub fn get_info_cache(
+        &self,
+        block_id: usize,
+        block_device: Arc<dyn BlockDevice>,
+        rw_mode: CacheMode,
+    ) -> Result<Arc<RwLock<BlockCache>>, BlockError> {
+        let _ = rw_mode; // 读写都需要先将块载入缓存
+        self.info.write().get_block_cache(block_id, block_device)
+    }
+
+    /// 将满足条件的脏块写回磁盘，块仍保留在缓存中
+    /// 出错时继续写回其余的块，返回第一个错误，写回失败的块重新标脏
+    fn sync_if(&self, f: impl Fn(usize) -> bool) -> Result<(), BlockError> {
+        let mut caches = self.info.read().collect_if(&f);
+        caches.append(&mut self.data.read().collect_if(&f));
+        // 释放管理器的锁之后再写回，避免与持有缓存块的调用者死锁
+        // 先复制出脏块的内容，不同时持有多个缓存块的锁
+        let mut dirty: Vec<DirtyBlock> = caches
+            .iter()
+            .filter_map(|cache| {
+                let mut block = cache.write().take_dirty()?;
+                block.cache = Some(Arc::clone(cache));
+                Some(block)
+            })
+            .collect();
+        dirty.sort_unstable_by_key(|block| (device_id(&block.block_device), block.block_id));
+        // 同一设备上连续的脏块合并为一次多块写入
+        let mut result = Ok(());
+        let mut i = 0;
+        while i < dirty.len() {
+            let mut j = i + 1;
+            while j < dirty.len()
+                && dirty[j].block_id == dirty[j - 1].block_id + 1
+                && Arc::ptr_eq(&dirty[j].block_device, &dirty[i].block_device)
+            {
+                j += 1;
+            }
+            let block_device = &dirty[i].block_device;
+            let written = if j - i == 1 {
+                block_device.write_block(dirty[i].block_id, &dirty[i].data)
+            } else {
+                let mut data = Vec::with_capacity((j - i) * BLOCK_SZ);
+                for block in &dirty[i..j] {
+                    data.extend_from_slice(&block.data);
+                }
+                block_device.write_blocks(dirty[i].block_id, &data)
+            }
+            .and_then(|_| block_device.flush());
+            if let Err(err) = written {
+                for block in &dirty[i..j] {
+                    if let Some(cache) = &block.cache {
+                        cache.write().modified = true;
+                    }
+                }
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+            i = j;
+        }
+        result
+    }
 
-pub fn write_to_dev() {
-    INFO_CACHE_MANAGER.write().drop_all();
-    DATA_BLOCK_CACHE_MANAGER.write().drop_all();
+    /// 将所有脏块写回磁盘
+    pub fn sync_all(&self) -> Result<(), BlockError> {
+        self.sync_if(|_| true)
+    }
+
+    /// 将指定扇区的脏块写回磁盘
+    pub fn sync_blocks(&self, block_ids: &BTreeSet<usize>) -> Result<(), BlockError> {
+        self.sync_if(|block_id| block_ids.contains(&block_id))
+    }
+
+    /// 设置两个缓存各自的容量
+    pub fn set_capacity(&self, capacity: usize) {
+        self.info.write().set_capacity(capacity);
+        self.data.write().set_capacity(capacity);
+    }
+
+    /// 数据块是否需要预读
+    pub fn need_read_ahead(&self, block_id: usize, block_device: &Arc<dyn BlockDevice>) -> bool {
+        self.data.read().need_read_ahead(block_id, block_device)
+    }
+
+    /// 预读从block_id开始的count个数据块
+    pub fn read_ahead(&self, block_id: usize, count: usize, block_device: Arc<dyn BlockDevice>) {
+        self.data.write().prefetch(block_id, count, block_device);
+    }
+
+    /// 返回(目录项缓存, 数据缓存)的统计信息
+    pub fn stats(&self) -> (CacheStats, CacheStats) {
+        (self.info.read().stats(), self.data.read().stats())
+    }
 }
@@ -1,6 +1,50 @@
+use super::BLOCK_SZ;
 use core::any::Any;
 
+/// 块设备读写失败的原因
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockError {
+    IoError,  // 设备报告读写失败
+    NotReady, // 设备未就绪或超时
+}
+
 pub trait BlockDevice: Send + Sync + Any {
-    fn read_block(&self, block_id: usize, buf: &mut [u8]);
-    fn write_block(&self, block_id: usize, buf: &[u8]);
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlockError>;
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// 读取从start_block开始的连续多个块，buf的长度须为块大小的整数倍
+    /// 默认逐块读取，驱动可以用多块传输覆盖
+    fn read_blocks(&self, start_block: usize, buf: &mut [u8]) -> Result<(), BlockError> {
+        let block_size = self.block_size();
+        assert_eq!(buf.len() % block_size, 0);
+        for (i, chunk) in buf.chunks_mut(block_size).enumerate() {
+            self.read_block(start_block + i, chunk)?;
+        }
+        Ok(())
+    }
+
+    /// 写入从start_block开始的连续多个块，buf的长度须为块大小的整数倍
+    fn write_blocks(&self, start_block: usize, buf: &[u8]) -> Result<(), BlockError> {
+        let block_size = self.block_size();
+        assert_eq!(buf.len() % block_size, 0);
+        for (i, chunk) in buf.chunks(block_size).enumerate() {
+            self.write_block(start_block + i, chunk)?;
+        }
+        Ok(())
+    }
+
+    /// 等待设备把已写入的数据落盘
+    fn flush(&self) -> Result<(), BlockError> {
+        Ok(())
+    }
+
+    /// 设备的总块数，0表示未知
+    fn num_blocks(&self) -> usize {
+        0
+    }
+
+    /// 块大小(字节)
+    fn block_size(&self) -> usize {
+        BLOCK_SZ
+    }
 }
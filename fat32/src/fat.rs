@@ -1,91 +1,210 @@
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::ops::Range;
 
 use crate::{
-    block_cache::{get_info_cache, CacheMode},
-    BlockDevice, BAD_CLUSTER, END_CLUSTER, FATENTRY_PER_SEC, FREE_CLUSTER,
+    block_cache::{CacheMode, VolumeCache},
+    BlockDevice, BlockError, BAD_CLUSTER, BLOCK_SZ, END_CLUSTER, FATENTRY_PER_SEC, FREE_CLUSTER,
 };
 
+/// FAT[1]中的干净位，置位表示卷上次被正常卸载
+const CLEAN_SHUTDOWN: u32 = 0x08000000;
+/// 卷被正常卸载时挂载检查只比较的FAT扇区数，均匀分布在整个FAT中
+const MIRROR_SAMPLE_SECTORS: u32 = 8;
+
 // 常驻内存，不作一一映射
 #[allow(unused)]
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct FAT {
     fat1_sector: u32, // FAT1的起始扇区
-    fat2_sector: u32, // FAT2的起始扇区
+    n_fats: u32,      // FAT的份数，各份依次紧邻存放
     n_sectors: u32,   // 大小
     n_entry: u32,     // 表项数量
+    max_cluster: u32, // 最大的有效簇号
+    mirroring: bool,  // 是否向所有FAT写入
+    active_fat: u32,  // 读取时使用的FAT编号
+    cache: Arc<VolumeCache>,
 }
 
 impl FAT {
-    pub fn new(fat1_sector: u32, fat2_sector: u32, n_sectors: u32, n_entry: u32) -> Self {
+    /// n_clusters为数据区的簇数，有效簇号为2..n_clusters+2
+    /// 开启镜像时读FAT1、写所有FAT，否则只读写active_fat号FAT
+    // 参数都是挂载时从引导扇区算出的几何信息，与字段一一对应
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        fat1_sector: u32,
+        n_fats: u32,
+        n_sectors: u32,
+        n_entry: u32,
+        n_clusters: u32,
+        mirroring: bool,
+        active_fat: u32,
+        cache: Arc<VolumeCache>,
+    ) -> Self {
         Self {
             fat1_sector,
-            fat2_sector,
+            n_fats,
             n_sectors,
             n_entry,
+            max_cluster: (n_clusters + 1).min(n_entry - 1),
+            mirroring,
+            active_fat: if mirroring || active_fat >= n_fats {
+                0
+            } else {
+                active_fat
+            },
+            cache,
         }
     }
 
     /* 计算簇对应表项的位置：sector和offset */
-    fn calculate_pos(&self, cluster: u32) -> (u32, u32, u32) {
-        // 返回sector号和offset
-        // 前为FAT1的扇区号，后为FAT2的扇区号，最后为offset
-        let fat1_sec = self.fat1_sector + cluster / FATENTRY_PER_SEC;
-        let fat2_sec = self.fat2_sector + cluster / FATENTRY_PER_SEC;
+    fn calculate_pos(&self, cluster: u32) -> (u32, u32) {
+        // 返回当前使用的FAT中的sector号和offset
+        let sec = self.fat_sector(self.active_fat) + cluster / FATENTRY_PER_SEC;
         let offset = 4 * (cluster % FATENTRY_PER_SEC);
-        (fat1_sec, fat2_sec, offset)
+        (sec, offset)
+    }
+
+    /// 第index份FAT的起始扇区
+    fn fat_sector(&self, index: u32) -> u32 {
+        self.fat1_sector + index * self.n_sectors
+    }
+
+    /// 修改表项时需要写入的FAT编号
+    fn written_fats(&self) -> Range<u32> {
+        if self.mirroring {
+            0..self.n_fats
+        } else {
+            self.active_fat..self.active_fat + 1
+        }
+    }
+
+    /// 簇对应表项在各份需要写入的FAT中所在的扇区
+    pub fn entry_sectors(&self, cluster: u32) -> Vec<u32> {
+        self.written_fats()
+            .map(|index| self.fat_sector(index) + cluster / FATENTRY_PER_SEC)
+            .collect()
     }
 
-    /* 搜索下一个可用簇 */
-    // caller需要确定有足够的空闲簇，这里不作越界检查
+    /// 最大的有效簇号
+    pub fn max_cluster(&self) -> u32 {
+        self.max_cluster
+    }
+
+    /// 从current_cluster之后开始搜索空闲簇，到达末尾后从头绕回
+    /// current_cluster不是有效簇号时从头搜索，没有空闲簇时返回None
     pub fn next_free_cluster(
         &self,
         current_cluster: u32,
         block_device: Arc<dyn BlockDevice>,
-    ) -> u32 {
-        let mut curr_cluster = current_cluster + 1;
-        loop {
-            #[allow(unused)]
-            let (fat1_sec, fat2_sec, offset) = self.calculate_pos(curr_cluster);
-            // 查看当前cluster的表项
-            let entry_val =
-                get_info_cache(fat1_sec as usize, block_device.clone(), CacheMode::READ)
-                    .read()
-                    .read(offset as usize, |&entry_val: &u32| entry_val);
-            if entry_val == FREE_CLUSTER {
-                break;
-            } else {
-                curr_cluster += 1;
+    ) -> Result<Option<u32>, BlockError> {
+        let start = if current_cluster < 2 || current_cluster >= self.max_cluster {
+            2
+        } else {
+            current_cluster + 1
+        };
+        match self.find_free_in(start, self.max_cluster + 1, &block_device)? {
+            Some(cluster) => Ok(Some(cluster)),
+            None => self.find_free_in(2, start, &block_device),
+        }
+    }
+
+    /// 在[from, to)中搜索第一个空闲簇，每次读取一整个扇区的表项
+    fn find_free_in(
+        &self,
+        from: u32,
+        to: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<Option<u32>, BlockError> {
+        let mut cluster = from;
+        while cluster < to {
+            let (fat_sec, _) = self.calculate_pos(cluster);
+            let end = ((cluster / FATENTRY_PER_SEC + 1) * FATENTRY_PER_SEC).min(to);
+            let found = self
+                .cache
+                .get_info_cache(fat_sec as usize, block_device.clone(), CacheMode::READ)?
+                .read()
+                .read(0, |entries: &[u32; FATENTRY_PER_SEC as usize]| {
+                    (cluster..end).find(|&clu| {
+                        entries[(clu % FATENTRY_PER_SEC) as usize] & 0x0FFFFFFF == FREE_CLUSTER
+                    })
+                });
+            if found.is_some() {
+                return Ok(found);
             }
+            cluster = end;
         }
-        curr_cluster & 0x0FFFFFFF
+        Ok(None)
+    }
+
+    /// 扫描整个FAT，对每个空闲簇调用f，返回空闲簇总数
+    pub fn scan_free_clusters(
+        &self,
+        block_device: Arc<dyn BlockDevice>,
+        mut f: impl FnMut(u32),
+    ) -> Result<u32, BlockError> {
+        let mut count = 0;
+        let mut cluster = 2;
+        while cluster <= self.max_cluster {
+            let (fat_sec, _) = self.calculate_pos(cluster);
+            let end =
+                ((cluster / FATENTRY_PER_SEC + 1) * FATENTRY_PER_SEC).min(self.max_cluster + 1);
+            self.cache
+                .get_info_cache(fat_sec as usize, block_device.clone(), CacheMode::READ)?
+                .read()
+                .read(0, |entries: &[u32; FATENTRY_PER_SEC as usize]| {
+                    for clu in cluster..end {
+                        if entries[(clu % FATENTRY_PER_SEC) as usize] & 0x0FFFFFFF == FREE_CLUSTER {
+                            count += 1;
+                            f(clu);
+                        }
+                    }
+                });
+            cluster = end;
+        }
+        Ok(count)
     }
 
     /// 查询当前簇的下一个簇
-    pub fn get_next_cluster(&self, cluster: u32, block_device: Arc<dyn BlockDevice>) -> u32 {
+    pub fn get_next_cluster(
+        &self,
+        cluster: u32,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Result<u32, BlockError> {
         // 需要对损坏簇作出判断
         // 及时使用备用表
         // 无效或未使用返回0
-        let (fat1_sec, fat2_sec, offset) = self.calculate_pos(cluster);
-        //println!("fat1_sec={} offset = {}", fat1_sec, offset);
-        let fat1_rs = get_info_cache(fat1_sec as usize, block_device.clone(), CacheMode::READ)
-            .read()
-            .read(offset as usize, |&next_cluster: &u32| next_cluster);
-        let fat2_rs = get_info_cache(fat2_sec as usize, block_device.clone(), CacheMode::READ)
+        let (fat_sec, offset) = self.calculate_pos(cluster);
+        let next_cluster = self
+            .cache
+            .get_info_cache(fat_sec as usize, block_device.clone(), CacheMode::READ)?
             .read()
             .read(offset as usize, |&next_cluster: &u32| next_cluster);
-        if fat1_rs == BAD_CLUSTER {
-            if fat2_rs == BAD_CLUSTER {
-                0
-            } else {
-                fat2_rs & 0x0FFFFFFF
+        if next_cluster & 0x0FFFFFFF != BAD_CLUSTER {
+            return Ok(next_cluster & 0x0FFFFFFF);
+        }
+        for index in self
+            .written_fats()
+            .filter(|&index| index != self.active_fat)
+        {
+            let sec = self.fat_sector(index) + cluster / FATENTRY_PER_SEC;
+            let backup = self
+                .cache
+                .get_info_cache(sec as usize, block_device.clone(), CacheMode::READ)?
+                .read()
+                .read(offset as usize, |&next_cluster: &u32| next_cluster);
+            if backup & 0x0FFFFFFF != BAD_CLUSTER {
+                return Ok(backup & 0x0FFFFFFF);
             }
-        } else {
-            fat1_rs & 0x0FFFFFFF
         }
+        Ok(0)
     }
 
-    pub fn set_end(&self, cluster: u32, block_device: Arc<dyn BlockDevice>) {
-        self.set_next_cluster(cluster, END_CLUSTER, block_device);
+    pub fn set_end(
+        &self,
+        cluster: u32,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Result<(), BlockError> {
+        self.set_next_cluster(cluster, END_CLUSTER, block_device)
     }
 
     /* 设置当前簇的下一个簇 */
@@ -94,21 +213,103 @@ impl FAT {
         cluster: u32,
         next_cluster: u32,
         block_device: Arc<dyn BlockDevice>,
-    ) {
-        // 同步修改两个FAT
+    ) -> Result<(), BlockError> {
+        // 同步修改所有FAT，表项的高4位保留不变
         // 注意设置末尾项为 0x0FFFFFF8
         //assert_ne!(next_cluster, 0);
-        let (fat1_sec, fat2_sec, offset) = self.calculate_pos(cluster);
-        get_info_cache(fat1_sec as usize, block_device.clone(), CacheMode::WRITE)
-            .write()
-            .modify(offset as usize, |old_clu: &mut u32| {
-                *old_clu = next_cluster;
-            });
-        get_info_cache(fat2_sec as usize, block_device.clone(), CacheMode::WRITE)
-            .write()
-            .modify(offset as usize, |old_clu: &mut u32| {
-                *old_clu = next_cluster;
-            });
+        let (_, offset) = self.calculate_pos(cluster);
+        for sec in self.entry_sectors(cluster) {
+            self.cache
+                .get_info_cache(sec as usize, block_device.clone(), CacheMode::WRITE)?
+                .write()
+                .modify(offset as usize, |old_clu: &mut u32| {
+                    *old_clu = (*old_clu & 0xF0000000) | (next_cluster & 0x0FFFFFFF);
+                });
+        }
+        Ok(())
+    }
+
+    /// 卷是否被正常卸载
+    pub fn is_clean(&self, block_device: Arc<dyn BlockDevice>) -> Result<bool, BlockError> {
+        let (fat_sec, offset) = self.calculate_pos(1);
+        Ok(self
+            .cache
+            .get_info_cache(fat_sec as usize, block_device, CacheMode::READ)?
+            .read()
+            .read(offset as usize, |&entry: &u32| entry & CLEAN_SHUTDOWN != 0))
+    }
+
+    /// 在所有需要写入的FAT中设置干净位，挂载后清除，卸载时置位
+    pub fn set_clean(
+        &self,
+        clean: bool,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Result<(), BlockError> {
+        let (_, offset) = self.calculate_pos(1);
+        for sec in self.entry_sectors(1) {
+            self.cache
+                .get_info_cache(sec as usize, block_device.clone(), CacheMode::WRITE)?
+                .write()
+                .modify(offset as usize, |entry: &mut u32| {
+                    if clean {
+                        *entry |= CLEAN_SHUTDOWN;
+                    } else {
+                        *entry &= !CLEAN_SHUTDOWN;
+                    }
+                });
+        }
+        Ok(())
+    }
+
+    /// 比较各份FAT与当前使用的FAT，返回内容不一致的扇区数
+    /// full为false时只抽查均匀分布的几个扇区
+    /// repair为true时用当前使用的FAT覆盖不一致的扇区，未开启镜像时不检查
+    pub fn check_mirrors(
+        &self,
+        repair: bool,
+        full: bool,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Result<usize, BlockError> {
+        let mut diverged = 0;
+        if !self.mirroring {
+            return Ok(diverged);
+        }
+        let sectors: Vec<u32> = if full || self.n_sectors <= MIRROR_SAMPLE_SECTORS {
+            (0..self.n_sectors).collect()
+        } else {
+            (0..MIRROR_SAMPLE_SECTORS)
+                .map(|i| i * (self.n_sectors / MIRROR_SAMPLE_SECTORS))
+                .collect()
+        };
+        for sec in sectors {
+            let primary = self
+                .cache
+                .get_info_cache(
+                    (self.fat_sector(self.active_fat) + sec) as usize,
+                    block_device.clone(),
+                    CacheMode::READ,
+                )?
+                .read()
+                .read(0, |blk: &[u8; BLOCK_SZ]| *blk);
+            for index in self
+                .written_fats()
+                .filter(|&index| index != self.active_fat)
+            {
+                let cache = self.cache.get_info_cache(
+                    (self.fat_sector(index) + sec) as usize,
+                    block_device.clone(),
+                    CacheMode::READ,
+                )?;
+                let mut cache_writer = cache.write();
+                if cache_writer.read(0, |blk: &[u8; BLOCK_SZ]| *blk != primary) {
+                    diverged += 1;
+                    if repair {
+                        cache_writer.modify(0, |blk: &mut [u8; BLOCK_SZ]| *blk = primary);
+                    }
+                }
+            }
+        }
+        Ok(diverged)
     }
 
     /* 获取某个文件的指定cluster */
@@ -117,31 +318,35 @@ impl FAT {
         start_cluster: u32,
         index: u32,
         block_device: Arc<dyn BlockDevice>,
-    ) -> u32 {
+    ) -> Result<u32, BlockError> {
         // 如果有异常，返回0
         //println!("** get_cluster_at index = {}",index);
         let mut cluster = start_cluster;
         #[allow(unused)]
         for i in 0..index {
             //print!("in fat curr cluster = {}", cluster);
-            cluster = self.get_next_cluster(cluster, block_device.clone());
+            cluster = self.get_next_cluster(cluster, block_device.clone())?;
             //println!(", next cluster = {:X}", cluster);
             if cluster == 0 {
                 break;
             }
         }
-        cluster & 0x0FFFFFFF
+        Ok(cluster & 0x0FFFFFFF)
     }
 
-    pub fn final_cluster(&self, start_cluster: u32, block_device: Arc<dyn BlockDevice>) -> u32 {
+    pub fn final_cluster(
+        &self,
+        start_cluster: u32,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Result<u32, BlockError> {
         let mut curr_cluster = start_cluster;
         assert_ne!(start_cluster, 0);
         loop {
-            let next_cluster = self.get_next_cluster(curr_cluster, block_device.clone());
+            let next_cluster = self.get_next_cluster(curr_cluster, block_device.clone())?;
             //println!("in fianl cl {};{}", curr_cluster, next_cluster);
             //assert_ne!(next_cluster, 0);
             if next_cluster >= END_CLUSTER || next_cluster == 0 {
-                return curr_cluster & 0x0FFFFFFF;
+                return Ok(curr_cluster & 0x0FFFFFFF);
             } else {
                 curr_cluster = next_cluster;
             }
@@ -152,37 +357,114 @@ impl FAT {
         &self,
         start_cluster: u32,
         block_device: Arc<dyn BlockDevice>,
-    ) -> Vec<u32> {
+    ) -> Result<Vec<u32>, BlockError> {
         let mut curr_cluster = start_cluster;
         let mut v_cluster: Vec<u32> = Vec::new();
         loop {
             v_cluster.push(curr_cluster & 0x0FFFFFFF);
-            let next_cluster = self.get_next_cluster(curr_cluster, block_device.clone());
+            let next_cluster = self.get_next_cluster(curr_cluster, block_device.clone())?;
             //println!("in all, curr = {}, next = {}", curr_cluster, next_cluster);
             //assert_ne!(next_cluster, 0);
             if next_cluster >= END_CLUSTER || next_cluster == 0 {
-                return v_cluster;
+                return Ok(v_cluster);
             } else {
                 curr_cluster = next_cluster;
             }
         }
     }
     // 计算文件的簇数量
-    pub fn count_cluster_num(&self, start_cluster: u32, block_device: Arc<dyn BlockDevice>) -> u32 {
+    pub fn count_cluster_num(
+        &self,
+        start_cluster: u32,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Result<u32, BlockError> {
         if start_cluster == 0 {
-            return 0;
+            return Ok(0);
         }
         let mut curr_cluster = start_cluster;
         let mut count: u32 = 0;
         loop {
             count += 1;
-            let next_cluster = self.get_next_cluster(curr_cluster, block_device.clone());
+            let next_cluster = self.get_next_cluster(curr_cluster, block_device.clone())?;
             // println!("next_cluster = {:X}", next_cluster);
             if next_cluster >= END_CLUSTER || next_cluster > 0xF000000 {
-                return count;
+                return Ok(count);
             } else {
                 curr_cluster = next_cluster;
             }
         }
     }
 }
+
+/// 内存中的空闲簇位图，置位表示空闲
+/// 挂载时由FAT构建，此后与FAT同步修改，分配时无需读取FAT
+pub struct FreeBitmap {
+    bits: Vec<u64>,
+    max_cluster: u32,
+    free: u32,
+}
+
+impl FreeBitmap {
+    /// 扫描FAT构建位图
+    pub fn build(fat: &FAT, block_device: Arc<dyn BlockDevice>) -> Result<Self, BlockError> {
+        let max_cluster = fat.max_cluster();
+        let mut bits = vec![0u64; max_cluster as usize / 64 + 1];
+        let free = fat.scan_free_clusters(block_device, |cluster| {
+            bits[cluster as usize / 64] |= 1 << (cluster % 64);
+        })?;
+        Ok(Self {
+            bits,
+            max_cluster,
+            free,
+        })
+    }
+
+    /// 空闲簇数
+    pub fn free_count(&self) -> u32 {
+        self.free
+    }
+
+    pub fn set_free(&mut self, cluster: u32) {
+        let word = &mut self.bits[cluster as usize / 64];
+        let mask = 1 << (cluster % 64);
+        if *word & mask == 0 {
+            *word |= mask;
+            self.free += 1;
+        }
+    }
+
+    pub fn set_used(&mut self, cluster: u32) {
+        let word = &mut self.bits[cluster as usize / 64];
+        let mask = 1 << (cluster % 64);
+        if *word & mask != 0 {
+            *word &= !mask;
+            self.free -= 1;
+        }
+    }
+
+    /// 与FAT::next_free_cluster语义相同，但只查询位图
+    pub fn next_free(&self, current_cluster: u32) -> Option<u32> {
+        let start = if current_cluster < 2 || current_cluster >= self.max_cluster {
+            2
+        } else {
+            current_cluster + 1
+        };
+        self.find_free_in(start, self.max_cluster + 1)
+            .or_else(|| self.find_free_in(2, start))
+    }
+
+    /// 在[from, to)中搜索第一个空闲簇，整字为0时跳过64个簇
+    fn find_free_in(&self, from: u32, to: u32) -> Option<u32> {
+        let mut cluster = from;
+        while cluster < to {
+            let word = self.bits[cluster as usize / 64] >> (cluster % 64);
+            if word == 0 {
+                cluster = (cluster / 64 + 1) * 64;
+                continue;
+            }
+            let found = cluster + word.trailing_zeros();
+            return if found < to { Some(found) } else { None };
+        }
+        None
+    }
+}
@@ -1,30 +1,108 @@
 use super::{
-    get_block_cache, get_info_cache, set_start_sec, write_to_dev, BlockDevice, CacheMode, FSInfo,
-    FatBS, FatExtBS,
+    BlockDevice, BlockError, CacheMode, CacheStats, FSInfo, FatBS, FatExtBS, VolumeCache, BLOCK_SZ,
+    DEFAULT_CACHE_CAPACITY,
 };
 
-use crate::{layout::*, VFile, FAT};
+use crate::{fat::FreeBitmap, layout::*, VFile, FAT};
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use spin::RwLock;
+use core::fmt::{self, Display, Formatter};
+use spin::{Mutex, RwLock};
+
+/// FAT32簇数的下限，簇数更少的卷是FAT12/16
+const MIN_FAT32_CLUSTERS: u32 = 65525;
+/// FAT32簇数的上限，更大的簇号与保留值冲突
+const MAX_FAT32_CLUSTERS: u32 = 0x0FFFFFF4;
+/// 簇大小的上限
+const MAX_CLUSTER_SIZE: u32 = 0x10000;
+
+/// 挂载FAT32卷时的错误
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MountError {
+    /// 读取设备失败
+    Io(BlockError),
+    /// 引导扇区的跳转指令无效
+    BadJump,
+    /// 引导扇区末尾缺少0x55AA签名
+    BadSignature,
+    /// 每扇区字节数不是512、1024、2048或4096
+    BadSectorSize(u16),
+    /// 设备不支持该扇区大小
+    UnsupportedSectorSize(u16),
+    /// 每簇扇区数不是2的幂，或簇大于64KiB
+    BadClusterSize(u8),
+    /// 保留扇区数为0
+    BadReservedSectors,
+    /// FAT的份数为0
+    BadFatCount(u8),
+    /// 根目录簇号不在有效范围内
+    BadRootCluster(u32),
+    /// 不是FAT32卷，可能是FAT12/16
+    NotFat32,
+    /// 簇数超出FAT32的范围
+    TooManyClusters(u32),
+    /// 各区域大小与卷或设备的大小不符
+    BadGeometry,
+    /// FSInfo扇区的签名无效
+    BadFsInfo,
+}
+
+impl From<BlockError> for MountError {
+    fn from(err: BlockError) -> Self {
+        MountError::Io(err)
+    }
+}
+
+impl Display for MountError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            MountError::Io(err) => write!(f, "I/O error ({:?})", err),
+            MountError::BadJump => write!(f, "invalid jump instruction in boot sector"),
+            MountError::BadSignature => write!(f, "missing boot sector signature"),
+            MountError::BadSectorSize(size) => write!(f, "invalid sector size {}", size),
+            MountError::UnsupportedSectorSize(size) => {
+                write!(f, "sector size {} not supported by the device", size)
+            }
+            MountError::BadClusterSize(n) => write!(f, "invalid sectors per cluster {}", n),
+            MountError::BadReservedSectors => write!(f, "no reserved sectors"),
+            MountError::BadFatCount(n) => write!(f, "invalid number of FATs {}", n),
+            MountError::BadRootCluster(cluster) => {
+                write!(f, "invalid root directory cluster {}", cluster)
+            }
+            MountError::NotFat32 => write!(f, "not a FAT32 volume"),
+            MountError::TooManyClusters(n) => write!(f, "too many clusters ({})", n),
+            MountError::BadGeometry => write!(f, "volume layout does not fit"),
+            MountError::BadFsInfo => write!(f, "invalid FSInfo signature"),
+        }
+    }
+}
+
 pub struct FAT32Manager {
     block_device: Arc<dyn BlockDevice>,
+    cache: Arc<VolumeCache>, // 本卷的块缓存
     fsinfo: Arc<FSInfo>,
-    sectors_per_cluster: u32,
-    bytes_per_sector: u32,
+    sectors_per_cluster: u32, // 每簇的块数
+    bytes_per_sector: u32,    // 块缓存的块大小，内部的扇区号都以块为单位
+    sector_size: u32,         // 卷的逻辑扇区大小
     bytes_per_cluster: u32,
     root_sector: u32, // 根扇区 一般为2
     #[allow(unused)]
     total_sectors: u32, //总扇区数
     fat: Arc<RwLock<FAT>>,
+    /// 可选的空闲簇位图，只在持有FAT写锁时修改
+    free_map: Mutex<Option<FreeBitmap>>,
     vroot_dirent: Arc<RwLock<ShortDirEntry>>,
 }
 
 #[allow(unused)]
 // 向block_id 写入 12位
-pub fn create_fat(block_id: usize, device: Arc<dyn BlockDevice>) {
-    let cache = get_info_cache(block_id, device, CacheMode::WRITE);
+pub fn create_fat(
+    block_id: usize,
+    device: Arc<dyn BlockDevice>,
+    cache: &VolumeCache,
+) -> Result<(), BlockError> {
+    let cache = cache.get_info_cache(block_id, device, CacheMode::WRITE)?;
     let mut guard = cache.write();
     guard.modify(0, |fat: &mut u64| {
         *fat = 0xFFFFFFFFFFFFFFFF;
@@ -33,12 +111,13 @@ pub fn create_fat(block_id: usize, device: Arc<dyn BlockDevice>) {
         *fat = 0x0FFFFFFF;
     });
     drop(guard);
+    Ok(())
 }
 
 impl FAT32Manager {
     /// 创建FAT32管理者
-    pub fn create(block_device: Arc<dyn BlockDevice>) -> Arc<RwLock<Self>> {
-        Self::open(Arc::clone(&block_device))
+    pub fn create(block_device: Arc<dyn BlockDevice>) -> Result<Arc<RwLock<Self>>, MountError> {
+        Self::open(Arc::clone(&block_device), DEFAULT_CACHE_CAPACITY)
     }
 
     pub fn sectors_per_cluster(&self) -> u32 {
@@ -49,6 +128,11 @@ impl FAT32Manager {
         self.bytes_per_sector
     }
 
+    /// 卷的逻辑扇区大小，大于块大小时一个扇区由多个块组成
+    pub fn sector_size(&self) -> u32 {
+        self.sector_size
+    }
+
     pub fn bytes_per_cluster(&self) -> u32 {
         self.bytes_per_cluster
     }
@@ -64,87 +148,156 @@ impl FAT32Manager {
         (cluster as usize - 2) * self.sectors_per_cluster as usize + self.root_sector as usize
     }
 
-    /// 打开现有的FAT32
-    pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<RwLock<Self>> {
-        // 读入分区偏移
-        // println!("[fs]: Load FAT32");
-        // 0x1c6 存放起始扇区号 https://www.cnblogs.com/brucemengbm/p/7258268.html
-        let start_sector: u32 = get_info_cache(0, Arc::clone(&block_device), CacheMode::READ)
-            .read()
-            .read(0x1c6, |ssec_bytes: &[u8; 4]| {
-                let mut start_sector: u32 = 0;
-                for i in 0..4 {
-                    let tmp = ssec_bytes[i] as u32;
-                    start_sector = start_sector + (tmp << (8 * i));
-                }
-                start_sector
-            });
-        set_start_sec(start_sector as usize); // block cache
-
+    /// 打开现有的FAT32，cache_capacity为块缓存的容量(块数)
+    /// 引导扇区、FAT和FSInfo不合法时返回MountError
+    pub fn open(
+        block_device: Arc<dyn BlockDevice>,
+        cache_capacity: usize,
+    ) -> Result<Arc<RwLock<Self>>, MountError> {
+        let cache = Arc::new(VolumeCache::new(cache_capacity));
+        // 设备从卷的引导扇区开始，分区表由partition模块解析
         // 读入 Boot Sector DBR分区
         // Arc::clone(&T) = T.clone()
-        let boot_sector: FatBS = get_info_cache(0, block_device.clone(), CacheMode::READ)
-            .read()
-            .read(0, |bs: &FatBS| *bs);
-        // println!("{:?}", boot_sector);
-
+        let boot_cache = cache.get_info_cache(0, block_device.clone(), CacheMode::READ)?;
+        let boot_reader = boot_cache.read();
+        let boot_sector: FatBS = boot_reader.read(0, |bs: &FatBS| *bs);
         // 扩展 DBR分区
         // 读入 Extended Boot Sector
-        let ext_boot_sec: FatExtBS = get_info_cache(0, block_device.clone(), CacheMode::READ)
-            .read()
-            .read(36, |ebs: &FatExtBS| {
-                *ebs // DEBUG
-            });
-        // println!("{:?}", ext_boot_sec);
+        let ext_boot_sec: FatExtBS = boot_reader.read(36, |ebs: &FatExtBS| *ebs);
+        let jump: [u8; 3] = boot_reader.read(0, |jump: &[u8; 3]| *jump);
+        let signature: [u8; 2] = boot_reader.read(510, |sig: &[u8; 2]| *sig);
+        drop(boot_reader);
+        drop(boot_cache);
+
+        // 校验引导扇区
+        if !(jump[0] == 0xEB && jump[2] == 0x90 || jump[0] == 0xE9) {
+            return Err(MountError::BadJump);
+        }
+        if signature != [0x55, 0xAA] {
+            return Err(MountError::BadSignature);
+        }
+        let sector_size = boot_sector.bytes_per_sector;
+        if !matches!(sector_size, 512 | 1024 | 2048 | 4096) {
+            return Err(MountError::BadSectorSize(sector_size));
+        }
+        // 块缓存以BLOCK_SZ为单位，逻辑扇区必须由整数个设备块组成
+        if block_device.block_size() != BLOCK_SZ || sector_size as usize % BLOCK_SZ != 0 {
+            return Err(MountError::UnsupportedSectorSize(sector_size));
+        }
+        let spc = boot_sector.sectors_per_cluster;
+        if !spc.is_power_of_two() || spc as u32 * sector_size as u32 > MAX_CLUSTER_SIZE {
+            return Err(MountError::BadClusterSize(spc));
+        }
+        if boot_sector.reserved_sector_count == 0 {
+            return Err(MountError::BadReservedSectors);
+        }
+        if boot_sector.table_count == 0 {
+            return Err(MountError::BadFatCount(boot_sector.table_count));
+        }
+        // FAT32的根目录在数据区中，FAT大小只记录在扩展引导扇区
+        if boot_sector.root_entry_count != 0
+            || boot_sector.table_size_16 != 0
+            || ext_boot_sec.fat_size() == 0
+        {
+            return Err(MountError::NotFat32);
+        }
+
+        // 以逻辑扇区为单位计算各区域
+        let total_sectors = boot_sector.total_sectors();
+        let fat_size = ext_boot_sec.fat_size(); // fat表的大小
+        let meta_sectors = boot_sector.reserved_sector_count as u64
+            + boot_sector.table_count as u64 * fat_size as u64;
+        if meta_sectors >= total_sectors as u64 {
+            return Err(MountError::BadGeometry);
+        }
+        let n_clusters = (total_sectors - meta_sectors as u32) / spc as u32;
+        if n_clusters < MIN_FAT32_CLUSTERS {
+            return Err(MountError::NotFat32);
+        }
+        if n_clusters > MAX_FAT32_CLUSTERS {
+            return Err(MountError::TooManyClusters(n_clusters));
+        }
+        // FAT32中把簇是以32bit（4个字节）进行编码，FAT需要容纳所有簇的表项
+        let fat_n_entry = (fat_size as u64 * sector_size as u64 / 4).min(u32::MAX as u64) as u32;
+        if fat_n_entry < n_clusters + 2 {
+            return Err(MountError::BadGeometry);
+        }
+        let root_cluster = ext_boot_sec.root_clusters();
+        if root_cluster < 2 || root_cluster > n_clusters + 1 {
+            return Err(MountError::BadRootCluster(root_cluster));
+        }
+
+        // 内部的扇区号都以块为单位
+        let ratio = sector_size as u32 / BLOCK_SZ as u32;
+        let device_blocks = block_device.num_blocks();
+        if device_blocks != 0 && total_sectors as u64 * ratio as u64 > device_blocks as u64 {
+            return Err(MountError::BadGeometry);
+        }
+        let fat_info_sec = ext_boot_sec.fat_info_sec();
+        if fat_info_sec == 0 || fat_info_sec >= boot_sector.reserved_sector_count as u32 {
+            return Err(MountError::BadFsInfo);
+        }
 
         // 读入 FSInfo
-        let fsinfo = FSInfo::new(ext_boot_sec.fat_info_sec());
+        let fsinfo = FSInfo::new(fat_info_sec * ratio, cache.clone());
         // 校验签名
-        assert!(
-            fsinfo.check_signature(block_device.clone()),
-            "Error loading fat32! Illegal signature"
-        );
+        if !fsinfo.check_signature(block_device.clone())? {
+            return Err(MountError::BadFsInfo);
+        }
 
         // 基础信息
-        let sectors_per_cluster = boot_sector.sectors_per_cluster as u32;
-        let bytes_per_sector = boot_sector.bytes_per_sector as u32;
+        let sectors_per_cluster = spc as u32 * ratio;
+        let bytes_per_sector = BLOCK_SZ as u32;
         let bytes_per_cluster = sectors_per_cluster * bytes_per_sector;
 
         // 读取FAT表信息
-        let fat_n_sec = ext_boot_sec.fat_size(); // fat表的大小
-        let fat1_sector = boot_sector.first_fat_sector(); // fat1 扇区起始位置
-        let fat2_sector = fat1_sector + fat_n_sec; // fat2 扇区起始位置
-
-        // FAT32中把簇是以32bit（4个字节）进行编码
-        // 一个扇区有 512/4个入口 用来计算一共有多少entry
-        let fat_n_entry = fat_n_sec * bytes_per_sector / 4; // size * 512 / 4 = 1024
-
-        let fat = FAT::new(fat1_sector, fat2_sector, fat_n_sec, fat_n_entry);
+        let fat_n_sec = fat_size * ratio;
+        let fat1_sector = boot_sector.first_fat_sector() * ratio; // fat1 扇区起始位置
 
         // 根目录地址 = 保留扇区数 + 所有FAT表的扇区数
-        let root_sec =
-            boot_sector.table_count as u32 * fat_n_sec + boot_sector.reserved_sector_count as u32;
+        let root_sec = meta_sectors as u32 * ratio;
+
+        let fat = FAT::new(
+            fat1_sector,
+            boot_sector.table_count as u32,
+            fat_n_sec,
+            fat_n_entry,
+            n_clusters,
+            ext_boot_sec.fat_mirroring(),
+            ext_boot_sec.active_fat(),
+            cache.clone(),
+        );
+
+        // FSInfo中的空闲簇数只是提示，未知或超出簇总数时重新统计，偏小时在空间不足时修正
+        let free_clusters = fsinfo.read_free_clusters(block_device.clone())?;
+        if free_clusters == FREE_COUNT_UNKNOWN || free_clusters > n_clusters {
+            let counted = fat.scan_free_clusters(block_device.clone(), |_| {})?;
+            fsinfo.write_free_clusters(counted, block_device.clone())?;
+        }
 
-        // 初始化root_dirent 这里按理说应该是读取，这里之间自行定义
+        // 初始化root_dirent 根目录没有目录项，这里自行定义
         let mut root_dirent = ShortDirEntry::new(
             &[0x2F, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20], // '/'
             &[0x20, 0x20, 0x20],                               // 空
             ATTRIBUTE_DIRECTORY,                               // 属性为目录
         );
-        root_dirent.set_first_cluster(2); // 设置下一个簇
+        root_dirent.set_first_cluster(root_cluster); // 设置根目录的起始簇
 
         let fat32_manager = Self {
             block_device,
+            cache,
             fsinfo: Arc::new(fsinfo), // 线程安全
             sectors_per_cluster,
             bytes_per_sector,
+            sector_size: sector_size as u32,
             bytes_per_cluster,
             fat: Arc::new(RwLock::new(fat)), // 读写锁
+            free_map: Mutex::new(None),
             root_sector: root_sec,
-            total_sectors: boot_sector.total_sectors(),
+            total_sectors: total_sectors * ratio,
             vroot_dirent: Arc::new(RwLock::new(root_dirent)),
         };
-        Arc::new(RwLock::new(fat32_manager))
+        Ok(Arc::new(RwLock::new(fat32_manager)))
     }
 
     pub fn get_root_vfile(&self, fs_manager: &Arc<RwLock<Self>>) -> VFile {
@@ -156,89 +309,165 @@ impl FAT32Manager {
             long_pos_vec,
             ATTRIBUTE_DIRECTORY,
             fs_manager.clone(),
+            self.cache.clone(),
             self.block_device.clone(),
         )
+        .unwrap() // 根目录的目录项常驻内存，不访问磁盘
     }
 
     pub fn get_root_dirent(&self) -> Arc<RwLock<ShortDirEntry>> {
         self.vroot_dirent.clone()
     }
 
-    /// 分配簇，会填写FAT，成功返回第一个簇号，失败返回None
-    pub fn alloc_cluster(&self, num: u32) -> Option<u32> {
-        let free_clusters = self.free_clusters();
-        if num > free_clusters {
-            return None;
+    /// 检查各份FAT是否一致，返回不一致的扇区数
+    /// 卷上次被正常卸载时只抽查部分扇区，否则比较整个FAT
+    /// repair为true时以当前使用的FAT为准修复其余各份
+    pub fn check_fat_mirrors(&self, repair: bool) -> Result<usize, BlockError> {
+        let fat_writer = self.fat.write();
+        let full = !fat_writer.is_clean(self.block_device.clone())?;
+        let diverged = fat_writer.check_mirrors(repair, full, self.block_device.clone())?;
+        Ok(diverged)
+    }
+
+    /// 标记卷是否处于正常卸载的状态，挂载后立即标记为脏，卸载时先写回所有数据再标记为干净
+    pub fn set_clean(&self, clean: bool) -> Result<(), BlockError> {
+        if clean {
+            self.cache.sync_all()?;
+        }
+        self.fat
+            .write()
+            .set_clean(clean, self.block_device.clone())?;
+        self.cache.sync_all()
+    }
+
+    /// 扫描FAT构建空闲簇位图，此后分配簇和统计空闲簇都不再读取FAT
+    /// 同时用扫描结果校正FSInfo中的空闲簇数
+    pub fn enable_free_bitmap(&self) -> Result<(), BlockError> {
+        let fat_writer = self.fat.write();
+        let bitmap = FreeBitmap::build(&fat_writer, self.block_device.clone())?;
+        if self.fsinfo.read_free_clusters(self.block_device.clone())? != bitmap.free_count() {
+            self.fsinfo
+                .write_free_clusters(bitmap.free_count(), self.block_device.clone())?;
         }
-        // 获取FAT写锁
+        *self.free_map.lock() = Some(bitmap);
+        Ok(())
+    }
+
+    /// 分配簇，会填写FAT，成功返回第一个簇号，空间不足返回None
+    pub fn alloc_cluster(&self, num: u32) -> Result<Option<u32>, BlockError> {
+        // 获取FAT写锁，空闲簇数的读取和修改之间不会被其他分配打断
         let fat_writer = self.fat.write();
-        let prev_cluster = self.fsinfo.first_free_cluster(self.block_device.clone());
-
-        let first_cluster: u32 =
-            fat_writer.next_free_cluster(prev_cluster, self.block_device.clone());
-        let mut current_cluster = first_cluster;
-
-        // 搜索可用簇，同时写表项
-        #[allow(unused)]
-        for i in 1..num {
-            self.clear_cluster(current_cluster);
-            let next_cluster =
-                fat_writer.next_free_cluster(current_cluster, self.block_device.clone());
-            assert_ne!(next_cluster, 0);
-            fat_writer.set_next_cluster(current_cluster, next_cluster, self.block_device.clone());
-
-            current_cluster = next_cluster;
-        }
-        self.clear_cluster(current_cluster);
-        // 填写最后一个表项
-        fat_writer.set_end(current_cluster, self.block_device.clone());
+        let mut free_map = self.free_map.lock();
+        let mut free_clusters = match free_map.as_ref() {
+            Some(bitmap) => bitmap.free_count(),
+            None => self.fsinfo.read_free_clusters(self.block_device.clone())?,
+        };
+        if num > free_clusters && free_map.is_none() {
+            // 没有位图时FSInfo中的空闲簇数可能偏小，确认空间不足之前扫描FAT重新统计
+            free_clusters = fat_writer.scan_free_clusters(self.block_device.clone(), |_| {})?;
+            self.fsinfo
+                .write_free_clusters(free_clusters, self.block_device.clone())?;
+        }
+        if num > free_clusters {
+            return Ok(None);
+        }
+
+        // 从FSInfo记录的上次分配位置之后开始搜索，先找齐所需的簇再修改FAT
+        let mut clusters: Vec<u32> = Vec::with_capacity(num.max(1) as usize);
+        let mut prev_cluster = self.fsinfo.first_free_cluster(self.block_device.clone())?;
+        while clusters.len() < num.max(1) as usize {
+            let next_cluster = match free_map.as_ref() {
+                Some(bitmap) => bitmap.next_free(prev_cluster),
+                None => fat_writer.next_free_cluster(prev_cluster, self.block_device.clone())?,
+            };
+            match next_cluster {
+                // 绕回到第一个找到的簇说明所有空闲簇都已找到
+                Some(cluster) if clusters.first() != Some(&cluster) => {
+                    clusters.push(cluster);
+                    prev_cluster = cluster;
+                }
+                _ => {
+                    // FSInfo中的空闲簇数偏大，修正为实际数量
+                    self.fsinfo
+                        .write_free_clusters(clusters.len() as u32, self.block_device.clone())?;
+                    return Ok(None);
+                }
+            }
+        }
+
+        // 清空簇并写表项
+        for (i, &cluster) in clusters.iter().enumerate() {
+            self.clear_cluster(cluster)?;
+            match clusters.get(i + 1) {
+                Some(&next_cluster) => {
+                    fat_writer.set_next_cluster(cluster, next_cluster, self.block_device.clone())?
+                }
+                None => fat_writer.set_end(cluster, self.block_device.clone())?,
+            }
+            if let Some(bitmap) = free_map.as_mut() {
+                bitmap.set_used(cluster);
+            }
+        }
         // 修改FSINFO
-        self.fsinfo
-            .write_free_clusters(free_clusters - num, self.block_device.clone());
+        self.fsinfo.write_free_clusters(
+            free_clusters - clusters.len() as u32,
+            self.block_device.clone(),
+        )?;
         // 写入分配的最后一个簇
         self.fsinfo
-            .write_first_free_cluster(current_cluster, self.block_device.clone());
-        self.cache_write_back();
-        Some(first_cluster)
+            .write_first_free_cluster(prev_cluster, self.block_device.clone())?;
+        Ok(Some(clusters[0]))
     }
 
-    pub fn dealloc_cluster(&self, clusters: Vec<u32>) {
+    pub fn dealloc_cluster(&self, clusters: Vec<u32>) -> Result<(), BlockError> {
         let fat_writer = self.fat.write();
-        let free_clusters = self.free_clusters();
-        let num = clusters.len();
-        for i in 0..num {
+        let mut free_map = self.free_map.lock();
+        if clusters.is_empty() {
+            return Ok(());
+        }
+        for &cluster in clusters.iter() {
             // 将FAT对应表项清零
-            fat_writer.set_next_cluster(clusters[i], FREE_CLUSTER, self.block_device.clone())
+            fat_writer.set_next_cluster(cluster, FREE_CLUSTER, self.block_device.clone())?;
+            if let Some(bitmap) = free_map.as_mut() {
+                bitmap.set_free(cluster);
+            }
         }
         // 修改FSINFO
-        if num > 0 {
-            self.fsinfo
-                .write_free_clusters(free_clusters + num as u32, self.block_device.clone());
-            // 如果释放的簇号小于开始空闲簇字段，更新该字段
-            if clusters[0] > 2
-                && clusters[0] < self.fsinfo.first_free_cluster(self.block_device.clone())
-            {
-                self.fsinfo
-                    .write_first_free_cluster(clusters[0] - 1, self.block_device.clone());
+        let free_clusters = match free_map.as_ref() {
+            Some(bitmap) => bitmap.free_count(),
+            None => {
+                self.fsinfo.read_free_clusters(self.block_device.clone())? + clusters.len() as u32
             }
+        };
+        self.fsinfo
+            .write_free_clusters(free_clusters, self.block_device.clone())?;
+        // 释放的簇位于上次分配位置之前时回退该位置，使后续分配优先复用低地址的簇
+        let min_cluster = *clusters.iter().min().unwrap();
+        let prev_cluster = self.fsinfo.first_free_cluster(self.block_device.clone())?;
+        if min_cluster <= prev_cluster || prev_cluster > fat_writer.max_cluster() {
+            self.fsinfo
+                .write_first_free_cluster(min_cluster - 1, self.block_device.clone())?;
         }
+        Ok(())
     }
 
-    pub fn clear_cluster(&self, cluster_id: u32) {
+    pub fn clear_cluster(&self, cluster_id: u32) -> Result<(), BlockError> {
         let start_sec = self.first_sector_of_cluster(cluster_id);
         for i in 0..self.sectors_per_cluster {
-            get_block_cache(
-                start_sec + i as usize,
-                self.block_device.clone(),
-                CacheMode::WRITE,
-            )
-            .write()
-            .modify(0, |blk: &mut [u8; 512]| {
-                for j in 0..512 {
-                    blk[j] = 0;
-                }
-            });
+            self.cache
+                .get_block_cache(
+                    start_sec + i as usize,
+                    self.block_device.clone(),
+                    CacheMode::WRITE,
+                )?
+                .write()
+                .modify(0, |blk: &mut [u8; 512]| {
+                    for j in 0..512 {
+                        blk[j] = 0;
+                    }
+                });
         }
+        Ok(())
     }
 
     pub fn get_fat(&self) -> Arc<RwLock<FAT>> {
@@ -252,18 +481,18 @@ impl FAT32Manager {
         new_size: u32,
         is_dir: bool,
         first_cluster: u32,
-    ) -> u32 {
+    ) -> Result<u32, BlockError> {
         if old_size >= new_size {
-            0
+            Ok(0)
         } else {
             if is_dir {
                 let old_clusters = self
                     .fat
                     .read()
-                    .count_cluster_num(first_cluster, self.block_device.clone());
-                self.size_to_clusters(new_size) - old_clusters
+                    .count_cluster_num(first_cluster, self.block_device.clone())?;
+                Ok(self.size_to_clusters(new_size) - old_clusters)
             } else {
-                self.size_to_clusters(new_size) - self.size_to_clusters(old_size)
+                Ok(self.size_to_clusters(new_size) - self.size_to_clusters(old_size))
             }
         }
     }
@@ -278,8 +507,17 @@ impl FAT32Manager {
         offset as u32 / self.bytes_per_cluster
     }
 
-    pub fn free_clusters(&self) -> u32 {
-        self.fsinfo.read_free_clusters(self.block_device.clone())
+    /// 空闲簇数，启用位图时直接取位图中的计数
+    pub fn free_clusters(&self) -> Result<u32, BlockError> {
+        match self.free_map.lock().as_ref() {
+            Some(bitmap) => Ok(bitmap.free_count()),
+            None => self.fsinfo.read_free_clusters(self.block_device.clone()),
+        }
+    }
+
+    /// 数据区的簇总数
+    pub fn total_clusters(&self) -> u32 {
+        self.fat.read().max_cluster() - 1
     }
 
     /// 将长文件名拆分，并且补全0
@@ -347,25 +585,41 @@ impl FAT32Manager {
         let name = name_.as_bytes();
         let extension = ext_.as_bytes();
         let mut short_name = String::new();
-        for i in 0..6 {
+        for i in 0..6.min(name.len()) {
             //fill name
             short_name.push((name[i] as char).to_ascii_uppercase())
         }
         short_name.push('~');
         short_name.push('1');
-        let ext_len = extension.len();
-        for i in 0..3 {
+        let ext_len = extension.len().min(3);
+        if ext_len > 0 {
+            // short_name_format 按 . 拆分扩展名
+            short_name.push('.');
+        }
+        for i in 0..ext_len {
             //fill extension
-            if i >= ext_len {
-                short_name.push(0x20 as char); //填充
-            } else {
-                short_name.push((name[i] as char).to_ascii_uppercase()); //需要为大写
-            }
+            short_name.push((extension[i] as char).to_ascii_uppercase()); //需要为大写
         }
         short_name
     }
 
-    pub fn cache_write_back(&self) {
-        write_to_dev();
+    /// 将整个卷的脏块写回磁盘
+    pub fn sync(&self) -> Result<(), BlockError> {
+        self.cache.sync_all()
+    }
+
+    /// 本卷的块缓存
+    pub fn cache(&self) -> Arc<VolumeCache> {
+        self.cache.clone()
+    }
+
+    /// 设置本卷两个缓存各自的容量(块数)
+    pub fn set_cache_capacity(&self, capacity: usize) {
+        self.cache.set_capacity(capacity);
+    }
+
+    /// 返回(目录项缓存, 数据缓存)的统计信息
+    pub fn cache_stats(&self) -> (CacheStats, CacheStats) {
+        self.cache.stats()
     }
 }
@@ -1,8 +1,9 @@
-use crate::utils::clone_into_array;
+use crate::utils::{clone_into_array, fat_to_unix, unix_to_fat};
 use crate::FAT;
 
 use super::{
-    fat32_manager::FAT32Manager, get_block_cache, get_info_cache, BlockDevice, CacheMode, BLOCK_SZ,
+    fat32_manager::FAT32Manager, BlockDevice, BlockError, CacheMode, VolumeCache, BLOCK_SZ,
+    READ_AHEAD_BLOCKS,
 };
 use alloc::format;
 use alloc::string::String;
@@ -15,6 +16,8 @@ pub const LEAD_SIGNATURE: u32 = 0x41615252;
 pub const STRUCT_SIGNATURE: u32 = 0x61417272;
 // FSI_Free_Count
 // 此值的含义是当前分区free cluster的个数，如果此值为0Xffffffff,那么则说明free cluster的个数是未知的
+pub const FREE_COUNT_UNKNOWN: u32 = 0xFFFFFFFF;
+// 空闲簇的FAT表项
 pub const FREE_CLUSTER: u32 = 0x00000000;
 // 对于FAT32而言，代表文件结束的FAT表项值为0x0FFFFFFF。
 // 0x0FFFFFF8;FAT表起始固定标识
@@ -33,6 +36,9 @@ pub const ATTRIBUTE_VOLUME_ID: u8 = 0x08; // 0000_1000 卷标
 pub const ATTRIBUTE_DIRECTORY: u8 = 0x10; // 0001_0000 目录
 pub const ATTRIBUTE_ARCHIVE: u8 = 0x20; // 0010_0000 归档
 pub const ATTRIBUTE_LFN: u8 = 0x0F; // 0000_1111 长目录标志
+pub const ATTRIBUTE_SYMLINK: u8 = ATTRIBUTE_SYSTEM; // 符号链接带有系统属性，只作为提示
+/// 符号链接文件内容的开头，之后是UTF-16LE编码的链接目标，与Interix(SFU)的格式相同
+pub const SYMLINK_MAGIC: &[u8; 8] = b"IntxLNK\x01";
 
 #[allow(unused)]
 pub const DIRENT_SZ: usize = 32;
@@ -154,12 +160,21 @@ impl FatExtBS {
         self.table_size_32
     }
 
+    /// extended_flags第7位为0时运行时对所有FAT镜像写入
+    pub fn fat_mirroring(&self) -> bool {
+        self.extended_flags & 0x80 == 0
+    }
+
+    /// 关闭镜像时唯一使用的FAT编号，位于extended_flags低4位
+    pub fn active_fat(&self) -> u32 {
+        (self.extended_flags & 0x0F) as u32
+    }
+
     /// FSINFO（文件系统信息扇区）扇区号是1，该扇区为操作系统提供关于空簇总数及下一可用簇的信息。
     pub fn fat_info_sec(&self) -> u32 {
         self.fat_info as u32
     }
 
-    #[allow(unused)]
     pub fn root_clusters(&self) -> u32 {
         self.root_clusters
     }
@@ -169,11 +184,12 @@ impl FatExtBS {
 /// 但是为其中信息的获取和修改提供了接口
 pub struct FSInfo {
     sector_num: u32,
+    cache: Arc<VolumeCache>,
 }
 
 impl FSInfo {
-    pub fn new(sector_num: u32) -> Self {
-        Self { sector_num } // sector_num = 1
+    pub fn new(sector_num: u32, cache: Arc<VolumeCache>) -> Self {
+        Self { sector_num, cache } // sector_num = 1
     }
 
     // 这个地方是生成 FAT,
@@ -193,55 +209,84 @@ impl FSInfo {
     // }
 
     // 检查 lead signature
-    fn check_lead_signature(&self, block_device: Arc<dyn BlockDevice>) -> bool {
-        get_info_cache(self.sector_num as usize, block_device, CacheMode::READ)
+    fn check_lead_signature(&self, block_device: Arc<dyn BlockDevice>) -> Result<bool, BlockError> {
+        Ok(self
+            .cache
+            .get_info_cache(self.sector_num as usize, block_device, CacheMode::READ)?
             .read()
-            .read(0, |&lead_sig: &u32| lead_sig == LEAD_SIGNATURE)
+            .read(0, |&lead_sig: &u32| lead_sig == LEAD_SIGNATURE))
     }
 
-    fn check_struct_signature(&self, block_device: Arc<dyn BlockDevice>) -> bool {
-        get_info_cache(self.sector_num as usize, block_device, CacheMode::READ)
+    fn check_struct_signature(
+        &self,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Result<bool, BlockError> {
+        Ok(self
+            .cache
+            .get_info_cache(self.sector_num as usize, block_device, CacheMode::READ)?
             .read()
-            .read(484, |&sec_sig: &u32| sec_sig == STRUCT_SIGNATURE)
+            .read(484, |&sec_sig: &u32| sec_sig == STRUCT_SIGNATURE))
     }
 
     /// 对签名进行校验
-    pub fn check_signature(&self, block_device: Arc<dyn BlockDevice>) -> bool {
-        return self.check_lead_signature(block_device.clone())
-            && self.check_struct_signature(block_device.clone());
+    pub fn check_signature(&self, block_device: Arc<dyn BlockDevice>) -> Result<bool, BlockError> {
+        Ok(self.check_lead_signature(block_device.clone())?
+            && self.check_struct_signature(block_device.clone())?)
     }
 
     /// 读取空闲簇数
-    pub fn read_free_clusters(&self, block_device: Arc<dyn BlockDevice>) -> u32 {
-        get_info_cache(self.sector_num as usize, block_device, CacheMode::READ)
+    pub fn read_free_clusters(
+        &self,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Result<u32, BlockError> {
+        Ok(self
+            .cache
+            .get_info_cache(self.sector_num as usize, block_device, CacheMode::READ)?
             .read()
-            .read(488, |&free_cluster_count: &u32| free_cluster_count)
+            .read(488, |&free_cluster_count: &u32| free_cluster_count))
     }
 
     /// 写空闲块数
-    pub fn write_free_clusters(&self, free_clusters: u32, block_device: Arc<dyn BlockDevice>) {
-        get_info_cache(self.sector_num as usize, block_device, CacheMode::WRITE)
+    pub fn write_free_clusters(
+        &self,
+        free_clusters: u32,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Result<(), BlockError> {
+        self.cache
+            .get_info_cache(self.sector_num as usize, block_device, CacheMode::WRITE)?
             .write()
             .modify(488, |free_cluster_count: &mut u32| {
                 *free_cluster_count = free_clusters;
             });
+        Ok(())
     }
 
     /// 读起始空闲块
-    pub fn first_free_cluster(&self, block_device: Arc<dyn BlockDevice>) -> u32 {
-        get_info_cache(self.sector_num as usize, block_device, CacheMode::READ)
+    pub fn first_free_cluster(
+        &self,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Result<u32, BlockError> {
+        Ok(self
+            .cache
+            .get_info_cache(self.sector_num as usize, block_device, CacheMode::READ)?
             .read()
-            .read(492, |&start_cluster: &u32| start_cluster)
+            .read(492, |&start_cluster: &u32| start_cluster))
     }
 
     /// 写起始空闲块
-    pub fn write_first_free_cluster(&self, start_cluster: u32, block_device: Arc<dyn BlockDevice>) {
+    pub fn write_first_free_cluster(
+        &self,
+        start_cluster: u32,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Result<(), BlockError> {
         //println!("sector_num = {}, start_c = {}", self.sector_num, start_cluster);
-        get_info_cache(self.sector_num as usize, block_device, CacheMode::WRITE)
+        self.cache
+            .get_info_cache(self.sector_num as usize, block_device, CacheMode::WRITE)?
             .write()
             .modify(492, |start_clu: &mut u32| {
                 *start_clu = start_cluster;
             });
+        Ok(())
     }
 }
 
@@ -328,6 +373,23 @@ impl ShortDirEntry {
         };
     }
 
+    /// 修改短文件名，其余字段保持不变
+    pub fn set_name(&mut self, name_: &[u8], extension_: &[u8]) {
+        self.name = clone_into_array(&name_[0..8]);
+        self.extension = clone_into_array(&extension_[0..3]);
+    }
+
+    /// 从other复制除文件名以外的内容（属性、时间、起始簇和大小）
+    pub fn copy_payload_from(&mut self, other: &ShortDirEntry) {
+        let name = self.name;
+        let extension = self.extension;
+        let case = self.winnt_reserved;
+        *self = *other;
+        self.name = name;
+        self.extension = extension;
+        self.winnt_reserved = case;
+    }
+
     /// 返回目前使用的簇的数量
     pub fn data_clusters(&self, bytes_per_cluster: u32) -> u32 {
         // size为0的时候就是0
@@ -394,8 +456,7 @@ impl ShortDirEntry {
         let hour: u32 = ((self.creation_time & 0xF800) >> 11) as u32;
         let min: u32 = ((self.creation_time & 0x07E0) >> 5) as u32;
         let sec: u32 = ((self.creation_time & 0x001F) << 1) as u32; // 秒数需要*2
-        let long_sec: u64 =
-            ((((year - 1970) * 365 + month * 30 + day) * 24 + hour) * 3600 + min * 60 + sec) as u64;
+        let long_sec: u64 = fat_to_unix(self.creation_date, self.creation_time);
         (year, month, day, hour, min, sec, long_sec)
     }
 
@@ -407,8 +468,7 @@ impl ShortDirEntry {
         let hour: u32 = ((self.modification_time & 0xF800) >> 11) as u32;
         let min: u32 = ((self.modification_time & 0x07E0) >> 5) as u32;
         let sec: u32 = ((self.modification_time & 0x001F) << 1) as u32; // 秒数需要*2
-        let long_sec: u64 =
-            ((((year - 1970) * 365 + month * 30 + day) * 24 + hour) * 3600 + min * 60 + sec) as u64;
+        let long_sec: u64 = fat_to_unix(self.modification_date, self.modification_time);
         (year, month, day, hour, min, sec, long_sec)
     }
 
@@ -420,11 +480,40 @@ impl ShortDirEntry {
         let hour: u32 = 0;
         let min: u32 = 0;
         let sec: u32 = 0; // 没有相关信息，默认0
-        let long_sec: u64 =
-            ((((year - 1970) * 365 + month * 30 + day) * 24 + hour) * 3600 + min * 60 + sec) as u64;
+        let long_sec: u64 = fat_to_unix(self.last_acc_date, 0);
         (year, month, day, hour, min, sec, long_sec)
     }
 
+    /// 设置创建时间，sec为Unix时间戳，nsec为秒内的纳秒数
+    pub fn set_creation_time(&mut self, sec: u64, nsec: u32) {
+        let (date, time) = unix_to_fat(sec);
+        self.creation_date = date;
+        self.creation_time = time;
+        // 单位为10ms，包含FAT时间中被舍去的奇数秒
+        self.creation_tenths = ((sec % 2) as u32 * 100 + nsec / 10_000_000) as u8;
+    }
+
+    /// 设置修改时间，精度为2s，返回是否发生了变化
+    pub fn set_modification_time(&mut self, sec: u64) -> bool {
+        let (date, time) = unix_to_fat(sec);
+        if self.modification_date == date && self.modification_time == time {
+            return false;
+        }
+        self.modification_date = date;
+        self.modification_time = time;
+        true
+    }
+
+    /// 设置访问日期，FAT只记录日期，返回是否发生了变化
+    pub fn set_accessed_time(&mut self, sec: u64) -> bool {
+        let (date, _) = unix_to_fat(sec);
+        if self.last_acc_date == date {
+            return false;
+        }
+        self.last_acc_date = date;
+        true
+    }
+
     /// 获取文件起始簇号
     pub fn first_cluster(&self) -> u32 {
         ((self.cluster_high as u32) << 16) + (self.cluster_low as u32)
@@ -534,6 +623,11 @@ impl ShortDirEntry {
         self.set_first_cluster(0);
     }
 
+    /// 仅标记删除，保留起始簇和大小，用于延迟释放
+    pub fn mark_deleted(&mut self) {
+        self.name[0] = 0xE5;
+    }
+
     /// 获取文件偏移量所在的簇、扇区和偏移
     pub fn get_pos(
         &self,
@@ -541,7 +635,7 @@ impl ShortDirEntry {
         manager: &Arc<RwLock<FAT32Manager>>,
         fat: &Arc<RwLock<FAT>>,
         block_device: &Arc<dyn BlockDevice>,
-    ) -> (u32, usize, usize) {
+    ) -> Result<(u32, usize, usize), BlockError> {
         let manager_reader = manager.read();
         let fat_reader = fat.read();
         let bytes_per_sector = manager_reader.bytes_per_sector() as usize;
@@ -551,10 +645,10 @@ impl ShortDirEntry {
             self.first_cluster(),
             cluster_index,
             Arc::clone(block_device),
-        );
+        )?;
         let current_sector = manager_reader.first_sector_of_cluster(current_cluster)
             + (offset - cluster_index as usize * bytes_per_cluster) / bytes_per_sector;
-        (current_cluster, current_sector, offset % bytes_per_sector)
+        Ok((current_cluster, current_sector, offset % bytes_per_sector))
     }
 
     /// 以偏移量读取文件，这里会对fat和manager加读锁
@@ -565,28 +659,29 @@ impl ShortDirEntry {
         manager: &Arc<RwLock<FAT32Manager>>,
         fat: &Arc<RwLock<FAT>>,
         block_device: &Arc<dyn BlockDevice>,
-    ) -> usize {
+    ) -> Result<usize, BlockError> {
         // 获取共享锁
         let manager_reader = manager.read();
         let fat_reader = fat.read();
+        let cache = manager_reader.cache();
         let bytes_per_sector = manager_reader.bytes_per_sector() as usize;
         let bytes_per_cluster = manager_reader.bytes_per_cluster() as usize;
         let mut current_off = offset;
         let end: usize;
         if self.is_dir() {
             let size = bytes_per_cluster
-                * fat_reader.count_cluster_num(self.first_cluster() as u32, block_device.clone())
+                * fat_reader.count_cluster_num(self.first_cluster() as u32, block_device.clone())?
                     as usize;
             end = offset + buf.len().min(size); // DEBUG:约束上界
         } else {
             end = (offset + buf.len()).min(self.size as usize);
         }
         if current_off >= end {
-            return 0;
+            return Ok(0);
         }
-        let (curr_clu, curr_sec, _) = self.get_pos(offset, manager, fat, block_device);
+        let (curr_clu, curr_sec, _) = self.get_pos(offset, manager, fat, block_device)?;
         if curr_clu >= END_CLUSTER {
-            return 0;
+            return Ok(0);
         };
         let mut current_cluster = curr_clu;
         let mut current_sector = curr_sec;
@@ -600,20 +695,36 @@ impl ShortDirEntry {
             let block_read_size = end_current_block - current_off;
             let dst = &mut buf[read_size..read_size + block_read_size];
             if self.is_dir() {
-                get_info_cache(
-                    // 目录项通过Infocache访问
-                    current_sector,
-                    Arc::clone(block_device),
-                    CacheMode::READ,
-                )
-                .read()
-                .read(0, |data_block: &DataBlock| {
-                    let src = &data_block
-                        [current_off % BLOCK_SZ..current_off % BLOCK_SZ + block_read_size];
-                    dst.copy_from_slice(src);
-                });
+                cache
+                    .get_info_cache(
+                        // 目录项通过Infocache访问
+                        current_sector,
+                        Arc::clone(block_device),
+                        CacheMode::READ,
+                    )?
+                    .read()
+                    .read(0, |data_block: &DataBlock| {
+                        let src = &data_block
+                            [current_off % BLOCK_SZ..current_off % BLOCK_SZ + block_read_size];
+                        dst.copy_from_slice(src);
+                    });
             } else {
-                get_block_cache(current_sector, Arc::clone(block_device), CacheMode::READ)
+                if cache.need_read_ahead(current_sector, block_device) {
+                    // 顺序读且未命中，预读剩余部分所在的连续簇
+                    let remaining = (self.size as usize - current_off + bytes_per_sector - 1)
+                        / bytes_per_sector;
+                    let count = self.contiguous_sectors(
+                        current_cluster,
+                        current_sector,
+                        remaining.min(READ_AHEAD_BLOCKS),
+                        &manager_reader,
+                        &fat_reader,
+                        block_device,
+                    )?;
+                    cache.read_ahead(current_sector, count, Arc::clone(block_device));
+                }
+                cache
+                    .get_block_cache(current_sector, Arc::clone(block_device), CacheMode::READ)?
                     .read()
                     .read(0, |data_block: &DataBlock| {
                         let src = &data_block
@@ -631,7 +742,7 @@ impl ShortDirEntry {
             if current_off % bytes_per_cluster == 0 {
                 // 读完一个簇
                 current_cluster =
-                    fat_reader.get_next_cluster(current_cluster, Arc::clone(block_device));
+                    fat_reader.get_next_cluster(current_cluster, Arc::clone(block_device))?;
                 if current_cluster >= END_CLUSTER {
                     break;
                 }
@@ -640,7 +751,31 @@ impl ShortDirEntry {
                 current_sector += 1; //没读完一个簇，直接进入下一扇区
             }
         }
-        read_size
+        Ok(read_size)
+    }
+
+    /// 从cluster中的sector开始，沿物理上连续的簇最多数出limit个扇区
+    fn contiguous_sectors(
+        &self,
+        cluster: u32,
+        sector: usize,
+        limit: usize,
+        manager: &FAT32Manager,
+        fat: &FAT,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<usize, BlockError> {
+        let sectors_per_cluster = manager.sectors_per_cluster() as usize;
+        let mut count = manager.first_sector_of_cluster(cluster) + sectors_per_cluster - sector;
+        let mut current_cluster = cluster;
+        while count < limit {
+            let next_cluster = fat.get_next_cluster(current_cluster, Arc::clone(block_device))?;
+            if next_cluster != current_cluster + 1 {
+                break;
+            }
+            current_cluster = next_cluster;
+            count += sectors_per_cluster;
+        }
+        Ok(count.min(limit))
     }
 
     /// 以偏移量写文件，这里会对fat和manager加读锁
@@ -651,17 +786,18 @@ impl ShortDirEntry {
         manager: &Arc<RwLock<FAT32Manager>>,
         fat: &Arc<RwLock<FAT>>,
         block_device: &Arc<dyn BlockDevice>,
-    ) -> usize {
+    ) -> Result<usize, BlockError> {
         // 获取共享锁
         let manager_reader = manager.read();
         let fat_reader = fat.read();
+        let cache = manager_reader.cache();
         let bytes_per_sector = manager_reader.bytes_per_sector() as usize;
         let bytes_per_cluster = manager_reader.bytes_per_cluster() as usize;
         let mut current_off = offset;
         let end: usize;
         if self.is_dir() {
             let size = bytes_per_cluster
-                * fat_reader.count_cluster_num(self.first_cluster() as u32, block_device.clone())
+                * fat_reader.count_cluster_num(self.first_cluster() as u32, block_device.clone())?
                     as usize;
             end = offset + buf.len().min(size); // DEBUG:约束上界
         } else {
@@ -669,7 +805,7 @@ impl ShortDirEntry {
             end = (offset + buf.len()).min(self.size as usize);
         }
         let (c_clu, c_sec, _) =
-            self.get_pos(offset, manager, &manager_reader.get_fat(), block_device);
+            self.get_pos(offset, manager, &manager_reader.get_fat(), block_device)?;
         // 找到当前的cluster和sector，我们这里应该是一样的
         let mut current_cluster = c_clu;
         let mut current_sector = c_sec;
@@ -685,21 +821,23 @@ impl ShortDirEntry {
             let block_write_size = end_current_block - current_off;
             // println!("write cache: current_sector = {}", current_sector);
             if self.is_dir() {
-                get_info_cache(
-                    // 目录项通过infocache访问
-                    current_sector,
-                    Arc::clone(block_device),
-                    CacheMode::READ,
-                )
-                .write()
-                .modify(0, |data_block: &mut DataBlock| {
-                    let src = &buf[write_size..write_size + block_write_size];
-                    let dst = &mut data_block
-                        [current_off % BLOCK_SZ..current_off % BLOCK_SZ + block_write_size];
-                    dst.copy_from_slice(src);
-                });
+                cache
+                    .get_info_cache(
+                        // 目录项通过infocache访问
+                        current_sector,
+                        Arc::clone(block_device),
+                        CacheMode::READ,
+                    )?
+                    .write()
+                    .modify(0, |data_block: &mut DataBlock| {
+                        let src = &buf[write_size..write_size + block_write_size];
+                        let dst = &mut data_block
+                            [current_off % BLOCK_SZ..current_off % BLOCK_SZ + block_write_size];
+                        dst.copy_from_slice(src);
+                    });
             } else {
-                get_block_cache(current_sector, Arc::clone(block_device), CacheMode::READ)
+                cache
+                    .get_block_cache(current_sector, Arc::clone(block_device), CacheMode::READ)?
                     .write()
                     .modify(0, |data_block: &mut DataBlock| {
                         let src = &buf[write_size..write_size + block_write_size];
@@ -721,7 +859,7 @@ impl ShortDirEntry {
 
                 // 查询下一个簇
                 current_cluster =
-                    fat_reader.get_next_cluster(current_cluster, Arc::clone(block_device));
+                    fat_reader.get_next_cluster(current_cluster, Arc::clone(block_device))?;
                 if current_cluster >= END_CLUSTER {
                     panic!("END_CLUSTER");
                 } //没有下一个簇
@@ -737,7 +875,7 @@ impl ShortDirEntry {
                 current_sector += 1; //没读完一个簇，直接进入下一扇区
             }
         }
-        write_size
+        Ok(write_size)
     }
 
     pub fn as_bytes(&self) -> &[u8] {
@@ -8,6 +8,7 @@ mod block_cache;
 mod block_dev;
 mod fat32_manager;
 mod layout;
+mod partition;
 mod sbi;
 mod utils;
 mod vfs;
@@ -19,12 +20,16 @@ mod console;
 
 pub const BLOCK_SZ: usize = 512;
 
-use block_cache::{get_block_cache, get_info_cache, set_start_sec, write_to_dev, CacheMode};
-pub use block_dev::BlockDevice;
+use block_cache::CacheMode;
+pub use block_cache::{CacheStats, VolumeCache, DEFAULT_CACHE_CAPACITY, READ_AHEAD_BLOCKS};
+pub use block_dev::{BlockDevice, BlockError};
 pub use fat::FAT;
-pub use fat32_manager::FAT32Manager;
+pub use fat32_manager::{FAT32Manager, MountError};
 pub use layout::ShortDirEntry;
 pub use layout::*;
-pub use vfs::VFile;
+pub use partition::{
+    read_partitions, Guid, Partition, PartitionDevice, PartitionSelector, PartitionType,
+};
+pub use vfs::{fcopy, fexchange, fmove, VFile};
 
 // pub use fat::DBR; // test
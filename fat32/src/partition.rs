@@ -0,0 +1,361 @@
+//! MBR与GPT分区表的解析
+//! 每个分区都可以包装为从分区起始扇区开始编号的块设备
+
+use super::{BlockDevice, BlockError, BLOCK_SZ};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Formatter};
+
+/// MBR中分区表的偏移
+const MBR_TABLE_OFFSET: usize = 0x1BE;
+/// MBR中的分区数
+const MBR_ENTRIES: usize = 4;
+/// 保护性MBR的分区类型，表示磁盘使用GPT
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+/// 扩展分区的类型
+const MBR_TYPE_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+/// FAT32分区的类型，依次为CHS、LBA及其隐藏版本
+const MBR_TYPE_FAT32: [u8; 4] = [0x0B, 0x0C, 0x1B, 0x1C];
+/// GPT头的签名"EFI PART"
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// GPT分区项的最小长度
+const GPT_ENTRY_MIN_SIZE: usize = 128;
+/// 支持的最大GPT分区项数，规范要求分区项数组至少能容纳128项，常见的工具也只使用128项
+const GPT_MAX_ENTRIES: usize = 128;
+/// GPT分区名的最大长度(UTF-16字符数)
+const GPT_NAME_LEN: usize = 36;
+
+/// 按磁盘上的混合字节序存放的GUID
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// Microsoft基本数据分区，FAT卷通常使用此类型
+    pub const BASIC_DATA: Guid = Guid([
+        0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99,
+        0xC7,
+    ]);
+    /// EFI系统分区，同样是FAT卷
+    pub const EFI_SYSTEM: Guid = Guid([
+        0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9,
+        0x3B,
+    ]);
+
+    /// 解析"XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX"形式的GUID，不区分大小写
+    pub fn parse(s: &str) -> Option<Self> {
+        let groups: Vec<&str> = s.split('-').collect();
+        if groups.len() != 5
+            || groups
+                .iter()
+                .zip([8, 4, 4, 4, 12].iter())
+                .any(|(group, &len)| group.len() != len)
+        {
+            return None;
+        }
+        let mut bytes = [0u8; 16];
+        let mut pos = 0;
+        for group in groups {
+            for i in (0..group.len()).step_by(2) {
+                bytes[pos] = u8::from_str_radix(group.get(i..i + 2)?, 16).ok()?;
+                pos += 1;
+            }
+        }
+        // 前三组在磁盘上为小端序
+        bytes[0..4].reverse();
+        bytes[4..6].reverse();
+        bytes[6..8].reverse();
+        Some(Guid(bytes))
+    }
+}
+
+impl Display for Guid {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-",
+            b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6]
+        )?;
+        write!(
+            f,
+            "{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+            b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15]
+        )
+    }
+}
+
+/// 分区类型
+#[derive(Clone, Copy, PartialEq)]
+pub enum PartitionType {
+    /// MBR分区的类型字节
+    Mbr(u8),
+    /// GPT分区的类型GUID
+    Gpt(Guid),
+}
+
+impl PartitionType {
+    /// 是否为可能包含FAT32卷的分区类型
+    pub fn is_fat(&self) -> bool {
+        match self {
+            PartitionType::Mbr(kind) => MBR_TYPE_FAT32.contains(kind),
+            PartitionType::Gpt(guid) => *guid == Guid::BASIC_DATA || *guid == Guid::EFI_SYSTEM,
+        }
+    }
+}
+
+/// 分区表中的一个分区
+#[derive(Clone)]
+pub struct Partition {
+    /// 分区号，从0开始，与分区表中的顺序一致
+    pub index: usize,
+    /// 起始扇区
+    pub start: usize,
+    /// 扇区数
+    pub size: usize,
+    pub kind: PartitionType,
+    /// GPT分区名，MBR分区为空
+    pub label: String,
+}
+
+/// 选择根分区的方式
+#[derive(Clone, Copy)]
+pub enum PartitionSelector {
+    /// 第一个FAT类型的分区
+    FirstFat,
+    /// 分区号
+    Index(usize),
+    /// GPT分区名
+    Label(&'static str),
+    /// GPT分区类型GUID，格式同Guid::parse
+    TypeGuid(&'static str),
+}
+
+impl PartitionSelector {
+    pub fn select<'a>(&self, partitions: &'a [Partition]) -> Option<&'a Partition> {
+        partitions.iter().find(|part| match *self {
+            PartitionSelector::FirstFat => part.kind.is_fat(),
+            PartitionSelector::Index(index) => part.index == index,
+            PartitionSelector::Label(label) => part.label == label,
+            PartitionSelector::TypeGuid(guid) => {
+                Guid::parse(guid).map(PartitionType::Gpt) == Some(part.kind)
+            }
+        })
+    }
+}
+
+/// 读取设备上的分区表
+/// 没有分区表(例如整个设备就是一个FAT卷)时返回空表
+pub fn read_partitions(block_device: &Arc<dyn BlockDevice>) -> Result<Vec<Partition>, BlockError> {
+    let mut mbr = [0u8; BLOCK_SZ];
+    block_device.read_block(0, &mut mbr)?;
+    if !is_mbr(&mbr) {
+        return Ok(Vec::new());
+    }
+    if (0..MBR_ENTRIES).any(|i| mbr_entry(&mbr, i).0 == MBR_TYPE_GPT_PROTECTIVE) {
+        if let Some(partitions) = read_gpt(block_device)? {
+            return Ok(partitions);
+        }
+    }
+    Ok((0..MBR_ENTRIES)
+        .filter_map(|index| {
+            let (kind, start, size) = mbr_entry(&mbr, index);
+            // 不支持扩展分区中的逻辑分区
+            if kind == 0 || size == 0 || MBR_TYPE_EXTENDED.contains(&kind) {
+                None
+            } else {
+                Some(Partition {
+                    index,
+                    start: start as usize,
+                    size: size as usize,
+                    kind: PartitionType::Mbr(kind),
+                    label: String::new(),
+                })
+            }
+        })
+        .collect())
+}
+
+/// 判断0号扇区是否为MBR
+/// FAT卷的引导扇区同样以0x55AA结尾，需要排除
+fn is_mbr(sector: &[u8; BLOCK_SZ]) -> bool {
+    if sector[510] != 0x55 || sector[511] != 0xAA {
+        return false;
+    }
+    if &sector[0x52..0x57] == b"FAT32" || &sector[0x36..0x39] == b"FAT" {
+        return false;
+    }
+    (0..MBR_ENTRIES).all(|i| matches!(sector[MBR_TABLE_OFFSET + i * 16], 0x00 | 0x80))
+        && (0..MBR_ENTRIES).any(|i| mbr_entry(sector, i).0 != 0)
+}
+
+/// 返回第index个MBR分区项的(类型, 起始扇区, 扇区数)
+fn mbr_entry(sector: &[u8; BLOCK_SZ], index: usize) -> (u8, u32, u32) {
+    let entry = &sector[MBR_TABLE_OFFSET + index * 16..MBR_TABLE_OFFSET + (index + 1) * 16];
+    (entry[4], read_u32(entry, 8), read_u32(entry, 12))
+}
+
+/// 解析GPT，主GPT头损坏时尝试位于最后一个扇区的备份GPT头
+fn read_gpt(block_device: &Arc<dyn BlockDevice>) -> Result<Option<Vec<Partition>>, BlockError> {
+    let mut header = [0u8; BLOCK_SZ];
+    block_device.read_block(1, &mut header)?;
+    if !is_gpt_header(&header) {
+        let num_blocks = block_device.num_blocks();
+        if num_blocks < 2 {
+            return Ok(None);
+        }
+        block_device.read_block(num_blocks - 1, &mut header)?;
+        if !is_gpt_header(&header) {
+            return Ok(None);
+        }
+    }
+    let entries_lba = read_u64(&header, 72) as usize;
+    let n_entries = read_u32(&header, 80) as usize;
+    let entry_size = read_u32(&header, 84) as usize;
+    if entry_size < GPT_ENTRY_MIN_SIZE || entry_size % 8 != 0 || entry_size > BLOCK_SZ {
+        return Ok(None);
+    }
+    // 分区项数来自磁盘，过大时放弃，避免按其分配内存
+    if n_entries > GPT_MAX_ENTRIES {
+        return Ok(None);
+    }
+    // 分区项数组连续存放，一次读入并校验CRC
+    let array_len = n_entries * entry_size;
+    let array_blocks = (array_len + BLOCK_SZ - 1) / BLOCK_SZ;
+    let num_blocks = block_device.num_blocks();
+    // 设备大小未知(0)时不检查
+    if num_blocks != 0 && entries_lba.saturating_add(array_blocks) > num_blocks {
+        return Ok(None);
+    }
+    let mut array = vec![0u8; array_blocks * BLOCK_SZ];
+    block_device.read_blocks(entries_lba, &mut array)?;
+    if crc32(&array[..array_len]) != read_u32(&header, 88) {
+        return Ok(None);
+    }
+    Ok(Some(
+        array[..array_len]
+            .chunks(entry_size)
+            .enumerate()
+            .filter_map(|(index, entry)| {
+                let mut type_guid = [0u8; 16];
+                type_guid.copy_from_slice(&entry[0..16]);
+                let first = read_u64(entry, 32) as usize;
+                let last = read_u64(entry, 40) as usize;
+                // 类型为全0的分区项未使用
+                if type_guid == [0u8; 16] || last < first {
+                    return None;
+                }
+                let name: Vec<u16> = (0..GPT_NAME_LEN)
+                    .map(|i| entry[56 + 2 * i] as u16 | (entry[57 + 2 * i] as u16) << 8)
+                    .take_while(|&c| c != 0)
+                    .collect();
+                Some(Partition {
+                    index,
+                    start: first,
+                    size: last - first + 1,
+                    kind: PartitionType::Gpt(Guid(type_guid)),
+                    label: String::from_utf16_lossy(&name),
+                })
+            })
+            .collect(),
+    ))
+}
+
+/// 校验GPT头的签名与CRC
+fn is_gpt_header(header: &[u8; BLOCK_SZ]) -> bool {
+    if &header[0..8] != GPT_SIGNATURE {
+        return false;
+    }
+    let header_size = read_u32(header, 12) as usize;
+    if header_size < 92 || header_size > BLOCK_SZ {
+        return false;
+    }
+    // 计算CRC时CRC字段本身按0处理
+    let mut copy = [0u8; BLOCK_SZ];
+    copy[..header_size].copy_from_slice(&header[..header_size]);
+    copy[16..20].fill(0);
+    crc32(&copy[..header_size]) == read_u32(header, 16)
+}
+
+/// GPT使用的CRC32(IEEE 802.3)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buf[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// 分区对应的块设备，块号相对分区起始扇区，越界访问返回IoError
+pub struct PartitionDevice {
+    device: Arc<dyn BlockDevice>,
+    start: usize,
+    size: usize,
+}
+
+impl PartitionDevice {
+    pub fn new(device: Arc<dyn BlockDevice>, partition: &Partition) -> Self {
+        Self {
+            device,
+            start: partition.start,
+            size: partition.size,
+        }
+    }
+
+    /// 将分区内从block_id开始、长度为len字节的访问转换为设备上的块号
+    fn translate(&self, block_id: usize, len: usize) -> Result<usize, BlockError> {
+        let count = (len + BLOCK_SZ - 1) / BLOCK_SZ;
+        if block_id + count > self.size {
+            return Err(BlockError::IoError);
+        }
+        Ok(self.start + block_id)
+    }
+}
+
+impl BlockDevice for PartitionDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlockError> {
+        let block_id = self.translate(block_id, buf.len())?;
+        self.device.read_block(block_id, buf)
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), BlockError> {
+        let block_id = self.translate(block_id, buf.len())?;
+        self.device.write_block(block_id, buf)
+    }
+    fn read_blocks(&self, start_block: usize, buf: &mut [u8]) -> Result<(), BlockError> {
+        let start_block = self.translate(start_block, buf.len())?;
+        self.device.read_blocks(start_block, buf)
+    }
+    fn write_blocks(&self, start_block: usize, buf: &[u8]) -> Result<(), BlockError> {
+        let start_block = self.translate(start_block, buf.len())?;
+        self.device.write_blocks(start_block, buf)
+    }
+    fn flush(&self) -> Result<(), BlockError> {
+        self.device.flush()
+    }
+    fn num_blocks(&self) -> usize {
+        self.size
+    }
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+}
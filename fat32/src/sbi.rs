@@ -15,6 +15,10 @@ const SBI_SHUTDOWN: usize = 8;
 #[inline(always)]
 fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
     let mut ret;
+    // 在主机上运行单元测试时没有SBI
+    #[cfg(not(target_arch = "riscv64"))]
+    unimplemented!("SBI call {} on a non-RISC-V host", which);
+    #[cfg(target_arch = "riscv64")]
     unsafe {
         asm!(
             "ecall",
@@ -25,3 +25,83 @@ where
     <A as AsMut<[T]>>::as_mut(&mut a).clone_from_slice(slice);
     a
 }
+
+/// 1970-01-01起的天数转换为(年, 月, 日)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// (年, 月, 日)转换为1970-01-01起的天数
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// FAT能表示的时间范围：1980-01-01 00:00:00 至 2107-12-31 23:59:58
+const FAT_EPOCH_SEC: u64 = 315532800;
+const FAT_MAX_SEC: u64 = 4354819198;
+
+/// Unix时间戳转换为FAT的(日期, 时间)，超出范围时取边界值
+pub fn unix_to_fat(sec: u64) -> (u16, u16) {
+    let sec = sec.clamp(FAT_EPOCH_SEC, FAT_MAX_SEC);
+    let (year, month, day) = civil_from_days((sec / 86400) as i64);
+    // 一天的秒数超出u16的范围，只有拆开后的各字段才能转换为u16
+    let secs_of_day = (sec % 86400) as u32;
+    let date = (((year - 1980) as u16) << 9) | ((month as u16) << 5) | day as u16;
+    let time = (((secs_of_day / 3600) as u16) << 11)
+        | (((secs_of_day % 3600 / 60) as u16) << 5)
+        | (secs_of_day % 60 / 2) as u16;
+    (date, time)
+}
+
+/// FAT的(日期, 时间)转换为Unix时间戳，日期为0时返回0
+pub fn fat_to_unix(date: u16, time: u16) -> u64 {
+    if date == 0 {
+        return 0;
+    }
+    let year = ((date >> 9) & 0x7F) as i64 + 1980;
+    let month = ((date >> 5) & 0x0F) as u32;
+    let day = (date & 0x1F) as u32;
+    let hour = ((time >> 11) & 0x1F) as u64;
+    let min = ((time >> 5) & 0x3F) as u64;
+    let sec = ((time & 0x1F) << 1) as u64;
+    days_from_civil(year, month.max(1), day.max(1)) as u64 * 86400 + hour * 3600 + min * 60 + sec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn late_evening_time() {
+        // 2022-06-01 20:34:56 UTC，一天中的秒数大于u16::MAX
+        let sec = 1654115696;
+        let (date, time) = unix_to_fat(sec);
+        assert_eq!(date, (42 << 9) | (6 << 5) | 1);
+        assert_eq!(time, (20 << 11) | (34 << 5) | (56 / 2));
+        assert_eq!(fat_to_unix(date, time), sec);
+    }
+
+    #[test]
+    fn out_of_range_is_clamped() {
+        assert_eq!(unix_to_fat(0), ((0 << 9) | (1 << 5) | 1, 0));
+        assert_eq!(
+            fat_to_unix(unix_to_fat(u64::MAX).0, unix_to_fat(u64::MAX).1),
+            FAT_MAX_SEC
+        );
+    }
+}
@@ -1,9 +1,25 @@
-use super::{fat32_manager::*, get_info_cache, layout::*, println, BlockDevice, CacheMode};
+use super::{
+    block_cache::BlockCache, fat32_manager::*, layout::*, println, BlockDevice, BlockError,
+    CacheMode, VolumeCache,
+};
+use alloc::collections::BTreeSet;
 use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use spin::RwLock;
 
+/// 短目录项的存放位置
+#[derive(Clone)]
+enum ShortDirent {
+    /// 根目录没有目录项，使用文件系统中常驻内存的目录项
+    Root,
+    /// 目录项所在的块，持有期间不会被换出
+    Block(Arc<RwLock<BlockCache>>),
+    /// 已经从目录中删除的文件，目录项只保留在内存中
+    Detached(Arc<RwLock<ShortDirEntry>>),
+}
+
 // 虚拟文件系统和物理文件系统互为映射
 #[derive(Clone)]
 pub struct VFile {
@@ -12,7 +28,9 @@ pub struct VFile {
     pub short_offset: usize,               // 文件短目录项所在扇区和偏移
     pub long_pos_vec: Vec<(usize, usize)>, // 长目录项的位置<sector, offset>
     attribute: u8,                         // 类型
+    short_dirent: ShortDirent,
     fs: Arc<RwLock<FAT32Manager>>,
+    cache: Arc<VolumeCache>, // 所在卷的块缓存
     block_device: Arc<dyn BlockDevice>,
 }
 
@@ -23,6 +41,8 @@ pub struct VFile {
 // type DirEntryArray = [ShortDirEntry; 16]; // 一般是16...
 
 impl VFile {
+    // 目录项的位置和所属的卷都由调用者在查找目录时得到，与字段一一对应
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         name: String,
         short_sector: usize,
@@ -31,17 +51,30 @@ impl VFile {
         attribute: u8,
         // size: u32,
         fs: Arc<RwLock<FAT32Manager>>,
+        cache: Arc<VolumeCache>,
         block_device: Arc<dyn BlockDevice>,
-    ) -> Self {
-        Self {
+    ) -> Result<Self, BlockError> {
+        // 根目录的目录项不在磁盘上
+        let short_dirent = if short_sector == 0 {
+            ShortDirent::Root
+        } else {
+            ShortDirent::Block(cache.get_info_cache(
+                short_sector,
+                block_device.clone(),
+                CacheMode::READ,
+            )?)
+        };
+        Ok(Self {
             name,
             short_sector,
             short_offset,
             long_pos_vec,
             attribute,
+            short_dirent,
             fs,
+            cache,
             block_device,
-        }
+        })
     }
 
     pub fn get_name(&self) -> &str {
@@ -68,6 +101,21 @@ impl VFile {
         }
     }
 
+    /// 符号链接用带有系统属性、内容以SYMLINK_MAGIC开头的普通文件模拟
+    /// 其他工具创建的系统文件没有这个开头，仍是普通文件
+    pub fn is_symlink(&self) -> bool {
+        if self.attribute & ATTRIBUTE_DIRECTORY != 0 || self.attribute & ATTRIBUTE_SYMLINK == 0 {
+            return false;
+        }
+        let mut magic = [0u8; SYMLINK_MAGIC.len()];
+        matches!(self.read_at(0, &mut magic), Ok(len) if len == magic.len() && &magic == SYMLINK_MAGIC)
+    }
+
+    /// 目录项是否在磁盘上，根目录和已删除的文件没有磁盘上的目录项
+    pub fn is_attached(&self) -> bool {
+        matches!(self.short_dirent, ShortDirent::Block(_))
+    }
+
     pub fn is_short(&self) -> bool {
         if self.long_pos_vec.len() == 0 {
             true
@@ -83,50 +131,65 @@ impl VFile {
 
     // 读取 dir parent
     pub fn read_short_dirent<V>(&self, f: impl FnOnce(&ShortDirEntry) -> V) -> V {
-        if self.short_sector == 0 {
-            // 没有缓冲
-            let root_dirent = self.fs.read().get_root_dirent();
-            let root_dirent_reader = root_dirent.read();
-            f(&root_dirent_reader)
-        } else {
+        match &self.short_dirent {
             // 已经缓冲
-            get_info_cache(
-                self.short_sector,
-                self.block_device.clone(),
-                CacheMode::READ,
-            )
-            .read()
-            .read(self.short_offset, f)
+            ShortDirent::Block(cache) => cache.read().read(self.short_offset, f),
+            ShortDirent::Detached(dirent) => f(&dirent.read()),
+            ShortDirent::Root => {
+                // 没有缓冲
+                let root_dirent = self.fs.read().get_root_dirent();
+                let root_dirent_reader = root_dirent.read();
+                f(&root_dirent_reader)
+            }
         }
     }
 
     pub fn modify_short_dirent<V>(&self, f: impl FnOnce(&mut ShortDirEntry) -> V) -> V {
-        if self.short_sector == 0 {
-            //println!("[fs]: modify vroot dent");
-            let root_dirent = self.fs.read().get_root_dirent();
-            let mut rw = root_dirent.write();
-            f(&mut rw)
-        } else {
-            get_info_cache(
-                self.short_sector,
-                self.block_device.clone(),
-                CacheMode::READ,
-            )
-            .write()
-            .modify(self.short_offset, f)
+        match &self.short_dirent {
+            ShortDirent::Block(cache) => cache.write().modify(self.short_offset, f),
+            ShortDirent::Detached(dirent) => f(&mut dirent.write()),
+            ShortDirent::Root => {
+                //println!("[fs]: modify vroot dent");
+                let root_dirent = self.fs.read().get_root_dirent();
+                let mut rw = root_dirent.write();
+                f(&mut rw)
+            }
         }
     }
 
-    pub fn modify_long_dirent<V>(&self, index: usize, f: impl FnOnce(&mut LongDirEntry) -> V) -> V {
+    pub fn modify_long_dirent<V>(
+        &self,
+        index: usize,
+        f: impl FnOnce(&mut LongDirEntry) -> V,
+    ) -> Result<V, BlockError> {
         let (sector, offset) = self.long_pos_vec[index];
-        get_info_cache(sector, self.block_device.clone(), CacheMode::READ)
+        Ok(self
+            .cache
+            .get_info_cache(sector, self.block_device.clone(), CacheMode::READ)?
             .write()
-            .modify(offset, f)
+            .modify(offset, f))
+    }
+
+    /// 复制一份目录项只在内存中的VFile，用于删除后仍被打开的文件
+    /// 原目录项所在的簇之后可能被释放并重用，副本不再访问它
+    pub fn detach(&self) -> VFile {
+        let dirent = self.read_short_dirent(|se: &ShortDirEntry| *se);
+        VFile {
+            name: self.name.clone(),
+            short_sector: 0,
+            short_offset: 0,
+            long_pos_vec: Vec::new(),
+            attribute: self.attribute,
+            short_dirent: ShortDirent::Detached(Arc::new(RwLock::new(dirent))),
+            fs: self.fs.clone(),
+            cache: self.cache.clone(),
+            block_device: self.block_device.clone(),
+        }
     }
 
     /* 返回sector和offset */
     // 获取短文件名目录项所在的扇区和偏移
-    pub fn get_pos(&self, offset: usize) -> (usize, usize) {
+    pub fn get_pos(&self, offset: usize) -> Result<(usize, usize), BlockError> {
         let (_, sec, off) = self.read_short_dirent(|s_ent: &ShortDirEntry| {
             s_ent.get_pos(
                 offset,
@@ -134,11 +197,15 @@ impl VFile {
                 &self.fs.read().get_fat(),
                 &self.block_device,
             )
-        });
-        (sec, off)
+        })?;
+        Ok((sec, off))
     }
 
-    fn find_long_name(&self, name: &str, dir_ent: &ShortDirEntry) -> Option<VFile> {
+    fn find_long_name(
+        &self,
+        name: &str,
+        dir_ent: &ShortDirEntry,
+    ) -> Result<Option<VFile>, BlockError> {
         // 长文件名，可能需要多个目录项
         let name_vec = self.fs.read().long_name_split(name, false); // 拆分
         let mut offset: usize = 0; // 偏移量
@@ -157,9 +224,9 @@ impl VFile {
                 &self.fs,
                 &self.fs.read().get_fat(),
                 &self.block_device,
-            );
+            )?;
             if read_sz != DIRENT_SZ || long_ent.is_empty() {
-                return None; // 读取结束，不是目录项
+                return Ok(None); // 读取结束，不是目录项
             }
             // 最后一项匹配
             if long_ent.get_name_format() == name_last && long_ent.attribute() == ATTRIBUTE_LFN {
@@ -184,9 +251,9 @@ impl VFile {
                         &self.fs,
                         &self.fs.read().get_fat(),
                         &self.block_device,
-                    );
+                    )?;
                     if read_sz != DIRENT_SZ {
-                        return None;
+                        return Ok(None);
                     }
                     if long_ent.get_name_raw() != name_vec[long_ent_num - 1 - i]
                         || long_ent.attribute() != ATTRIBUTE_LFN
@@ -205,28 +272,30 @@ impl VFile {
                         &self.fs,
                         &self.fs.read().get_fat(),
                         &self.block_device,
-                    );
+                    )?;
                     if read_sz != DIRENT_SZ {
-                        return None;
+                        return Ok(None);
                     }
                     if short_ent.is_valid() && l_checksum == short_ent.checksum() {
-                        let (short_sector, short_offset) = self.get_pos(s_off);
+                        let (short_sector, short_offset) = self.get_pos(s_off)?;
                         for i in 0..order as usize {
                             // 存入长名目录项位置了，第一个在栈顶
-                            let pos = self.get_pos(offset + i);
+                            let pos = self.get_pos(offset + i * DIRENT_SZ)?;
                             long_pos_vec.push(pos);
                         }
-                        return Some(VFile::new(
+                        return VFile::new(
                             String::from(name),
                             short_sector,
                             short_offset,
                             long_pos_vec,
                             short_ent.attribute(),
                             self.fs.clone(),
+                            self.cache.clone(),
                             self.block_device.clone(),
-                        ));
+                        )
+                        .map(Some);
                     } else {
-                        return None; // QUES
+                        return Ok(None); // QUES
                     }
                 } else {
                     offset += step * DIRENT_SZ;
@@ -239,7 +308,11 @@ impl VFile {
     }
 
     /// 查找短文件名目录
-    fn find_short_name(&self, name: &str, dir_ent: &ShortDirEntry) -> Option<VFile> {
+    fn find_short_name(
+        &self,
+        name: &str,
+        dir_ent: &ShortDirEntry,
+    ) -> Result<Option<VFile>, BlockError> {
         let name_upper = name.to_ascii_uppercase();
         let mut short_ent = ShortDirEntry::empty();
         let mut offset = 0;
@@ -251,23 +324,25 @@ impl VFile {
                 &self.fs,
                 &self.fs.read().get_fat(),
                 &self.block_device,
-            );
+            )?;
             // println!("short_ent.name: {:?}", short_ent.name);
             if read_sz != DIRENT_SZ || short_ent.is_empty() {
-                return None;
+                return Ok(None);
             } else {
                 if short_ent.is_valid() && name_upper == short_ent.get_name_uppercase() {
-                    let (short_sector, short_offset) = self.get_pos(offset);
+                    let (short_sector, short_offset) = self.get_pos(offset)?;
                     let long_pos_vec: Vec<(usize, usize)> = Vec::new();
-                    return Some(VFile::new(
+                    return VFile::new(
                         String::from(name),
                         short_sector,
                         short_offset,
                         long_pos_vec,
                         short_ent.attribute(),
                         self.fs.clone(),
+                        self.cache.clone(),
                         self.block_device.clone(),
-                    ));
+                    )
+                    .map(Some);
                 } else {
                     offset += DIRENT_SZ;
                     continue;
@@ -277,7 +352,7 @@ impl VFile {
     }
 
     /// 根据名称搜索当前目录下的文件
-    pub fn find_vfile_byname(&self, name: &str) -> Option<VFile> {
+    pub fn find_vfile_byname(&self, name: &str) -> Result<Option<VFile>, BlockError> {
         assert!(self.is_dir());
         let mut name_and_ext: Vec<&str> = name.split(".").collect();
         let name_ = name_and_ext[0].as_bytes();
@@ -295,25 +370,8 @@ impl VFile {
         })
     }
 
-    /// 根据路径递归搜索文件
-    pub fn find_vfile_bypath(&self, path: Vec<&str>) -> Option<Arc<VFile>> {
-        let len = path.len();
-        let mut current_vfile = self.clone();
-        for i in 0..len {
-            if path[i] == "." {
-                continue; // 跳过，表示仍然为当前目录
-            }
-            if let Some(vfile) = current_vfile.find_vfile_byname(path[i]) {
-                current_vfile = vfile;
-            } else {
-                return None;
-            }
-        }
-        Some(Arc::new(current_vfile))
-    }
-
-    fn increase_size(&self, new_size: u32) {
-        // TODO: return sth when cannot increase
+    /// 扩大文件至new_size，空间不足时返回false
+    fn increase_size(&self, new_size: u32) -> Result<bool, BlockError> {
         // println!("===================== in increase =======================");
         // println!("file: {}, newsz = {}", self.get_name(), new_size);
         // println!("try lock");
@@ -321,11 +379,11 @@ impl VFile {
         let old_size = self.get_size();
         let manager_writer = self.fs.write();
         if new_size <= old_size {
-            return;
+            return Ok(true);
         }
         // 获取现在需要多少cluster去增长size
         let needed =
-            manager_writer.cluster_num_needed(old_size, new_size, self.is_dir(), first_cluster);
+            manager_writer.cluster_num_needed(old_size, new_size, self.is_dir(), first_cluster)?;
         // println!("needed = {}", needed);
         if needed == 0 {
             if !self.is_dir() {
@@ -333,11 +391,11 @@ impl VFile {
                     se.set_size(new_size);
                 });
             }
-            return;
+            return Ok(true);
         }
 
         // println!("first cluster = {} nxt = {}", first_cluster, manager_writer.get_fat().read().get_next_cluster(first_cluster, self.block_device.clone()));
-        if let Some(cluster) = manager_writer.alloc_cluster(needed) {
+        if let Some(cluster) = manager_writer.alloc_cluster(needed)? {
             //println!("*** cluster alloc = {}",cluster);
             if first_cluster == 0 {
                 //未分配簇
@@ -357,9 +415,9 @@ impl VFile {
                 let fat_writer = fat.write();
                 //println!("get lock1");
                 let final_cluster =
-                    fat_writer.final_cluster(first_cluster, self.block_device.clone());
+                    fat_writer.final_cluster(first_cluster, self.block_device.clone())?;
                 assert_ne!(cluster, 0);
-                fat_writer.set_next_cluster(final_cluster, cluster, self.block_device.clone());
+                fat_writer.set_next_cluster(final_cluster, cluster, self.block_device.clone())?;
                 //let allc = fat_writer.get_all_cluster_of(first_cluster, self.block_device.clone());
                 // println!("  finish set next cluster, cluster chain:{:?}", allc);
                 drop(manager_writer);
@@ -368,8 +426,10 @@ impl VFile {
             self.modify_short_dirent(|se: &mut ShortDirEntry| {
                 se.set_size(new_size);
             });
+            Ok(true)
         } else {
-            panic!("SD Card no space!!!");
+            // SD Card no space
+            Ok(false)
         }
     }
 
@@ -379,21 +439,26 @@ impl VFile {
         });
     }
 
-    /// 在当前目录下创建文件
-    pub fn create(&self, name: &str, attribute: u8) -> Option<Arc<VFile>> {
-        // 检测同名文件, 此时应在根目录下
+    /// 在当前目录下写入名为name的目录项（必要时包括长名目录项）
+    /// 短目录项除文件名外的字段取自template
+    fn link_dirent(
+        &self,
+        name: &str,
+        template: &ShortDirEntry,
+    ) -> Result<Option<VFile>, BlockError> {
         assert!(self.is_dir());
         let manager_reader = self.fs.read();
         let (name_, ext_) = manager_reader.split_name_ext(name);
         // 搜索空处
         // 此时若不是目录文件，则返回为None
         let mut dirent_offset: usize;
-        if let Some(offset) = self.find_free_dirent() {
+        if let Some(offset) = self.find_free_dirent()? {
             dirent_offset = offset;
         } else {
-            return None;
+            return Ok(None);
         }
-        let mut short_ent = ShortDirEntry::empty();
+        let mut short_ent = *template;
+        let mut long_pos_vec: Vec<(usize, usize)> = Vec::new();
         if name_.len() > 8 || ext_.len() > 3 {
             // 长文件名拆分
             let mut v_long_name = manager_reader.long_name_split(name, true);
@@ -402,7 +467,8 @@ impl VFile {
             // 生成短文件名及对应目录项
             let short_name = manager_reader.generate_short_name(name);
             let (name_bytes, ext_bytes) = manager_reader.short_name_format(short_name.as_str());
-            short_ent.initialize(&name_bytes, &ext_bytes, attribute);
+            short_ent.set_name(&name_bytes, &ext_bytes);
+            short_ent.set_case(ALL_UPPER_CASE);
             let check_sum = short_ent.checksum();
             drop(manager_reader);
             // 写长名目录项
@@ -412,29 +478,49 @@ impl VFile {
                     order |= 0x40;
                 }
                 long_ent.initialize(v_long_name.pop().unwrap().as_bytes(), order, check_sum);
-                assert_eq!(
-                    // 写长目录项
-                    self.write_at(dirent_offset, long_ent.as_bytes_mut()),
-                    DIRENT_SZ
-                );
+                // 写长目录项
+                if self.write_at(dirent_offset, long_ent.as_bytes_mut())? != DIRENT_SZ {
+                    return Ok(None); // 空间不足
+                }
+                long_pos_vec.push(self.get_pos(dirent_offset)?);
                 dirent_offset += DIRENT_SZ;
             }
         } else {
             // 短文件名格式化
             let (name_bytes, ext_bytes) = manager_reader.short_name_format(name);
-            short_ent.initialize(&name_bytes, &ext_bytes, attribute);
+            short_ent.set_name(&name_bytes, &ext_bytes);
             short_ent.set_case(ALL_LOWER_CASE);
             drop(manager_reader);
         }
         // 写短目录项
-        assert_eq!(
-            self.write_at(dirent_offset, short_ent.as_bytes_mut()),
-            DIRENT_SZ
-        );
+        if self.write_at(dirent_offset, short_ent.as_bytes_mut())? != DIRENT_SZ {
+            return Ok(None);
+        }
+        // 按写入的位置构造，目录中可能暂时还有同名的目录项(如rename覆盖目标之前)
+        let (short_sector, short_offset) = self.get_pos(dirent_offset)?;
+        VFile::new(
+            String::from(name),
+            short_sector,
+            short_offset,
+            long_pos_vec,
+            short_ent.attribute(),
+            self.fs.clone(),
+            self.cache.clone(),
+            self.block_device.clone(),
+        )
+        .map(Some)
+    }
+
+    /// 在当前目录下创建文件
+    pub fn create(&self, name: &str, attribute: u8) -> Result<Option<Arc<VFile>>, BlockError> {
+        // 检测同名文件, 此时应在根目录下
+        assert!(self.is_dir());
+        let mut template = ShortDirEntry::empty();
+        template.initialize(&[0x20; 8], &[0x20; 3], attribute);
 
         // 如果是目录类型，需要创建.和..
 
-        if let Some(vfile) = self.find_vfile_byname(name) {
+        if let Some(vfile) = self.link_dirent(name, &template)? {
             if attribute & ATTRIBUTE_DIRECTORY != 0 {
                 let manager_reader = self.fs.read();
                 let (name_bytes, ext_bytes) = manager_reader.short_name_format(".");
@@ -444,16 +530,99 @@ impl VFile {
                 drop(manager_reader);
                 par_dir.set_first_cluster(self.first_cluster());
 
-                vfile.write_at(0, self_dir.as_bytes_mut());
-                vfile.write_at(DIRENT_SZ, par_dir.as_bytes_mut());
+                vfile.write_at(0, self_dir.as_bytes_mut())?;
+                vfile.write_at(DIRENT_SZ, par_dir.as_bytes_mut())?;
                 let first_cluster =
                     vfile.read_short_dirent(|se: &ShortDirEntry| se.first_cluster());
                 self_dir.set_first_cluster(first_cluster);
-                vfile.write_at(0, self_dir.as_bytes_mut());
+                vfile.write_at(0, self_dir.as_bytes_mut())?;
             }
-            return Some(Arc::new(vfile));
+            return Ok(Some(Arc::new(vfile)));
         } else {
-            None
+            Ok(None)
+        }
+    }
+
+    /// 在当前目录下创建指向target的符号链接
+    pub fn create_symlink(
+        &self,
+        name: &str,
+        target: &str,
+    ) -> Result<Option<Arc<VFile>>, BlockError> {
+        let vfile = match self.create(name, ATTRIBUTE_ARCHIVE | ATTRIBUTE_SYMLINK)? {
+            Some(vfile) => vfile,
+            None => return Ok(None),
+        };
+        let mut content = SYMLINK_MAGIC.to_vec();
+        for unit in target.encode_utf16() {
+            content.extend_from_slice(&unit.to_le_bytes());
+        }
+        if vfile.write_at(0, &content)? != content.len() {
+            vfile.remove()?;
+            return Ok(None);
+        }
+        Ok(Some(vfile))
+    }
+
+    /// 读取符号链接的目标，不是符号链接时返回None
+    pub fn read_link(&self) -> Result<Option<String>, BlockError> {
+        if !self.is_symlink() {
+            return Ok(None);
+        }
+        let mut buf = vec![0u8; self.get_size() as usize];
+        let len = self.read_at(0, &mut buf)?;
+        if len < SYMLINK_MAGIC.len() {
+            return Ok(None);
+        }
+        let units = buf[SYMLINK_MAGIC.len()..len]
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+        Ok(char::decode_utf16(units)
+            .collect::<Result<String, _>>()
+            .ok())
+    }
+
+    /// 修改目录中..目录项指向的父目录起始簇
+    fn set_parent_cluster(&self, cluster: u32) -> Result<(), BlockError> {
+        if !self.is_dir() {
+            return Ok(());
+        }
+        let mut dotdot = ShortDirEntry::empty();
+        if self.read_at(DIRENT_SZ, dotdot.as_bytes_mut())? != DIRENT_SZ
+            || dotdot.get_name_uppercase() != ".."
+        {
+            return Ok(());
+        }
+        dotdot.set_first_cluster(cluster);
+        self.write_at(DIRENT_SZ, dotdot.as_bytes_mut())?;
+        Ok(())
+    }
+
+    /// 判断当前目录是否为dir本身或其祖先目录
+    pub fn is_ancestor_of(&self, dir: &VFile) -> Result<bool, BlockError> {
+        if !self.is_dir() || !dir.is_dir() {
+            return Ok(false);
+        }
+        let target = self.first_cluster();
+        let root_cluster = self.fs.read().get_root_dirent().read().first_cluster();
+        if target == root_cluster {
+            return Ok(true);
+        }
+        let mut current = dir.clone();
+        loop {
+            let cluster = current.first_cluster();
+            if cluster == target {
+                return Ok(true);
+            }
+            if cluster == 0 || cluster == root_cluster {
+                return Ok(false);
+            }
+            // 沿着..向上查找
+            if let Some(parent) = current.find_vfile_byname("..")? {
+                current = parent;
+            } else {
+                return Ok(false);
+            }
         }
     }
 
@@ -467,15 +636,17 @@ impl VFile {
         let mut offset: usize = 0;
         let mut short_ent = ShortDirEntry::empty();
         loop {
-            let mut read_sz = self.read_short_dirent(|curr_ent: &ShortDirEntry| {
-                curr_ent.read_at(
-                    offset,
-                    short_ent.as_bytes_mut(),
-                    &self.fs,
-                    &self.fs.read().get_fat(),
-                    &self.block_device,
-                )
-            });
+            let mut read_sz = self
+                .read_short_dirent(|curr_ent: &ShortDirEntry| {
+                    curr_ent.read_at(
+                        offset,
+                        short_ent.as_bytes_mut(),
+                        &self.fs,
+                        &self.fs.read().get_fat(),
+                        &self.block_device,
+                    )
+                })
+                .ok()?;
             // 检测是否结束或被删除
             if read_sz != DIRENT_SZ || short_ent.is_empty() {
                 return Some(list);
@@ -500,15 +671,17 @@ impl VFile {
                 let mut name = long_ent.get_name_format();
                 for _ in 1..order as usize {
                     offset += DIRENT_SZ;
-                    read_sz = self.read_short_dirent(|curr_ent: &ShortDirEntry| {
-                        curr_ent.read_at(
-                            offset,
-                            long_ent.as_bytes_mut(),
-                            &self.fs,
-                            &self.fs.read().get_fat(),
-                            &self.block_device,
-                        )
-                    });
+                    read_sz = self
+                        .read_short_dirent(|curr_ent: &ShortDirEntry| {
+                            curr_ent.read_at(
+                                offset,
+                                long_ent.as_bytes_mut(),
+                                &self.fs,
+                                &self.fs.read().get_fat(),
+                                &self.block_device,
+                            )
+                        })
+                        .ok()?;
                     if read_sz != DIRENT_SZ || long_ent.is_empty() || long_ent.is_deleted() {
                         return Some(list);
                     }
@@ -518,15 +691,17 @@ impl VFile {
 
                 // 从短文件获取类型
                 offset += DIRENT_SZ;
-                read_sz = self.read_short_dirent(|curr_ent: &ShortDirEntry| {
-                    curr_ent.read_at(
-                        offset,
-                        long_ent.as_bytes_mut(),
-                        &self.fs,
-                        &self.fs.read().get_fat(),
-                        &self.block_device,
-                    )
-                });
+                read_sz = self
+                    .read_short_dirent(|curr_ent: &ShortDirEntry| {
+                        curr_ent.read_at(
+                            offset,
+                            long_ent.as_bytes_mut(),
+                            &self.fs,
+                            &self.fs.read().get_fat(),
+                            &self.block_device,
+                        )
+                    })
+                    .ok()?;
                 if read_sz != DIRENT_SZ || long_ent.is_empty() || long_ent.is_deleted() {
                     return Some(list);
                 }
@@ -554,15 +729,17 @@ impl VFile {
         let mut name = String::new();
         let mut is_long = false;
         loop {
-            let read_sz = self.read_short_dirent(|curr_ent: &ShortDirEntry| {
-                curr_ent.read_at(
-                    offset,
-                    long_ent.as_bytes_mut(),
-                    &self.fs,
-                    &self.fs.read().get_fat(),
-                    &self.block_device,
-                )
-            });
+            let read_sz = self
+                .read_short_dirent(|curr_ent: &ShortDirEntry| {
+                    curr_ent.read_at(
+                        offset,
+                        long_ent.as_bytes_mut(),
+                        &self.fs,
+                        &self.fs.read().get_fat(),
+                        &self.block_device,
+                    )
+                })
+                .ok()?;
             if read_sz != DIRENT_SZ || long_ent.is_empty() {
                 return None;
             }
@@ -604,8 +781,10 @@ impl VFile {
                 let fs_reader = self.fs.read();
                 let fat = fs_reader.get_fat();
                 let fat_reader = fat.read();
-                let cluster_num =
-                    fat_reader.count_cluster_num(first_clu, self.block_device.clone());
+                // 读取FAT出错时目录大小记为0
+                let cluster_num = fat_reader
+                    .count_cluster_num(first_clu, self.block_device.clone())
+                    .unwrap_or(0);
                 size = cluster_num * fs_reader.bytes_per_cluster();
                 //println!("{} {}",cluster_num, fs_reader.bytes_per_cluster());
             }
@@ -631,15 +810,17 @@ impl VFile {
 
         // 读取
         loop {
-            let read_sz = self.read_short_dirent(|curr_ent: &ShortDirEntry| {
-                curr_ent.read_at(
-                    offset,
-                    long_ent.as_bytes_mut(),
-                    &self.fs,
-                    &self.fs.read().get_fat(),
-                    &self.block_device,
-                )
-            });
+            let read_sz = self
+                .read_short_dirent(|curr_ent: &ShortDirEntry| {
+                    curr_ent.read_at(
+                        offset,
+                        long_ent.as_bytes_mut(),
+                        &self.fs,
+                        &self.fs.read().get_fat(),
+                        &self.block_device,
+                    )
+                })
+                .ok()?;
             // 不是目录项
             if read_sz != DIRENT_SZ || long_ent.is_empty() {
                 return Some(list);
@@ -672,7 +853,7 @@ impl VFile {
         }
     }
 
-    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, BlockError> {
         self.read_short_dirent(|short_ent: &ShortDirEntry| {
             short_ent.read_at(
                 offset,
@@ -685,8 +866,30 @@ impl VFile {
     }
 
     /// 写入文件的具体内容
-    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
-        self.increase_size((offset + buf.len()) as u32);
+    /// 写入位置超过文件末尾时，中间的空洞填0；空间不足时返回0
+    /// 返回写入的字节数，空间不足时只写入已分配的簇能容纳的部分，超过4GB的部分不写入
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, BlockError> {
+        let old_size = self.get_size() as usize;
+        let mut end = (offset + buf.len()).min(u32::MAX as usize);
+        if end <= offset {
+            return Ok(0);
+        }
+        if !self.increase_size(end as u32)? {
+            if self.is_dir() {
+                return Ok(0);
+            }
+            let bytes_per_cluster = self.fs.read().bytes_per_cluster() as usize;
+            let allocated =
+                (old_size + bytes_per_cluster - 1) / bytes_per_cluster * bytes_per_cluster;
+            end = end.min(allocated);
+            if end <= offset || !self.increase_size(end as u32)? {
+                return Ok(0);
+            }
+        }
+        let buf = &buf[..end - offset];
+        if !self.is_dir() && offset > old_size {
+            self.zero_fill(old_size, offset)?;
+        }
         // 写入短目录
         self.modify_short_dirent(|short_ent: &mut ShortDirEntry| {
             // 写入短目录的数据
@@ -700,16 +903,41 @@ impl VFile {
         })
     }
 
-    pub fn clear(&self) {
+    /// 将[start, end)清零，新分配的簇已经由alloc_cluster清零，只需处理原来最后一个簇的剩余部分
+    fn zero_fill(&self, start: usize, end: usize) -> Result<(), BlockError> {
+        let bytes_per_cluster = self.fs.read().bytes_per_cluster() as usize;
+        let end = end.min((start + bytes_per_cluster - 1) / bytes_per_cluster * bytes_per_cluster);
+        if start >= end {
+            return Ok(());
+        }
+        let zeros = [0u8; 512];
+        let mut offset = start;
+        while offset < end {
+            let len = (end - offset).min(zeros.len());
+            self.modify_short_dirent(|short_ent: &mut ShortDirEntry| {
+                short_ent.write_at(
+                    offset,
+                    &zeros[..len],
+                    &self.fs,
+                    &self.fs.read().get_fat(),
+                    &self.block_device,
+                )
+            })?;
+            offset += len;
+        }
+        Ok(())
+    }
+
+    pub fn clear(&self) -> Result<(), BlockError> {
         // 难点:长名目录项也要修改
         let first_cluster: u32 = self.first_cluster();
         if self.is_dir() || first_cluster == 0 {
-            return;
+            return Ok(());
         }
         for i in 0..self.long_pos_vec.len() {
             self.modify_long_dirent(i, |long_ent: &mut LongDirEntry| {
                 long_ent.clear();
-            });
+            })?;
         }
         self.modify_short_dirent(|short_ent: &mut ShortDirEntry| {
             short_ent.clear();
@@ -719,18 +947,58 @@ impl VFile {
             .read()
             .get_fat()
             .read()
-            .get_all_cluster_of(first_cluster, self.block_device.clone());
+            .get_all_cluster_of(first_cluster, self.block_device.clone())?;
         //self.fs.write().dealloc_cluster(all_clusters);
         let fs_reader = self.fs.read();
-        fs_reader.dealloc_cluster(all_clusters);
-        fs_reader.cache_write_back();
+        // FAT和FSInfo所在的块留给定期写回和sync
+        fs_reader.dealloc_cluster(all_clusters)
+    }
+
+    /// 将文件大小调整为new_size，缩小时释放多余的簇，扩大时填0；空间不足时返回false
+    pub fn truncate(&self, new_size: u32) -> Result<bool, BlockError> {
+        let old_size = self.get_size();
+        if self.is_dir() || new_size == old_size {
+            return Ok(true);
+        }
+        if new_size > old_size {
+            if !self.increase_size(new_size)? {
+                return Ok(false);
+            }
+            self.zero_fill(old_size as usize, new_size as usize)?;
+            return Ok(true);
+        }
+        if new_size == 0 {
+            self.clear()?;
+            return Ok(true);
+        }
+        let fs_reader = self.fs.read();
+        let keep = fs_reader.size_to_clusters(new_size);
+        if keep < fs_reader.size_to_clusters(old_size) {
+            let fat = fs_reader.get_fat();
+            let fat_writer = fat.write();
+            let last = fat_writer.get_cluster_at(
+                self.first_cluster(),
+                keep - 1,
+                self.block_device.clone(),
+            )?;
+            let rest = fat_writer.get_next_cluster(last, self.block_device.clone())?;
+            fat_writer.set_end(last, self.block_device.clone())?;
+            let freed = fat_writer.get_all_cluster_of(rest, self.block_device.clone())?;
+            drop(fat_writer);
+            fs_reader.dealloc_cluster(freed)?;
+        }
+        drop(fs_reader);
+        self.modify_short_dirent(|se: &mut ShortDirEntry| {
+            se.set_size(new_size);
+        });
+        Ok(true)
     }
 
     /// 查找可用目录项，返回offset，簇不够也会返回相应的offset，caller需要及时分配
-    fn find_free_dirent(&self) -> Option<usize> {
+    fn find_free_dirent(&self) -> Result<Option<usize>, BlockError> {
         // 不是目录项，返回空
         if !self.is_dir() {
-            return None;
+            return Ok(None);
         }
         let mut offset = 0;
         loop {
@@ -747,10 +1015,10 @@ impl VFile {
                     &self.fs.read().get_fat(),
                     &self.block_device,
                 )
-            });
+            })?;
             // 判断短目录项是否为空
             if tmp_dirent.is_empty() || read_sz == 0 {
-                return Some(offset);
+                return Ok(Some(offset));
             }
             offset += DIRENT_SZ;
         }
@@ -768,44 +1036,233 @@ impl VFile {
         self.read_short_dirent(|sde: &ShortDirEntry| sde.get_modification_time())
     }
 
-    /* WAITING 目前只支持删除自己*/
-    pub fn remove(&self) -> usize {
-        //self.modify_short_dirent(|sdent: &mut ShortDirEntry|{
-        //    sdent.delete();
-        //});
-        let first_cluster: u32 = self.first_cluster();
+    /// 将文件的数据和元数据写回磁盘
+    /// data_only为true时(fdatasync)只写回数据以及找到数据所需的FAT表项和短目录项
+    pub fn sync(&self, data_only: bool) -> Result<(), BlockError> {
+        let mut blocks = BTreeSet::new();
+        let first_cluster = self.first_cluster();
+        if first_cluster != 0 {
+            let fs_reader = self.fs.read();
+            let fat = fs_reader.get_fat();
+            let fat_reader = fat.read();
+            let sectors_per_cluster = fs_reader.sectors_per_cluster() as usize;
+            for cluster in
+                fat_reader.get_all_cluster_of(first_cluster, self.block_device.clone())?
+            {
+                let first_sector = fs_reader.first_sector_of_cluster(cluster);
+                blocks.extend(first_sector..first_sector + sectors_per_cluster);
+                blocks.extend(
+                    fat_reader
+                        .entry_sectors(cluster)
+                        .into_iter()
+                        .map(|sec| sec as usize),
+                );
+            }
+        }
+        if self.is_attached() {
+            blocks.insert(self.short_sector);
+        }
+        if !data_only {
+            blocks.extend(self.long_pos_vec.iter().map(|(sector, _)| *sector));
+        }
+        self.cache.sync_blocks(&blocks)
+    }
+
+    /// 新建文件时设置创建、修改和访问时间
+    pub fn init_times(&self, sec: u64, nsec: u32) {
+        self.modify_short_dirent(|sde: &mut ShortDirEntry| {
+            sde.set_creation_time(sec, nsec);
+            sde.set_modification_time(sec);
+            sde.set_accessed_time(sec);
+        });
+    }
+
+    /// 设置访问时间和修改时间，None表示不修改；时间未变化时不写回目录项
+    pub fn set_times(&self, atime: Option<u64>, mtime: Option<u64>) {
+        let apply = |sde: &mut ShortDirEntry| {
+            let mut changed = false;
+            if let Some(atime) = atime {
+                changed |= sde.set_accessed_time(atime);
+            }
+            if let Some(mtime) = mtime {
+                changed |= sde.set_modification_time(mtime);
+            }
+            changed
+        };
+        // 先在副本上检查，避免每次读写都把目录项所在块标脏
+        let mut copy = self.read_short_dirent(|sde: &ShortDirEntry| *sde);
+        if apply(&mut copy) {
+            self.modify_short_dirent(apply);
+        }
+    }
+
+    /// 判断目录是否为空（忽略.和..）
+    pub fn is_empty_dir(&self) -> Result<bool, BlockError> {
+        if !self.is_dir() {
+            return Ok(false);
+        }
+        let mut offset: usize = 0;
+        let mut short_ent = ShortDirEntry::empty();
+        loop {
+            let read_sz = self.read_short_dirent(|curr_ent: &ShortDirEntry| {
+                curr_ent.read_at(
+                    offset,
+                    short_ent.as_bytes_mut(),
+                    &self.fs,
+                    &self.fs.read().get_fat(),
+                    &self.block_device,
+                )
+            })?;
+            // 读到末尾或空目录项，说明后面没有文件了
+            if read_sz != DIRENT_SZ || short_ent.is_empty() {
+                return Ok(true);
+            }
+            offset += DIRENT_SZ;
+            if short_ent.is_deleted() {
+                continue;
+            }
+            if short_ent.is_long() {
+                // 有效的长目录项后必然跟着一个文件
+                return Ok(false);
+            }
+            let name = short_ent.get_name_uppercase();
+            if name != "." && name != ".." {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// 将长短目录项标记为删除，但保留簇链
+    /// 已经打开的文件仍可以通过短目录项访问数据，最后关闭时再调用free_clusters
+    pub fn unlink(&self) -> Result<(), BlockError> {
         for i in 0..self.long_pos_vec.len() {
             self.modify_long_dirent(i, |long_ent: &mut LongDirEntry| {
                 long_ent.delete();
-            });
+            })?;
         }
-        //println!("[fs]: rm file");
         self.modify_short_dirent(|short_ent: &mut ShortDirEntry| {
-            short_ent.delete();
+            short_ent.mark_deleted();
         });
+        Ok(())
+    }
+
+    /// 释放文件占用的所有簇，返回释放的簇数
+    pub fn free_clusters(&self) -> Result<usize, BlockError> {
+        let first_cluster: u32 = self.first_cluster();
+        if first_cluster == 0 {
+            return Ok(0);
+        }
         let all_clusters = self
             .fs
             .read()
             .get_fat()
             .read()
-            .get_all_cluster_of(first_cluster, self.block_device.clone());
-        self.fs.write().dealloc_cluster(all_clusters.clone());
-        return all_clusters.len();
+            .get_all_cluster_of(first_cluster, self.block_device.clone())?;
+        self.modify_short_dirent(|short_ent: &mut ShortDirEntry| {
+            short_ent.clear();
+        });
+        let num = all_clusters.len();
+        let fs_reader = self.fs.read();
+        fs_reader.dealloc_cluster(all_clusters)?;
+        Ok(num)
     }
 
-    /* WAITING */
-    #[allow(unused)]
-    fn remove_file() {}
+    /// 删除文件或目录本身，返回释放的簇数
+    pub fn remove(&self) -> Result<usize, BlockError> {
+        self.unlink()?;
+        self.free_clusters()
+    }
+
+    /// 删除普通文件，目标是目录时返回None
+    pub fn remove_file(&self) -> Result<Option<usize>, BlockError> {
+        if self.is_dir() {
+            return Ok(None);
+        }
+        self.remove().map(Some)
+    }
+
+    /// 删除空目录，目标不是目录、是根目录或者非空时返回None
+    pub fn remove_dir(&self) -> Result<Option<usize>, BlockError> {
+        if !self.is_dir() || !self.is_attached() || !self.is_empty_dir()? {
+            return Ok(None);
+        }
+        self.remove().map(Some)
+    }
+}
 
-    /* WAITING */
-    #[allow(unused)]
-    fn remove_dir() {}
+/// 在dst_dir下创建名为name的新文件，并复制src的内容，不支持目录
+pub fn fcopy(src: &VFile, dst_dir: &VFile, name: &str) -> Result<Option<Arc<VFile>>, BlockError> {
+    if src.is_dir() {
+        return Ok(None);
+    }
+    let dst = match dst_dir.create(name, src.get_attribute())? {
+        Some(dst) => dst,
+        None => return Ok(None),
+    };
+    let mut buffer = [0u8; 512];
+    let mut offset = 0;
+    loop {
+        let len = src.read_at(offset, &mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        dst.write_at(offset, &buffer[..len])?;
+        offset += len;
+    }
+    Ok(Some(dst))
 }
 
-/* WAITING */
-#[allow(unused)]
-pub fn fcopy() {}
+/// 将src移动到dst_dir下并命名为name，只移动长短目录项，不复制数据簇
+/// 先写入新目录项再删除旧目录项，中途出错时最多多出一个目录项而不会丢失文件
+/// dst_dir中已有的同名目录项不受影响，由调用者在之后删除
+pub fn fmove(src: &VFile, dst_dir: &VFile, name: &str) -> Result<Option<VFile>, BlockError> {
+    let short_ent = src.read_short_dirent(|se: &ShortDirEntry| *se);
+    let dst = match dst_dir.link_dirent(name, &short_ent)? {
+        Some(dst) => dst,
+        None => return Ok(None),
+    };
+    src.unlink()?;
+    if dst.is_dir() {
+        // 目录被移动后需要修改..
+        dst.set_parent_cluster(dst_dir.first_cluster())?;
+    }
+    Ok(Some(dst))
+}
 
-/* WAITING */
-#[allow(unused)]
-pub fn fmove() {}
+/// 交换a和b指向的内容，两者保留原来的名字，a_dir和b_dir分别是它们所在的目录
+/// 返回交换后a和b两个位置上的VFile
+pub fn fexchange(
+    a: &VFile,
+    a_dir: &VFile,
+    b: &VFile,
+    b_dir: &VFile,
+) -> Result<(VFile, VFile), BlockError> {
+    let a_ent = a.read_short_dirent(|se: &ShortDirEntry| *se);
+    let b_ent = b.read_short_dirent(|se: &ShortDirEntry| *se);
+    a.modify_short_dirent(|se: &mut ShortDirEntry| se.copy_payload_from(&b_ent));
+    b.modify_short_dirent(|se: &mut ShortDirEntry| se.copy_payload_from(&a_ent));
+    // 交换之后，a处存放的是原来b的内容，反之亦然
+    let new_a = VFile::new(
+        String::from(a.get_name()),
+        a.short_sector,
+        a.short_offset,
+        a.long_pos_vec.clone(),
+        b_ent.attribute(),
+        a.fs.clone(),
+        a.cache.clone(),
+        a.block_device.clone(),
+    )?;
+    let new_b = VFile::new(
+        String::from(b.get_name()),
+        b.short_sector,
+        b.short_offset,
+        b.long_pos_vec.clone(),
+        a_ent.attribute(),
+        b.fs.clone(),
+        b.cache.clone(),
+        b.block_device.clone(),
+    )?;
+    new_a.set_parent_cluster(a_dir.first_cluster())?;
+    new_b.set_parent_cluster(b_dir.first_cluster())?;
+    Ok((new_a, new_b))
+}
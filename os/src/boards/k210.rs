@@ -1,5 +1,7 @@
 #![allow(unused)]
 
+use crate::drivers::plic::{Plic, TargetPriority};
+
 pub const CLOCK_FREQ: usize = 403000000 / 62;
 
 pub const MMIO: &[(usize, usize)] = &[
@@ -21,11 +23,25 @@ pub const MMIO: &[(usize, usize)] = &[
     (0x5400_0000, 0x1000), /* SPI2      */
 ];
 
+pub type CharDeviceImpl = crate::drivers::chardev::UartHs;
+pub const UART_IRQ: usize = 33; // UARTHS的中断号
 pub type BlockDeviceImpl = crate::drivers::block::SDCardWrapper;
+pub const BLOCK_DEVICE_NAME: &str = "mmcblk0"; // 块设备名，分区名在其后加上分区号
+pub const BLOCK_DEVICE_MAJOR: u32 = 179; // MMC块设备的主设备号
+
+pub const PLIC_BASE: usize = 0x0C00_0000;
+/// K210的PLIC没有S态上下文，由M态固件把hart 0上下文的外部中断转交给内核
+pub const PLIC_CONTEXT: usize = Plic::context(0, TargetPriority::Machine);
 
+/// 初始化中断控制器并打开S态外部中断
 pub fn device_init() {
-    unimplemented!();
+    crate::drivers::irq::init();
+    unsafe {
+        riscv::register::sie::set_sext();
+    }
 }
+
+/// S态外部中断的处理函数
 pub fn irq_handler() {
-    unimplemented!();
+    crate::drivers::irq::handle_irqs();
 }
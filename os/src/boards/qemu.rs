@@ -1,5 +1,32 @@
+use crate::drivers::plic::{Plic, TargetPriority};
+
 pub const CLOCK_FREQ: usize = 12500000;
 
-pub const MMIO: &[(usize, usize)] = &[(0x10001000, 0x1000)];
+pub const MMIO: &[(usize, usize)] = &[
+    (0x0C00_0000, 0x3000), /* PLIC      */
+    (0x0C20_0000, 0x2000), /* PLIC      */
+    (0x1000_0000, 0x1000), /* UART0     */
+    (0x1000_1000, 0x1000), /* VIRTIO0   */
+];
 
+pub type CharDeviceImpl = crate::drivers::chardev::Ns16550a;
+pub const UART_IRQ: usize = 10; // UART0的中断号
 pub type BlockDeviceImpl = crate::drivers::block::VirtIOBlock;
+pub const BLOCK_DEVICE_NAME: &str = "vda"; // 块设备名，分区名在其后加上分区号
+pub const BLOCK_DEVICE_MAJOR: u32 = 254; // virtio-blk的主设备号
+
+pub const PLIC_BASE: usize = 0x0C00_0000;
+pub const PLIC_CONTEXT: usize = Plic::context(0, TargetPriority::Supervisor);
+
+/// 初始化中断控制器并打开S态外部中断
+pub fn device_init() {
+    crate::drivers::irq::init();
+    unsafe {
+        riscv::register::sie::set_sext();
+    }
+}
+
+/// S态外部中断的处理函数
+pub fn irq_handler() {
+    crate::drivers::irq::handle_irqs();
+}
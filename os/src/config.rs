@@ -4,6 +4,16 @@
 pub const USER_STACK_SIZE: usize = 4096 * 2; // 8K
 pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_HEAP_SIZE: usize = 0x20_0000;
+pub const BLOCK_CACHE_CAPACITY: usize = 256; // 目录项缓存和数据缓存各自的块数
+pub const DENTRY_CACHE_CAPACITY: usize = 128; // 常驻内存的最近使用的目录项数
+pub const SDCARD_RETRIES: usize = 3; // SD卡传输失败后的重试次数
+pub const FAT_FREE_BITMAP: bool = true; // 挂载时构建空闲簇位图，加快分配和空闲空间统计
+pub const FAT_REPAIR_ON_MOUNT: bool = true; // 挂载时发现各份FAT不一致则以主FAT为准修复
+pub const TMPFS_MAX_PAGES: usize = 256; // 未指定size=时tmpfs最多使用的页数
+pub const TMPFS_ON_TMP: bool = true; // 启动时在/tmp上挂载tmpfs
+pub const UART_RX_BUFFER_SIZE: usize = 1024; // 串口接收缓冲区的字节数
+pub const UART_TX_BUFFER_SIZE: usize = 4096; // 串口发送缓冲区的字节数
+pub const ROOT_PARTITION: PartitionSelector = PartitionSelector::FirstFat; // 设备有分区表时根文件系统所在的分区
 pub const MEMORY_END: usize = 0x80800000; //8M
 pub const PAGE_SIZE: usize = 0x1000; // 4K
 pub const PAGE_SIZE_BITS: usize = 0xc;
@@ -13,3 +23,4 @@ pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 
 pub use crate::board::{CLOCK_FREQ, MMIO};
+use fat32::PartitionSelector;
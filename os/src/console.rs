@@ -1,14 +1,12 @@
-//! SBI console driver, for text output
-use crate::sbi::console_putchar;
+//! Kernel console output, through the UART driver once it is ready and SBI before
+use crate::drivers::console_putbytes;
 use core::fmt::{self, Write};
 
 struct Stdout;
 
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            console_putchar(c as usize);
-        }
+        console_putbytes(s.as_bytes());
         Ok(())
     }
 }
@@ -0,0 +1,55 @@
+//! 以普通文件为后端的loop块设备
+use crate::fs::{close_inode, open_inode, Inode};
+use alloc::sync::Arc;
+use fat32::{BlockDevice, BlockError, BLOCK_SZ};
+
+pub struct LoopDevice {
+    file: Arc<dyn Inode>,
+    /// 文件能容纳的完整块数，末尾不足一块的部分被忽略
+    blocks: usize,
+}
+
+impl LoopDevice {
+    /// 以file为后端创建loop设备，设备存在期间file保持打开
+    pub fn new(file: Arc<dyn Inode>) -> Result<Self, isize> {
+        let blocks = file.stat()?.size as usize / BLOCK_SZ;
+        open_inode(&file);
+        Ok(Self { file, blocks })
+    }
+}
+
+impl Drop for LoopDevice {
+    fn drop(&mut self) {
+        close_inode(&self.file);
+    }
+}
+
+impl BlockDevice for LoopDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlockError> {
+        if block_id >= self.blocks {
+            return Err(BlockError::IoError);
+        }
+        match self.file.read_at(block_id * BLOCK_SZ, buf) {
+            Ok(len) if len == buf.len() => Ok(()),
+            _ => Err(BlockError::IoError),
+        }
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), BlockError> {
+        if block_id >= self.blocks {
+            return Err(BlockError::IoError);
+        }
+        match self.file.write_at(block_id * BLOCK_SZ, buf) {
+            Ok(len) if len == buf.len() => Ok(()),
+            _ => Err(BlockError::IoError),
+        }
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.file.sync(false).map_err(|_| BlockError::IoError)
+    }
+
+    fn num_blocks(&self) -> usize {
+        self.blocks
+    }
+}
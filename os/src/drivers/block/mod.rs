@@ -1,30 +1,151 @@
+mod loop_dev;
 mod sdcard;
 mod virtio_blk;
 
+pub use loop_dev::LoopDevice;
 pub use sdcard::SDCardWrapper;
 pub use virtio_blk::VirtIOBlock;
 
-use crate::board::BlockDeviceImpl;
+use crate::board::{BlockDeviceImpl, BLOCK_DEVICE_MAJOR, BLOCK_DEVICE_NAME};
+use crate::config::ROOT_PARTITION;
+use crate::task::current_task;
+use alloc::format;
+use alloc::string::String;
 use alloc::sync::Arc;
-use fat32::BlockDevice;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use fat32::{read_partitions, BlockDevice, PartitionDevice};
 use lazy_static::*;
 
 // Arc 多线程安全共享对象的方法；不是为mut，多个指针指向同一块地址
 lazy_static! { // ref point define
-    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
+    /// 没有磁盘时为None
+    pub static ref BLOCK_DEVICE: Option<Arc<dyn BlockDevice>> =
+        BlockDeviceImpl::new().map(|device| Arc::new(device) as Arc<dyn BlockDevice>);
+}
+
+lazy_static! {
+    /// 根文件系统所在的块设备及其设备名
+    static ref ROOT: Option<(Arc<dyn BlockDevice>, String)> =
+        BLOCK_DEVICE.clone().and_then(root_partition);
+    /// 根文件系统所在的块设备，整个设备没有分区表时即为BLOCK_DEVICE
+    pub static ref ROOT_BLOCK_DEVICE: Option<Arc<dyn BlockDevice>> =
+        ROOT.as_ref().map(|root| root.0.clone());
+}
+
+/// 正在睡眠等待块设备I/O完成的任务数
+static IO_SLEEPERS: AtomicUsize = AtomicUsize::new(0);
+
+/// 执行f期间当前任务的块设备I/O睡眠等待设备的中断，其他时候轮询等待
+/// 文件系统在其缓存的自旋锁下读写设备，只有确定不持有自旋锁的调用者才能使用
+pub fn with_sleepable_io<T>(f: impl FnOnce() -> T) -> T {
+    let task = match current_task() {
+        Some(task) => task,
+        None => return f(),
+    };
+    let old = core::mem::replace(&mut task.inner_exclusive_access().io_may_sleep, true);
+    let ret = f();
+    task.inner_exclusive_access().io_may_sleep = old;
+    ret
+}
+
+/// 当前任务的块设备I/O能否睡眠
+fn io_may_sleep() -> bool {
+    current_task().map_or(false, |task| task.inner_exclusive_access().io_may_sleep)
+}
+
+/// 是否有任务在睡眠等待块设备I/O，空闲循环据此判断是否还有任务会被唤醒
+pub fn block_io_waiting() -> bool {
+    IO_SLEEPERS.load(Ordering::Relaxed) > 0
+}
+
+/// 根文件系统所在块设备的设备名，如vda或vda1
+pub fn root_device_name() -> Option<&'static str> {
+    ROOT.as_ref().map(|root| root.1.as_str())
+}
+
+/// 第index个分区(从0开始)的设备名，设备名以数字结尾时加上p，如mmcblk0p1
+fn partition_name(index: usize) -> String {
+    if BLOCK_DEVICE_NAME.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{}p{}", BLOCK_DEVICE_NAME, index + 1)
+    } else {
+        format!("{}{}", BLOCK_DEVICE_NAME, index + 1)
+    }
+}
+
+/// 根据设备名查找块设备或其上的分区
+pub fn block_device_by_name(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    let device = BLOCK_DEVICE.clone()?;
+    if name == BLOCK_DEVICE_NAME {
+        return Some(device);
+    }
+    let partitions = read_partitions(&device).ok()?;
+    let partition = partitions
+        .iter()
+        .find(|partition| partition_name(partition.index) == name)?;
+    Some(Arc::new(PartitionDevice::new(device, partition)))
+}
+
+/// 块设备及其各分区的(设备名, 主设备号, 次设备号)，整个设备的次设备号为0，分区依次加1
+pub fn block_device_numbers() -> Vec<(String, u32, u32)> {
+    let device = match BLOCK_DEVICE.clone() {
+        Some(device) => device,
+        None => return Vec::new(),
+    };
+    let mut devices = vec![(String::from(BLOCK_DEVICE_NAME), BLOCK_DEVICE_MAJOR, 0)];
+    if let Ok(partitions) = read_partitions(&device) {
+        for partition in partitions.iter() {
+            let minor = partition.index as u32 + 1;
+            devices.push((partition_name(partition.index), BLOCK_DEVICE_MAJOR, minor));
+        }
+    }
+    devices
+}
+
+/// 按ROOT_PARTITION在设备的分区表中选择根分区，找不到时返回None
+fn root_partition(device: Arc<dyn BlockDevice>) -> Option<(Arc<dyn BlockDevice>, String)> {
+    let partitions = match read_partitions(&device) {
+        Ok(partitions) => partitions,
+        Err(err) => {
+            println!("[fs]: failed to read the partition table: {:?}", err);
+            return None;
+        }
+    };
+    if partitions.is_empty() {
+        return Some((device, String::from(BLOCK_DEVICE_NAME)));
+    }
+    match ROOT_PARTITION.select(&partitions) {
+        Some(partition) => {
+            println!(
+                "[fs]: root on partition {} (start {}, {} sectors)",
+                partition.index, partition.start, partition.size
+            );
+            Some((
+                Arc::new(PartitionDevice::new(device, partition)),
+                partition_name(partition.index),
+            ))
+        }
+        None => {
+            println!("[fs]: root partition not found in the partition table");
+            None
+        }
+    }
 }
 
 #[allow(unused)]
 pub fn block_device_test() {
-    let block_device = BLOCK_DEVICE.clone();
+    let block_device = BLOCK_DEVICE.clone().expect("no block device");
     let mut write_buffer = [0u8; 512];
     let mut read_buffer = [0u8; 512];
     for i in 0..512 {
         for byte in write_buffer.iter_mut() {
             *byte = i as u8;
         }
-        block_device.write_block(i as usize, &write_buffer);
-        block_device.read_block(i as usize, &mut read_buffer);
+        block_device.write_block(i as usize, &write_buffer).unwrap();
+        block_device
+            .read_block(i as usize, &mut read_buffer)
+            .unwrap();
         assert_eq!(write_buffer, read_buffer);
     }
     println!("block device test passed!");
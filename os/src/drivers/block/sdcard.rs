@@ -5,8 +5,11 @@
 use crate::sync::UPSafeCell;
 
 use super::BlockDevice;
+use crate::config::SDCARD_RETRIES;
 use alloc::sync::Arc;
 use core::convert::TryInto;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use fat32::BlockError;
 use k210_hal::prelude::*;
 use k210_pac::{Peripherals, SPI0};
 use k210_soc::{
@@ -42,6 +45,8 @@ pub const SD_START_DATA_MULTIPLE_BLOCK_READ: u8 = 0xFE;
 pub const SD_START_DATA_SINGLE_BLOCK_WRITE: u8 = 0xFE;
 /** Data token start byte, Start Multiple Block Write */
 pub const SD_START_DATA_MULTIPLE_BLOCK_WRITE: u8 = 0xFC;
+/** Data token stop byte, Stop Transmission of a Multiple Block Write */
+pub const SD_STOP_DATA_MULTIPLE_BLOCK_WRITE: u8 = 0xFD;
 
 pub const SEC_LEN: usize = 512;
 
@@ -348,6 +353,24 @@ impl</*'a,*/ X: SPI> SDCard</*'a,*/ X> {
         0
     }
 
+    /*
+     * Wait until the SD card finishes programming, it holds MISO low while busy.
+     * @param  None
+     * @retval true if the card is ready, false on timeout
+     */
+    fn wait_ready(&self) -> bool {
+        let response = &mut [0u8];
+        let mut timeout = 0xFFFFF;
+        while timeout != 0 {
+            self.read_data(response);
+            if response[0] == 0xFF {
+                return true;
+            }
+            timeout -= 1;
+        }
+        false
+    }
+
     /*
      * Read the CSD card register
      *         Reading the contents of the CSD register in SPI mode is a simple
@@ -662,6 +685,10 @@ impl</*'a,*/ X: SPI> SDCard</*'a,*/ X> {
             self.send_cmd(CMD::CMD24, sector, 0);
         } else {
             frame[1] = SD_START_DATA_MULTIPLE_BLOCK_WRITE;
+            /* ACMD23 pre-erases the blocks, it is only a hint so its result is ignored */
+            self.send_cmd(CMD::CMD55, 0, 0);
+            self.get_response();
+            self.end_cmd();
             self.send_cmd(
                 CMD::ACMD23,
                 (data_buf.len() / SEC_LEN).try_into().unwrap(),
@@ -676,6 +703,7 @@ impl</*'a,*/ X: SPI> SDCard</*'a,*/ X> {
             self.end_cmd();
             return Err(());
         }
+        let mut error = false;
         //let mut dma_chunk = [0u32; SEC_LEN];
         let mut tmp_chunk = [0u8; SEC_LEN];
         for chunk in data_buf.chunks(SEC_LEN) {
@@ -692,13 +720,26 @@ impl</*'a,*/ X: SPI> SDCard</*'a,*/ X> {
             self.write_data(&[0xff, 0xff]);
             /* Read data response */
             if self.get_dataresponse() != 0x00 {
-                self.end_cmd();
-                return Err(());
+                error = true;
+                break;
             }
         }
+        if frame[1] == SD_START_DATA_MULTIPLE_BLOCK_WRITE {
+            /* Stop the transmission, also after a rejected block, then skip the stuff byte */
+            self.write_data(&[SD_STOP_DATA_MULTIPLE_BLOCK_WRITE]);
+            self.read_data(&mut [0u8]);
+        }
+        /* The card is busy until the data is programmed */
+        if !self.wait_ready() {
+            error = true;
+        }
         self.end_cmd();
         self.end_cmd();
-        Ok(())
+        if error {
+            Err(())
+        } else {
+            Ok(())
+        }
     }
 }
 
@@ -722,7 +763,8 @@ lazy_static! {
         unsafe { UPSafeCell::new(Peripherals::take().unwrap()) };
 }
 
-fn init_sdcard() -> SDCard<SPIImpl<SPI0>> {
+/// 没有插入SD卡或初始化失败时返回None
+fn init_sdcard() -> Option<(SDCard<SPIImpl<SPI0>>, usize)> {
     usleep(100000);
     let peripherals = unsafe { Peripherals::steal() };
     sysctl::pll_set_freq(sysctl::pll::PLL0, 800_000_000).unwrap();
@@ -736,27 +778,70 @@ fn init_sdcard() -> SDCard<SPIImpl<SPI0>> {
     // alert!("[sdcard] init sdcard");
     let spi = peripherals.SPI0.constrain();
     let sd = SDCard::new(spi, SD_CS, SD_CS_GPIONUM);
-    let info = sd.init().unwrap();
+    let info = sd.init().ok()?;
     let num_sectors = info.CardCapacity / 512;
-    assert!(num_sectors > 0);
+    if num_sectors == 0 {
+        return None;
+    }
 
     // println!("[sdcard] init sdcard! finish {}", num_sectors);
-    sd
+    Some((sd, num_sectors as usize))
 }
 
-pub struct SDCardWrapper(Arc<Mutex<SDCard<SPIImpl<SPI0>>>>);
+pub struct SDCardWrapper {
+    sdcard: Arc<Mutex<SDCard<SPIImpl<SPI0>>>>,
+    num_sectors: usize,
+    /// 传输失败后的重试次数
+    retries: AtomicUsize,
+}
 
 impl SDCardWrapper {
-    pub fn new() -> Self {
-        unsafe { Self(Arc::new(Mutex::new(init_sdcard()))) }
+    /// 没有可用的SD卡时返回None
+    pub fn new() -> Option<Self> {
+        let (sdcard, num_sectors) = init_sdcard()?;
+        Some(Self {
+            sdcard: Arc::new(Mutex::new(sdcard)),
+            num_sectors,
+            retries: AtomicUsize::new(SDCARD_RETRIES),
+        })
+    }
+
+    /// 设置传输失败后的重试次数
+    #[allow(unused)]
+    pub fn set_retries(&self, retries: usize) {
+        self.retries.store(retries, Ordering::Relaxed);
+    }
+
+    /// 执行一次传输，失败时重试，重试次数用尽后返回IoError
+    fn with_retry<F>(&self, mut op: F) -> Result<(), BlockError>
+    where
+        F: FnMut(&SDCard<SPIImpl<SPI0>>) -> Result<(), ()>,
+    {
+        let sdcard = self.sdcard.lock();
+        for _ in 0..=self.retries.load(Ordering::Relaxed) {
+            if op(&*sdcard).is_ok() {
+                return Ok(());
+            }
+        }
+        Err(BlockError::IoError)
     }
 }
 
 impl BlockDevice for SDCardWrapper {
-    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        self.0.lock().read_sector(buf, block_id as u32).unwrap();
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.with_retry(|sdcard| sdcard.read_sector(buf, block_id as u32))
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), BlockError> {
+        self.with_retry(|sdcard| sdcard.write_sector(buf, block_id as u32))
+    }
+    // 多于一个扇区时read_sector/write_sector使用CMD18/CMD25多块传输
+    fn read_blocks(&self, start_block: usize, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.with_retry(|sdcard| sdcard.read_sector(buf, start_block as u32))
+    }
+    fn write_blocks(&self, start_block: usize, buf: &[u8]) -> Result<(), BlockError> {
+        self.with_retry(|sdcard| sdcard.write_sector(buf, start_block as u32))
     }
-    fn write_block(&self, block_id: usize, buf: &[u8]) {
-        self.0.lock().write_sector(buf, block_id as u32).unwrap();
+    fn num_blocks(&self) -> usize {
+        self.num_sectors
     }
 }
@@ -1,46 +1,196 @@
-use super::BlockDevice;
+use super::{io_may_sleep, BlockDevice, IO_SLEEPERS};
+use crate::drivers::register_irq;
 use crate::mm::{
     frame_alloc, frame_dealloc, kernel_token, FrameTracker, PageTable, PhysAddr, PhysPageNum,
     StepByOne, VirtAddr,
 };
+use crate::task::WaitQueue;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+use fat32::BlockError;
 use lazy_static::*;
 use spin::Mutex;
-use virtio_drivers::{VirtIOBlk, VirtIOHeader};
-
-// use spin::Mutex;
+use virtio_drivers::{BlkResp, Error as VirtIOError, RespStatus, VirtIOBlk, VirtIOHeader};
 
 #[allow(unused)]
 const VIRTIO0: usize = 0x10001000;
+/// VIRTIO0在PLIC上的中断号
+const VIRTIO0_IRQ: usize = 1;
+/// 设备配置空间在MMIO寄存器中的偏移，前8字节为容量(扇区数)
+const VIRTIO_CONFIG_OFFSET: usize = 0x100;
+/// 每个请求占用的描述符数：请求头、数据、状态
+const DESC_PER_REQ: usize = 3;
+const SECTOR_SIZE: usize = 512;
+
+/// 请求异步提交到virtqueue，提交者睡眠等待，由设备的中断处理函数唤醒
+/// 调用者可能持有自旋锁(如文件系统的缓存)或还没有任务在运行时轮询等待
+pub struct VirtIOBlock(Arc<BlkQueue>);
+
+struct BlkQueue {
+    inner: Mutex<BlkInner>,
+    /// 等待请求完成或virtqueue中出现空位的任务
+    waiters: WaitQueue,
+}
 
-pub struct VirtIOBlock(Mutex<VirtIOBlk<'static>>);
+struct BlkInner {
+    blk: VirtIOBlk<'static>,
+    /// 下一个请求的序号，token在请求完成后会被重用，提交者用序号等待
+    next_seq: u64,
+    /// 已提交但尚未完成的请求，token -> 序号
+    inflight: BTreeMap<u16, u64>,
+    /// 已完成但提交者尚未取走的请求序号
+    completed: BTreeSet<u64>,
+}
 
 lazy_static! {
     static ref QUEUE_FRAMES: Mutex<Vec<FrameTracker>> = Mutex::new(Vec::new());
 }
 
 impl BlockDevice for VirtIOBlock {
-    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.read_blocks(block_id, buf)
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), BlockError> {
+        self.write_blocks(block_id, buf)
+    }
+    fn read_blocks(&self, start_block: usize, buf: &mut [u8]) -> Result<(), BlockError> {
         self.0
-            .lock()
-            .read_block(block_id, buf)
-            .expect("Error when reading VirtIOBlk");
+            .submit_and_wait(buf.len() / SECTOR_SIZE, |blk, i, resp| unsafe {
+                let chunk = &mut buf[i * SECTOR_SIZE..(i + 1) * SECTOR_SIZE];
+                blk.read_block_nb(start_block + i, chunk, resp)
+            })
     }
-    fn write_block(&self, block_id: usize, buf: &[u8]) {
+    fn write_blocks(&self, start_block: usize, buf: &[u8]) -> Result<(), BlockError> {
         self.0
-            .lock()
-            .write_block(block_id, buf)
-            .expect("Error when writing VirtIOBlk");
+            .submit_and_wait(buf.len() / SECTOR_SIZE, |blk, i, resp| unsafe {
+                let chunk = &buf[i * SECTOR_SIZE..(i + 1) * SECTOR_SIZE];
+                blk.write_block_nb(start_block + i, chunk, resp)
+            })
+    }
+    fn num_blocks(&self) -> usize {
+        let capacity =
+            unsafe { core::ptr::read_volatile((VIRTIO0 + VIRTIO_CONFIG_OFFSET) as *const u64) };
+        capacity as usize
+    }
+}
+
+/// 将virtio的错误转换为块设备错误
+fn blk_error(err: VirtIOError) -> BlockError {
+    match err {
+        VirtIOError::NotReady => BlockError::NotReady,
+        _ => BlockError::IoError,
     }
 }
 
 impl VirtIOBlock {
-    #[allow(unused)]
-    pub fn new() -> Self {
-        unsafe {
-            Self(Mutex::new(
-                VirtIOBlk::new(&mut *(VIRTIO0 as *mut VirtIOHeader)).unwrap(),
-            ))
+    /// 没有virtio块设备时返回None，找到设备时订阅其中断
+    pub fn new() -> Option<Self> {
+        let blk = unsafe { VirtIOBlk::new(&mut *(VIRTIO0 as *mut VirtIOHeader)).ok()? };
+        let queue = Arc::new(BlkQueue {
+            inner: Mutex::new(BlkInner {
+                blk,
+                next_seq: 0,
+                inflight: BTreeMap::new(),
+                completed: BTreeSet::new(),
+            }),
+            waiters: WaitQueue::new(),
+        });
+        let handler = queue.clone();
+        register_irq(VIRTIO0_IRQ, "virtio-blk", move || handler.handle_irq());
+        Some(Self(queue))
+    }
+}
+
+impl BlkInner {
+    /// 一次最多同时提交的请求数，受virtqueue中描述符数量的限制
+    fn capacity(&self) -> usize {
+        (self.blk.virt_queue_size() as usize / DESC_PER_REQ).max(1)
+    }
+
+    /// 取出设备已经处理完的请求，返回是否有请求完成
+    fn reap(&mut self) -> bool {
+        let mut reaped = false;
+        while let Ok(token) = self.blk.pop_used() {
+            if let Some(seq) = self.inflight.remove(&token) {
+                self.completed.insert(seq);
+            }
+            reaped = true;
+        }
+        reaped
+    }
+}
+
+impl BlkQueue {
+    /// 提交count个请求并等待全部完成，任一请求失败则返回错误
+    /// submit(blk, i, resp)提交第i个请求并返回其token，virtqueue满时先等待已提交的请求完成
+    fn submit_and_wait(
+        &self,
+        count: usize,
+        mut submit: impl FnMut(&mut VirtIOBlk<'static>, usize, &mut BlkResp) -> Result<u16, VirtIOError>,
+    ) -> Result<(), BlockError> {
+        // 在自旋锁下睡眠会使持有同一把锁的任务死锁，只有调用者允许时才睡眠
+        let sleep = io_may_sleep();
+        let mut resps = vec![BlkResp::default(); count];
+        let mut seqs = Vec::with_capacity(count);
+        let mut submitted = 0;
+        let mut result = Ok(());
+        loop {
+            let mut inner = self.inner.lock();
+            let reaped = inner.reap();
+            // 即使中途提交失败，也要等待已提交的请求完成后才能释放缓冲区
+            while result.is_ok() && submitted < count && inner.inflight.len() < inner.capacity() {
+                match submit(&mut inner.blk, submitted, &mut resps[submitted]) {
+                    Ok(token) => {
+                        let seq = inner.next_seq;
+                        inner.next_seq += 1;
+                        inner.inflight.insert(token, seq);
+                        seqs.push(seq);
+                        submitted += 1;
+                    }
+                    Err(err) => result = Err(blk_error(err)),
+                }
+            }
+            let done = (result.is_err() || submitted == count)
+                && seqs.iter().all(|seq| inner.completed.contains(seq));
+            if done {
+                for seq in seqs.iter() {
+                    inner.completed.remove(seq);
+                }
+            }
+            drop(inner);
+            // 自己取出的请求可能属于其他任务
+            if reaped {
+                self.waiters.wake_all();
+            }
+            if done {
+                break;
+            }
+            // 睡眠时不能持有设备的锁，内核中不响应中断，不会错过唤醒
+            if sleep {
+                IO_SLEEPERS.fetch_add(1, Ordering::Relaxed);
+                self.waiters.wait(None);
+                IO_SLEEPERS.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+        result?;
+        if resps.iter().all(|resp| resp.status() == RespStatus::Ok) {
+            Ok(())
+        } else {
+            Err(BlockError::IoError)
+        }
+    }
+
+    /// 设备的中断处理函数，唤醒等待请求完成的任务，空闲循环中也会轮询
+    fn handle_irq(&self) {
+        let mut inner = self.inner.lock();
+        inner.blk.ack_interrupt();
+        let reaped = inner.reap();
+        drop(inner);
+        if reaped {
+            self.waiters.wake_all();
         }
     }
 }
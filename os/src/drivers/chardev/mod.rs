@@ -0,0 +1,268 @@
+//! 控制台串口驱动
+//!
+//! 收发都经过环形缓冲区：中断处理时把硬件FIFO中收到的数据移入接收缓冲区并唤醒读者，
+//! 把发送缓冲区中的数据写入硬件FIFO。初始化之前的输出和读取使用SBI。
+mod ns16550a;
+mod uarths;
+
+pub use ns16550a::Ns16550a;
+pub use uarths::UartHs;
+
+use super::register_irq;
+use crate::board::{CharDeviceImpl, UART_IRQ};
+use crate::config::{UART_RX_BUFFER_SIZE, UART_TX_BUFFER_SIZE};
+use crate::sbi::{console_getchar, console_putchar};
+use crate::task::{suspend_current_and_run_next, WaitQueue};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::*;
+use spin::Mutex;
+
+/// 串口控制器的寄存器操作
+pub trait UartHardware: Send {
+    /// 设置数据格式并打开接收中断
+    fn init(&mut self);
+    /// 从接收FIFO取出一个字节
+    fn read_byte(&mut self) -> Option<u8>;
+    /// 发送FIFO是否还能写入
+    fn write_ready(&self) -> bool;
+    fn write_byte(&mut self, ch: u8);
+    /// 发送FIFO中的数据是否都已发出
+    fn tx_idle(&self) -> bool;
+    /// 打开或关闭发送FIFO可写时的中断
+    fn set_tx_interrupt(&mut self, enable: bool);
+}
+
+/// 固定容量的字节环形缓冲区
+struct RingBuffer {
+    buf: Vec<u8>,
+    head: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    fn new(capacity: usize) -> Self {
+        Self {
+            buf: vec![0; capacity],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == self.buf.len()
+    }
+
+    /// 缓冲区已满时返回false
+    fn push(&mut self, ch: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        let tail = (self.head + self.len) % self.buf.len();
+        self.buf[tail] = ch;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let ch = self.buf[self.head];
+        self.head = (self.head + 1) % self.buf.len();
+        self.len -= 1;
+        Some(ch)
+    }
+}
+
+struct SerialInner<H: UartHardware> {
+    hw: H,
+    rx: RingBuffer,
+    tx: RingBuffer,
+}
+
+impl<H: UartHardware> SerialInner<H> {
+    /// 在硬件FIFO和缓冲区之间搬运数据，返回是否收到了新的数据
+    fn service(&mut self) -> bool {
+        let mut received = false;
+        while let Some(ch) = self.hw.read_byte() {
+            // 接收缓冲区满时丢弃新的输入
+            self.rx.push(ch);
+            received = true;
+        }
+        while !self.tx.is_empty() && self.hw.write_ready() {
+            let ch = self.tx.pop().unwrap();
+            self.hw.write_byte(ch);
+        }
+        // 还有数据没发完时等硬件FIFO可写的中断
+        self.hw.set_tx_interrupt(!self.tx.is_empty());
+        received
+    }
+}
+
+pub struct SerialPort<H: UartHardware> {
+    inner: Mutex<SerialInner<H>>,
+    /// 等待输入的任务
+    readers: Arc<WaitQueue>,
+}
+
+impl<H: UartHardware> SerialPort<H> {
+    pub fn new(mut hw: H) -> Self {
+        hw.init();
+        Self {
+            inner: Mutex::new(SerialInner {
+                hw,
+                rx: RingBuffer::new(UART_RX_BUFFER_SIZE),
+                tx: RingBuffer::new(UART_TX_BUFFER_SIZE),
+            }),
+            readers: Arc::new(WaitQueue::new()),
+        }
+    }
+
+    fn service(&self, inner: &mut SerialInner<H>) {
+        if inner.service() {
+            self.readers.wake_all();
+        }
+    }
+
+    /// 读取一个字节，没有输入时返回None
+    pub fn read(&self) -> Option<u8> {
+        let mut inner = self.inner.lock();
+        self.service(&mut inner);
+        inner.rx.pop()
+    }
+
+    /// 写入发送缓冲区，缓冲区满时等待硬件发送
+    /// 串口正被使用时返回false，避免内核在驱动中输出时重入死锁
+    pub fn try_write(&self, bytes: &[u8]) -> bool {
+        let mut inner = match self.inner.try_lock() {
+            Some(inner) => inner,
+            None => return false,
+        };
+        for &ch in bytes {
+            while !inner.tx.push(ch) {
+                self.service(&mut inner);
+            }
+        }
+        self.service(&mut inner);
+        true
+    }
+
+    /// 忙等到发送缓冲区和硬件FIFO中的数据都发出，用于panic和关机之前
+    /// 串口正被使用时(如在驱动中panic)直接返回
+    pub fn flush(&self) {
+        let mut inner = match self.inner.try_lock() {
+            Some(inner) => inner,
+            None => return,
+        };
+        while !inner.tx.is_empty() {
+            self.service(&mut inner);
+        }
+        while !inner.hw.tx_idle() {}
+    }
+
+    pub fn has_input(&self) -> bool {
+        let mut inner = self.inner.lock();
+        self.service(&mut inner);
+        !inner.rx.is_empty()
+    }
+
+    /// 阻塞当前任务直到收到输入，也可能被信号提前唤醒
+    pub fn wait_input(&self) {
+        if !self.has_input() {
+            self.readers.wait(None);
+        }
+    }
+
+    /// 是否有任务在等待输入
+    pub fn has_readers(&self) -> bool {
+        !self.readers.is_empty()
+    }
+
+    /// 串口中断的处理函数，空闲循环中也会轮询
+    pub fn handle_irq(&self) {
+        let mut inner = self.inner.lock();
+        self.service(&mut inner);
+    }
+}
+
+lazy_static! {
+    pub static ref UART: SerialPort<CharDeviceImpl> = SerialPort::new(CharDeviceImpl::new());
+}
+
+/// 串口驱动是否已经初始化
+static UART_READY: AtomicBool = AtomicBool::new(false);
+
+/// 初始化控制台串口并订阅其中断，需要在内核地址空间映射MMIO之后调用
+pub fn init() {
+    lazy_static::initialize(&UART);
+    register_irq(UART_IRQ, "uart", || UART.handle_irq());
+    UART_READY.store(true, Ordering::Release);
+}
+
+fn uart() -> Option<&'static SerialPort<CharDeviceImpl>> {
+    if UART_READY.load(Ordering::Acquire) {
+        Some(&UART)
+    } else {
+        None
+    }
+}
+
+/// 从控制台读取一个字节，没有输入时返回None
+pub fn console_getchar_nb() -> Option<u8> {
+    match uart() {
+        Some(uart) => uart.read(),
+        None => match console_getchar() {
+            0 => None,
+            c => Some(c as u8),
+        },
+    }
+}
+
+pub fn console_putbytes(bytes: &[u8]) {
+    if let Some(uart) = uart() {
+        if uart.try_write(bytes) {
+            return;
+        }
+    }
+    for &ch in bytes {
+        console_putchar(ch as usize);
+    }
+}
+
+/// 发出控制台串口中缓冲的所有输出，panic和关机之前调用，否则最后的输出会丢失
+pub fn console_flush() {
+    if let Some(uart) = uart() {
+        uart.flush();
+    }
+}
+
+/// 等待控制台输入，SBI只能轮询，让出CPU后返回
+pub fn console_wait_input() {
+    match uart() {
+        Some(uart) => uart.wait_input(),
+        None => suspend_current_and_run_next(),
+    }
+}
+
+/// 控制台收到输入时唤醒的等待队列，只能轮询SBI时为None
+pub fn console_input_queue() -> Option<Arc<WaitQueue>> {
+    uart().map(|uart| uart.readers.clone())
+}
+
+/// 轮询控制台串口，返回是否有任务在等待输入
+pub fn console_poll() -> bool {
+    match uart() {
+        Some(uart) => {
+            uart.handle_irq();
+            uart.has_readers()
+        }
+        None => false,
+    }
+}
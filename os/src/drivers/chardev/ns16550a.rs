@@ -0,0 +1,93 @@
+#![allow(unused)]
+use super::UartHardware;
+use core::ptr::{read_volatile, write_volatile};
+
+/// QEMU virt上UART0的MMIO基址
+const UART0: usize = 0x1000_0000;
+
+// 寄存器偏移，每个寄存器一个字节
+const RBR: usize = 0; // 接收缓冲，读
+const THR: usize = 0; // 发送保持，写
+const IER: usize = 1;
+const FCR: usize = 2;
+const LCR: usize = 3;
+const MCR: usize = 4;
+const LSR: usize = 5;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const IER_TX_EMPTY: u8 = 1 << 1;
+const FCR_ENABLE_FIFO: u8 = 1 << 0;
+const FCR_CLEAR_FIFO: u8 = 0b11 << 1;
+const LCR_8N1: u8 = 0b11;
+/// DTR、RTS以及OUT2，OUT2打开后中断才会送出
+const MCR_DTR_RTS_OUT2: u8 = 0b1011;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+/// 发送FIFO和移位寄存器都为空
+const LSR_TX_IDLE: u8 = 1 << 6;
+
+/// QEMU virt的ns16550a串口，波特率沿用固件的设置
+pub struct Ns16550a {
+    base: usize,
+    ier: u8,
+}
+
+impl Ns16550a {
+    pub fn new() -> Self {
+        Self {
+            base: UART0,
+            ier: 0,
+        }
+    }
+
+    fn read_reg(&self, offset: usize) -> u8 {
+        unsafe { read_volatile((self.base + offset) as *const u8) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u8) {
+        unsafe { write_volatile((self.base + offset) as *mut u8, value) }
+    }
+}
+
+impl UartHardware for Ns16550a {
+    fn init(&mut self) {
+        self.write_reg(IER, 0);
+        self.write_reg(LCR, LCR_8N1);
+        self.write_reg(FCR, FCR_ENABLE_FIFO | FCR_CLEAR_FIFO);
+        self.write_reg(MCR, MCR_DTR_RTS_OUT2);
+        self.ier = IER_RX_AVAILABLE;
+        self.write_reg(IER, self.ier);
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        if self.read_reg(LSR) & LSR_DATA_READY != 0 {
+            Some(self.read_reg(RBR))
+        } else {
+            None
+        }
+    }
+
+    fn write_ready(&self) -> bool {
+        self.read_reg(LSR) & LSR_THR_EMPTY != 0
+    }
+
+    fn write_byte(&mut self, ch: u8) {
+        self.write_reg(THR, ch);
+    }
+
+    fn tx_idle(&self) -> bool {
+        self.read_reg(LSR) & LSR_TX_IDLE != 0
+    }
+
+    fn set_tx_interrupt(&mut self, enable: bool) {
+        let ier = if enable {
+            self.ier | IER_TX_EMPTY
+        } else {
+            self.ier & !IER_TX_EMPTY
+        };
+        if ier != self.ier {
+            self.ier = ier;
+            self.write_reg(IER, ier);
+        }
+    }
+}
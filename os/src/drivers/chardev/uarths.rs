@@ -0,0 +1,97 @@
+#![allow(unused)]
+use super::UartHardware;
+use core::ptr::{read_volatile, write_volatile};
+
+/// K210高速串口UARTHS的MMIO基址
+const UARTHS: usize = 0x3800_0000;
+
+// 寄存器偏移，每个寄存器32位
+const TXDATA: usize = 0x00;
+const RXDATA: usize = 0x04;
+const TXCTRL: usize = 0x08;
+const RXCTRL: usize = 0x0c;
+const IE: usize = 0x10;
+const IP: usize = 0x14;
+
+/// TXDATA中表示发送FIFO已满的位
+const TXDATA_FULL: u32 = 1 << 31;
+/// RXDATA中表示接收FIFO为空的位
+const RXDATA_EMPTY: u32 = 1 << 31;
+const TXCTRL_TXEN: u32 = 1 << 0;
+const RXCTRL_RXEN: u32 = 1 << 0;
+/// 水位字段的位置，发送FIFO少于txcnt项、接收FIFO多于rxcnt项时产生中断
+const CNT_SHIFT: u32 = 16;
+const CNT_MASK: u32 = 0b111 << CNT_SHIFT;
+const IE_TXWM: u32 = 1 << 0;
+const IE_RXWM: u32 = 1 << 1;
+/// 发送FIFO中的数据少于txcnt项，txcnt为1时即发送FIFO为空
+const IP_TXWM: u32 = 1 << 0;
+
+/// K210的UARTHS，波特率沿用固件的设置
+pub struct UartHs {
+    base: usize,
+    ie: u32,
+}
+
+impl UartHs {
+    pub fn new() -> Self {
+        Self {
+            base: UARTHS,
+            ie: 0,
+        }
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+}
+
+impl UartHardware for UartHs {
+    fn init(&mut self) {
+        // 发送FIFO为空时产生发送中断，收到任意数据时产生接收中断
+        let txctrl = self.read_reg(TXCTRL) & !CNT_MASK;
+        self.write_reg(TXCTRL, txctrl | TXCTRL_TXEN | (1 << CNT_SHIFT));
+        let rxctrl = self.read_reg(RXCTRL) & !CNT_MASK;
+        self.write_reg(RXCTRL, rxctrl | RXCTRL_RXEN);
+        self.ie = IE_RXWM;
+        self.write_reg(IE, self.ie);
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        // 读RXDATA会弹出FIFO中的一项，只能读一次
+        let rxdata = self.read_reg(RXDATA);
+        if rxdata & RXDATA_EMPTY != 0 {
+            None
+        } else {
+            Some(rxdata as u8)
+        }
+    }
+
+    fn write_ready(&self) -> bool {
+        self.read_reg(TXDATA) & TXDATA_FULL == 0
+    }
+
+    fn write_byte(&mut self, ch: u8) {
+        self.write_reg(TXDATA, ch as u32);
+    }
+
+    fn tx_idle(&self) -> bool {
+        self.read_reg(IP) & IP_TXWM != 0
+    }
+
+    fn set_tx_interrupt(&mut self, enable: bool) {
+        let ie = if enable {
+            self.ie | IE_TXWM
+        } else {
+            self.ie & !IE_TXWM
+        };
+        if ie != self.ie {
+            self.ie = ie;
+            self.write_reg(IE, ie);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! 外部中断的分发
+//!
+//! 驱动用register_irq订阅中断号，PLIC送来的中断按中断号调用对应的处理函数。
+use super::plic::Plic;
+use crate::board::{PLIC_BASE, PLIC_CONTEXT};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+/// 驱动订阅的中断默认使用的优先级，阈值为0，任何非0优先级都会被送出
+const DEFAULT_PRIORITY: u32 = 1;
+
+pub static PLIC: Plic = Plic::new(PLIC_BASE);
+
+struct IrqEntry {
+    name: &'static str,
+    handler: Arc<dyn Fn() + Send + Sync>,
+    count: usize,
+}
+
+lazy_static! {
+    static ref IRQ_TABLE: Mutex<BTreeMap<usize, IrqEntry>> = Mutex::new(BTreeMap::new());
+}
+
+/// 初始化PLIC，阈值设为0，中断源由驱动订阅时逐个打开
+pub fn init() {
+    PLIC.set_threshold(PLIC_CONTEXT, 0);
+}
+
+/// 订阅中断号irq并在PLIC中打开该中断源，同一中断号只能有一个处理函数
+pub fn register_irq(irq: usize, name: &'static str, handler: impl Fn() + Send + Sync + 'static) {
+    let old = IRQ_TABLE.lock().insert(
+        irq,
+        IrqEntry {
+            name,
+            handler: Arc::new(handler),
+            count: 0,
+        },
+    );
+    assert!(old.is_none(), "irq {} is already registered", irq);
+    PLIC.set_priority(irq, DEFAULT_PRIORITY);
+    PLIC.enable(PLIC_CONTEXT, irq);
+}
+
+/// 处理所有待处理的外部中断，返回处理的中断数
+/// 在外部中断到来时调用，也在空闲循环中轮询
+pub fn handle_irqs() -> usize {
+    let mut handled = 0;
+    while let Some(irq) = PLIC.claim(PLIC_CONTEXT) {
+        // 处理函数可能会注册中断或读取统计，调用时不能持有表的锁
+        let handler = IRQ_TABLE.lock().get_mut(&irq).map(|entry| {
+            entry.count += 1;
+            entry.handler.clone()
+        });
+        match handler {
+            Some(handler) => handler(),
+            None => {
+                println!("[kernel] unexpected irq {}", irq);
+            }
+        }
+        PLIC.complete(PLIC_CONTEXT, irq);
+        handled += 1;
+    }
+    handled
+}
+
+/// 各外部中断的(中断号, 发生次数, 名称)
+pub fn irq_counts() -> Vec<(usize, usize, &'static str)> {
+    IRQ_TABLE
+        .lock()
+        .iter()
+        .map(|(irq, entry)| (*irq, entry.count, entry.name))
+        .collect()
+}
@@ -1,3 +1,20 @@
 pub mod block;
+pub mod chardev;
+pub mod irq;
+pub mod plic;
 
-pub use block::BLOCK_DEVICE;
+pub use block::{
+    block_device_by_name, block_device_numbers, block_io_waiting, root_device_name,
+    with_sleepable_io, LoopDevice, ROOT_BLOCK_DEVICE,
+};
+pub use chardev::{
+    console_flush, console_getchar_nb, console_input_queue, console_poll, console_putbytes,
+    console_wait_input,
+};
+pub use irq::{irq_counts, register_irq};
+
+/// 初始化内核直接使用的设备，需要在启用内核地址空间之后调用
+pub fn init() {
+    crate::board::device_init();
+    chardev::init();
+}
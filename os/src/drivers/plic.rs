@@ -0,0 +1,76 @@
+//! RISC-V平台级中断控制器(PLIC)
+use core::ptr::{read_volatile, write_volatile};
+
+/// 中断源优先级寄存器的偏移，每个中断源4字节
+const PRIORITY: usize = 0x0000;
+/// 各上下文中断使能位的偏移，每个上下文0x80字节
+const ENABLE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+/// 各上下文阈值和claim/complete寄存器的偏移，每个上下文0x1000字节
+const CONTEXT: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const THRESHOLD: usize = 0x0;
+const CLAIM_COMPLETE: usize = 0x4;
+
+/// 中断的目标特权级
+#[derive(Copy, Clone)]
+pub enum TargetPriority {
+    /// 只有k210使用，其S态外部中断经由M态上下文送出
+    #[allow(dead_code)]
+    Machine = 0,
+    Supervisor = 1,
+}
+
+pub struct Plic {
+    base: usize,
+}
+
+impl Plic {
+    pub const fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    /// 每个hart有M态和S态两个上下文
+    pub const fn context(hart_id: usize, target: TargetPriority) -> usize {
+        hart_id * 2 + target as usize
+    }
+
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as *mut u32
+    }
+
+    /// 优先级为0的中断源不会被送出
+    pub fn set_priority(&self, irq: usize, priority: u32) {
+        unsafe { write_volatile(self.reg(PRIORITY + irq * 4), priority) }
+    }
+
+    fn enable_reg(&self, context: usize, irq: usize) -> *mut u32 {
+        self.reg(ENABLE + context * ENABLE_STRIDE + irq / 32 * 4)
+    }
+
+    pub fn enable(&self, context: usize, irq: usize) {
+        let reg = self.enable_reg(context, irq);
+        unsafe { write_volatile(reg, read_volatile(reg) | 1 << (irq % 32)) }
+    }
+
+    /// 只有优先级高于阈值的中断才会送到该上下文
+    pub fn set_threshold(&self, context: usize, threshold: u32) {
+        let reg = self.reg(CONTEXT + context * CONTEXT_STRIDE + THRESHOLD);
+        unsafe { write_volatile(reg, threshold) }
+    }
+
+    /// 取得一个待处理的中断源并标记为处理中，没有时返回None
+    pub fn claim(&self, context: usize) -> Option<usize> {
+        let reg = self.reg(CONTEXT + context * CONTEXT_STRIDE + CLAIM_COMPLETE);
+        match unsafe { read_volatile(reg) } {
+            0 => None,
+            irq => Some(irq as usize),
+        }
+    }
+
+    /// 通知中断源处理完毕，之后才能再次送出该中断
+    pub fn complete(&self, context: usize, irq: usize) {
+        let reg = self.reg(CONTEXT + context * CONTEXT_STRIDE + CLAIM_COMPLETE);
+        unsafe { write_volatile(reg, irq as u32) }
+    }
+}
@@ -0,0 +1,303 @@
+//! 目录项缓存
+//!
+//! 目录项组成一棵与路径对应的树，子目录项以弱引用挂在父目录项上，
+//! 最近使用的目录项由DENTRY_LRU持有，超出容量后被释放。
+use super::mount::has_mounts_under;
+use super::vfs::{fs_id, same_fs, FileSystem, Inode, InodeType, RENAME_EXCHANGE};
+use crate::config::DENTRY_CACHE_CAPACITY;
+use crate::syscall::errno::{EBUSY, EINVAL, ELOOP, ENOTDIR, EXDEV};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+/// 解析路径时最多跟随的符号链接数
+pub const SYMLOOP_MAX: usize = 40;
+
+pub struct Dentry {
+    name: String,
+    inode: Arc<dyn Inode>,
+    /// 根目录项没有父目录项
+    parent: Option<Arc<Dentry>>,
+    children: Mutex<BTreeMap<String, Weak<Dentry>>>,
+    /// 挂载在此目录上的文件系统的根目录项
+    mounted: Mutex<Option<Arc<Dentry>>>,
+}
+
+lazy_static! {
+    /// 最近使用的目录项，保证它们留在缓存中
+    static ref DENTRY_LRU: Mutex<VecDeque<Arc<Dentry>>> = Mutex::new(VecDeque::new());
+}
+
+/// 将目录项移到LRU队尾
+fn touch(dentry: &Arc<Dentry>) {
+    let mut lru = DENTRY_LRU.lock();
+    if let Some(pos) = lru.iter().position(|d| Arc::ptr_eq(d, dentry)) {
+        lru.remove(pos);
+    }
+    lru.push_back(dentry.clone());
+    let mut evicted = Vec::new();
+    while lru.len() > DENTRY_CACHE_CAPACITY {
+        evicted.push(lru.pop_front());
+    }
+    // 释放目录项可能释放inode，在锁外进行
+    drop(lru);
+    drop(evicted);
+}
+
+/// 从LRU中移除属于fs的目录项，卸载文件系统时调用
+pub fn purge_dentries(fs: &Arc<dyn FileSystem>) {
+    let id = fs_id(fs);
+    let mut lru = DENTRY_LRU.lock();
+    let (purged, kept): (Vec<_>, Vec<_>) = lru.drain(..).partition(|d| fs_id(&d.inode.fs()) == id);
+    lru.extend(kept);
+    drop(lru);
+    drop(purged);
+}
+
+impl Dentry {
+    pub fn new_root(inode: Arc<dyn Inode>) -> Arc<Self> {
+        Arc::new(Self {
+            name: String::from("/"),
+            inode,
+            parent: None,
+            children: Mutex::new(BTreeMap::new()),
+            mounted: Mutex::new(None),
+        })
+    }
+
+    /// 挂载在mountpoint上的文件系统的根目录项，它的..与mountpoint的..相同
+    pub fn new_mount_root(inode: Arc<dyn Inode>, mountpoint: &Arc<Dentry>) -> Arc<Self> {
+        Arc::new(Self {
+            name: mountpoint.name.clone(),
+            inode,
+            parent: mountpoint.parent.clone(),
+            children: Mutex::new(BTreeMap::new()),
+            mounted: Mutex::new(None),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    pub fn inode(&self) -> Arc<dyn Inode> {
+        self.inode.clone()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.inode.kind() == InodeType::Directory
+    }
+
+    /// 父目录项，根目录的父目录是它自己
+    pub fn parent(self: &Arc<Self>) -> Arc<Dentry> {
+        match &self.parent {
+            Some(parent) => parent.clone(),
+            None => self.clone(),
+        }
+    }
+
+    /// 目录项对应的绝对路径
+    pub fn path(&self) -> String {
+        let mut names = Vec::new();
+        let mut current = self;
+        while let Some(parent) = &current.parent {
+            names.push(current.name.as_str());
+            current = parent;
+        }
+        let mut path = String::new();
+        for name in names.iter().rev() {
+            path.push('/');
+            path.push_str(name);
+        }
+        if path.is_empty() {
+            path.push('/');
+        }
+        path
+    }
+
+    /// self是否为other本身或者other的祖先
+    pub fn is_ancestor_of(self: &Arc<Self>, other: &Arc<Dentry>) -> bool {
+        let mut current = other.clone();
+        loop {
+            if Arc::ptr_eq(self, &current) {
+                return true;
+            }
+            match &current.parent {
+                Some(parent) => current = parent.clone(),
+                None => return false,
+            }
+        }
+    }
+
+    fn add_child(self: &Arc<Self>, name: &str, inode: Arc<dyn Inode>) -> Arc<Dentry> {
+        let child = Arc::new(Dentry {
+            name: String::from(name),
+            inode,
+            parent: Some(self.clone()),
+            children: Mutex::new(BTreeMap::new()),
+            mounted: Mutex::new(None),
+        });
+        self.children
+            .lock()
+            .insert(String::from(name), Arc::downgrade(&child));
+        touch(&child);
+        child
+    }
+
+    /// 从缓存中删除名为name的子目录项
+    fn forget(&self, name: &str) {
+        self.children.lock().remove(name);
+    }
+
+    /// 在此目录上挂载或卸载文件系统
+    pub fn set_mounted(&self, root: Option<Arc<Dentry>>) {
+        *self.mounted.lock() = root;
+    }
+
+    /// 如果此目录是挂载点，返回最上层挂载的文件系统的根目录项
+    fn follow_mount(self: Arc<Self>) -> Arc<Dentry> {
+        let mut current = self;
+        loop {
+            let mounted = current.mounted.lock().clone();
+            match mounted {
+                Some(root) => current = root,
+                None => return current,
+            }
+        }
+    }
+
+    /// 查找名为name的子目录项，不穿过挂载点
+    fn lookup_child(self: &Arc<Self>, name: &str) -> Result<Arc<Dentry>, isize> {
+        if !self.is_dir() {
+            return Err(-ENOTDIR);
+        }
+        let cached = self.children.lock().get(name).and_then(|c| c.upgrade());
+        if let Some(child) = cached {
+            touch(&child);
+            return Ok(child);
+        }
+        let inode = self.inode.lookup(name)?;
+        Ok(self.add_child(name, inode))
+    }
+
+    /// 名为name的子目录项或其下有挂载的文件系统时返回EBUSY
+    fn check_busy(&self, name: &str) -> Result<(), isize> {
+        let cached = self.children.lock().get(name).and_then(|c| c.upgrade());
+        match cached {
+            Some(child) if has_mounts_under(&child) => Err(-EBUSY),
+            _ => Ok(()),
+        }
+    }
+
+    /// 查找名为name的子目录项，不跟随符号链接，遇到挂载点时进入挂载的文件系统
+    pub fn lookup(self: &Arc<Self>, name: &str) -> Result<Arc<Dentry>, isize> {
+        match name {
+            "" | "." => Ok(self.clone()),
+            ".." => Ok(self.parent()),
+            _ => Ok(self.lookup_child(name)?.follow_mount()),
+        }
+    }
+
+    pub fn create(self: &Arc<Self>, name: &str, kind: InodeType) -> Result<Arc<Dentry>, isize> {
+        let inode = self.inode.create(name, kind)?;
+        Ok(self.add_child(name, inode))
+    }
+
+    pub fn symlink(self: &Arc<Self>, name: &str, target: &str) -> Result<Arc<Dentry>, isize> {
+        let inode = self.inode.symlink(name, target)?;
+        Ok(self.add_child(name, inode))
+    }
+
+    /// 在此目录中新建指向target的硬链接
+    pub fn link(self: &Arc<Self>, name: &str, target: &Arc<Dentry>) -> Result<Arc<Dentry>, isize> {
+        if !same_fs(&self.inode, &target.inode) {
+            return Err(-EXDEV);
+        }
+        self.inode.link(name, &target.inode)?;
+        Ok(self.add_child(name, target.inode()))
+    }
+
+    pub fn unlink(&self, name: &str) -> Result<(), isize> {
+        self.check_busy(name)?;
+        self.forget(name);
+        self.inode.unlink(name)
+    }
+
+    pub fn rmdir(&self, name: &str) -> Result<(), isize> {
+        self.check_busy(name)?;
+        self.forget(name);
+        self.inode.rmdir(name)
+    }
+
+    /// 将old_name移动为new_dir下的new_name，不能把目录移动到它自己的子目录中
+    pub fn rename(
+        self: &Arc<Self>,
+        old_name: &str,
+        new_dir: &Arc<Dentry>,
+        new_name: &str,
+        flags: u32,
+    ) -> Result<(), isize> {
+        if !same_fs(&self.inode, &new_dir.inode) {
+            return Err(-EXDEV);
+        }
+        let old = self.lookup_child(old_name)?;
+        if old.is_ancestor_of(new_dir) {
+            return Err(-EINVAL);
+        }
+        if flags & RENAME_EXCHANGE != 0 {
+            let target = new_dir.lookup_child(new_name)?;
+            if target.is_ancestor_of(self) {
+                return Err(-EINVAL);
+            }
+        }
+        self.check_busy(old_name)?;
+        new_dir.check_busy(new_name)?;
+        self.forget(old_name);
+        new_dir.forget(new_name);
+        self.inode.rename(old_name, &new_dir.inode, new_name, flags)
+    }
+}
+
+/// 从start开始解析路径，绝对路径从root开始
+/// follow_last为false时不跟随路径最后一项的符号链接
+pub fn lookup_path(
+    root: &Arc<Dentry>,
+    start: &Arc<Dentry>,
+    path: &str,
+    follow_last: bool,
+) -> Result<Arc<Dentry>, isize> {
+    // 待解析的路径项，逆序存放
+    let mut pending: Vec<String> = path.split('/').rev().map(String::from).collect();
+    let mut current = if path.starts_with('/') {
+        root.clone()
+    } else {
+        start.clone()
+    };
+    let mut links = 0;
+    while let Some(name) = pending.pop() {
+        if name == "." || name == "" {
+            continue; // 跳过，表示仍然为当前目录
+        }
+        if !current.is_dir() {
+            return Err(-ENOTDIR); // 中间路径不是目录
+        }
+        let next = current.lookup(name.as_str())?;
+        if next.inode.kind() == InodeType::SymLink && (follow_last || !pending.is_empty()) {
+            links += 1;
+            if links > SYMLOOP_MAX {
+                return Err(-ELOOP);
+            }
+            let target = next.inode.read_link()?;
+            if target.starts_with('/') {
+                current = root.clone();
+            }
+            pending.extend(target.split('/').rev().map(String::from));
+            continue;
+        }
+        current = next;
+    }
+    Ok(current)
+}
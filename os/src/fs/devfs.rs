@@ -0,0 +1,378 @@
+//! 设备文件系统，挂载在/dev上
+//!
+//! 字符设备是固定的一组，块设备在每次查找时按当前的分区表列出。
+use super::mount::block_device_mounts;
+use super::tty::TTY;
+use super::vfs::{makedev, DirItem, FileSystem, Inode, InodeStat, InodeType};
+use super::PollEvents;
+use crate::drivers::{
+    block_device_by_name, block_device_numbers, console_input_queue, with_sleepable_io,
+};
+use crate::random::fill_random;
+use crate::syscall::errno::*;
+use crate::task::WaitQueue;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use core::any::Any;
+use fat32::BlockDevice;
+use spin::Mutex;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CharDevice {
+    Null,
+    Zero,
+    Full,
+    Random,
+    Urandom,
+    Tty,
+    Console,
+}
+
+/// (名称, 设备, 主设备号, 次设备号, 权限)，设备号与Linux相同
+const CHAR_DEVICES: [(&str, CharDevice, u32, u32, u32); 7] = [
+    ("null", CharDevice::Null, 1, 3, 0o666),
+    ("zero", CharDevice::Zero, 1, 5, 0o666),
+    ("full", CharDevice::Full, 1, 7, 0o666),
+    ("random", CharDevice::Random, 1, 8, 0o666),
+    ("urandom", CharDevice::Urandom, 1, 9, 0o666),
+    ("tty", CharDevice::Tty, 5, 0, 0o666),
+    ("console", CharDevice::Console, 5, 1, 0o600),
+];
+
+/// readdir中.和..之后的第一个offset
+const FIRST_ENTRY: usize = 2;
+
+enum DevNode {
+    Root,
+    /// CHAR_DEVICES中的下标
+    Char(usize),
+    Block {
+        name: String,
+        major: u32,
+        minor: u32,
+        device: Arc<dyn BlockDevice>,
+    },
+}
+
+pub struct DevFs {
+    this: Mutex<Weak<DevFs>>,
+}
+
+impl DevFs {
+    pub fn new() -> Arc<Self> {
+        let fs = Arc::new(Self {
+            this: Mutex::new(Weak::new()),
+        });
+        *fs.this.lock() = Arc::downgrade(&fs);
+        fs
+    }
+
+    fn node(&self, node: DevNode) -> Arc<dyn Inode> {
+        Arc::new(DevInode {
+            fs: self.this.lock().upgrade().unwrap(),
+            node,
+        })
+    }
+}
+
+impl FileSystem for DevFs {
+    fn fs_type(&self) -> &'static str {
+        "devtmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.node(DevNode::Root)
+    }
+
+    fn sync(&self) -> Result<(), isize> {
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct DevInode {
+    fs: Arc<DevFs>,
+    node: DevNode,
+}
+
+/// 块设备的inode号，与字符设备的不重叠
+fn block_ino(minor: u32) -> u64 {
+    0x100 + minor as u64
+}
+
+/// 以块为单位读写块设备上offset处的数据，超出设备末尾的部分被截断
+fn block_rw(
+    device: &Arc<dyn BlockDevice>,
+    offset: usize,
+    len: usize,
+    mut op: impl FnMut(&mut [u8], usize, usize) -> bool,
+) -> Result<usize, isize> {
+    let block_size = device.block_size();
+    let size = device.num_blocks() * block_size;
+    if offset >= size {
+        return Ok(0);
+    }
+    let end = size.min(offset + len);
+    let mut block = vec![0u8; block_size];
+    let mut pos = offset;
+    // 这里不持有任何自旋锁，可以睡眠等待磁盘
+    with_sleepable_io(|| {
+        while pos < end {
+            let block_id = pos / block_size;
+            let block_off = pos % block_size;
+            let n = (block_size - block_off).min(end - pos);
+            device.read_block(block_id, &mut block).map_err(|_| -EIO)?;
+            // op返回true表示修改了块的内容，需要写回
+            if op(&mut block, block_off, pos - offset) {
+                device.write_block(block_id, &block).map_err(|_| -EIO)?;
+            }
+            pos += n;
+        }
+        Ok(end - offset)
+    })
+}
+
+impl DevInode {
+    fn is_tty(&self) -> bool {
+        match self.node {
+            DevNode::Char(index) => {
+                matches!(CHAR_DEVICES[index].1, CharDevice::Tty | CharDevice::Console)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Inode for DevInode {
+    fn fs(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    fn kind(&self) -> InodeType {
+        match self.node {
+            DevNode::Root => InodeType::Directory,
+            DevNode::Char(_) => InodeType::CharDevice,
+            DevNode::Block { .. } => InodeType::BlockDevice,
+        }
+    }
+
+    fn stat(&self) -> Result<InodeStat, isize> {
+        let (ino, mode, size, rdev) = match &self.node {
+            DevNode::Root => (1, 0o755, 0, 0),
+            DevNode::Char(index) => {
+                let (_, _, major, minor, mode) = CHAR_DEVICES[*index];
+                (2 + *index as u64, mode, 0, makedev(major, minor))
+            }
+            DevNode::Block {
+                major,
+                minor,
+                device,
+                ..
+            } => {
+                let size = (device.num_blocks() * device.block_size()) as u64;
+                (block_ino(*minor), 0o660, size, makedev(*major, *minor))
+            }
+        };
+        Ok(InodeStat {
+            ino,
+            kind: self.kind(),
+            mode,
+            size,
+            blocks: 0,
+            nlink: if self.kind() == InodeType::Directory {
+                2
+            } else {
+                1
+            },
+            rdev,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, isize> {
+        if !matches!(self.node, DevNode::Root) {
+            return Err(-ENOTDIR);
+        }
+        if let Some(index) = CHAR_DEVICES.iter().position(|(n, ..)| *n == name) {
+            return Ok(self.fs.node(DevNode::Char(index)));
+        }
+        let (_, major, minor) = block_device_numbers()
+            .into_iter()
+            .find(|(n, ..)| n == name)
+            .ok_or(-ENOENT)?;
+        let device = block_device_by_name(name).ok_or(-ENOENT)?;
+        Ok(self.fs.node(DevNode::Block {
+            name: String::from(name),
+            major,
+            minor,
+            device,
+        }))
+    }
+
+    fn create(&self, _name: &str, _kind: InodeType) -> Result<Arc<dyn Inode>, isize> {
+        Err(-EPERM)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<(), isize> {
+        Err(-EPERM)
+    }
+
+    fn rmdir(&self, _name: &str) -> Result<(), isize> {
+        Err(-EPERM)
+    }
+
+    fn rename(
+        &self,
+        _old_name: &str,
+        _new_dir: &Arc<dyn Inode>,
+        _new_name: &str,
+        _flags: u32,
+    ) -> Result<(), isize> {
+        Err(-EPERM)
+    }
+
+    fn readdir(&self, offset: usize) -> Result<Option<(DirItem, usize)>, isize> {
+        if !matches!(self.node, DevNode::Root) {
+            return Err(-ENOTDIR);
+        }
+        let item = |name: &str, ino: u64, kind: InodeType| DirItem {
+            name: String::from(name),
+            ino,
+            kind,
+        };
+        let entry = match offset {
+            0 => item(".", 1, InodeType::Directory),
+            1 => item("..", 1, InodeType::Directory),
+            _ => {
+                let index = offset - FIRST_ENTRY;
+                match CHAR_DEVICES.get(index) {
+                    Some((name, ..)) => item(name, 2 + index as u64, InodeType::CharDevice),
+                    None => match block_device_numbers().get(index - CHAR_DEVICES.len()) {
+                        Some((name, _, minor)) => {
+                            item(name, block_ino(*minor), InodeType::BlockDevice)
+                        }
+                        None => return Ok(None),
+                    },
+                }
+            }
+        };
+        Ok(Some((entry, offset + 1)))
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        match &self.node {
+            DevNode::Root => Err(-EISDIR),
+            DevNode::Char(index) => match CHAR_DEVICES[*index].1 {
+                CharDevice::Null => Ok(0),
+                CharDevice::Zero | CharDevice::Full => {
+                    buf.fill(0);
+                    Ok(buf.len())
+                }
+                CharDevice::Random | CharDevice::Urandom => {
+                    fill_random(buf);
+                    Ok(buf.len())
+                }
+                CharDevice::Tty | CharDevice::Console => TTY.read(buf),
+            },
+            DevNode::Block { name, device, .. } => {
+                // 已挂载的文件系统的脏块还在其缓存中，先写回再读设备
+                for fs in block_device_mounts(name) {
+                    fs.sync()?;
+                }
+                block_rw(device, offset, buf.len(), |block, block_off, buf_off| {
+                    let n = (block.len() - block_off).min(buf.len() - buf_off);
+                    buf[buf_off..buf_off + n].copy_from_slice(&block[block_off..block_off + n]);
+                    false
+                })
+            }
+        }
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, isize> {
+        match &self.node {
+            DevNode::Root => Err(-EISDIR),
+            DevNode::Char(index) => match CHAR_DEVICES[*index].1 {
+                CharDevice::Full => Err(-ENOSPC),
+                CharDevice::Tty | CharDevice::Console => Ok(TTY.write(buf)),
+                // 写入随机数设备的数据只丢弃，不作为熵
+                _ => Ok(buf.len()),
+            },
+            DevNode::Block { name, device, .. } => {
+                // 绕过已挂载文件系统的缓存直接写设备会被写回覆盖或破坏其元数据
+                if !block_device_mounts(name).is_empty() {
+                    return Err(-EBUSY);
+                }
+                let written = block_rw(device, offset, buf.len(), |block, block_off, buf_off| {
+                    let n = (block.len() - block_off).min(buf.len() - buf_off);
+                    block[block_off..block_off + n].copy_from_slice(&buf[buf_off..buf_off + n]);
+                    true
+                })?;
+                if written == 0 && !buf.is_empty() {
+                    return Err(-ENOSPC);
+                }
+                Ok(written)
+            }
+        }
+    }
+
+    fn truncate(&self, _size: usize) -> Result<(), isize> {
+        match self.node {
+            DevNode::Root => Err(-EISDIR),
+            _ => Ok(()), // 设备文件忽略O_TRUNC
+        }
+    }
+
+    fn sync(&self, _data_only: bool) -> Result<(), isize> {
+        match &self.node {
+            DevNode::Block { device, .. } => device.flush().map_err(|_| -EIO),
+            _ => Ok(()),
+        }
+    }
+
+    fn seek_data(&self, offset: usize, hole: bool) -> Result<usize, isize> {
+        match &self.node {
+            DevNode::Block { device, .. } => {
+                let size = device.num_blocks() * device.block_size();
+                if offset >= size {
+                    return Err(-ENXIO);
+                }
+                Ok(if hole { size } else { offset })
+            }
+            _ => Err(-ESPIPE),
+        }
+    }
+
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        if self.is_tty() && !TTY.readable() {
+            events - PollEvents::POLLIN
+        } else {
+            events
+        }
+    }
+
+    fn wait_queue(&self) -> Option<Arc<WaitQueue>> {
+        if self.is_tty() {
+            console_input_queue()
+        } else {
+            None
+        }
+    }
+
+    fn ioctl(&self, cmd: usize, arg: usize) -> Result<isize, isize> {
+        if self.is_tty() {
+            TTY.ioctl(cmd, arg)
+        } else {
+            Err(-ENOTTY)
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -5,8 +5,12 @@ use core::slice::{from_raw_parts, from_raw_parts_mut};
 pub const NAME_LIMIE: usize = 128;
 
 pub const DT_UNKNOWN: u8 = 0;
+pub const DT_FIFO: u8 = 1;
+pub const DT_CHR: u8 = 2;
+pub const DT_BLK: u8 = 6;
 pub const DT_DIR: u8 = 4;
 pub const DT_REG: u8 = 4;
+pub const DT_LNK: u8 = 10;
 
 #[derive(Debug)]
 #[repr(C)]
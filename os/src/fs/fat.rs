@@ -0,0 +1,492 @@
+//! FAT32在VFS中的实现
+use super::vfs::{
+    DirItem, FileSystem, Inode, InodeStat, InodeType, RENAME_EXCHANGE, RENAME_NOREPLACE,
+};
+use crate::config::{BLOCK_CACHE_CAPACITY, FAT_FREE_BITMAP, FAT_REPAIR_ON_MOUNT};
+use crate::drivers::with_sleepable_io;
+use crate::syscall::errno::*;
+use crate::task::SleepLock;
+use crate::timer::get_wall_time;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::any::Any;
+use fat32::{
+    fexchange, fmove, BlockDevice, BlockError, CacheStats, FAT32Manager, MountError, VFile,
+    ATTRIBUTE_ARCHIVE, ATTRIBUTE_DIRECTORY, ATTRIBUTE_SYMLINK, DIRENT_SZ,
+};
+use spin::{Mutex, RwLock};
+
+/// 磁盘I/O错误统一报告为EIO
+fn io_errno(_: BlockError) -> isize {
+    -EIO
+}
+
+/// 打开文件的引用计数
+#[derive(Default)]
+struct OpenCount {
+    count: usize,
+    /// 已经被unlink，最后一个打开者关闭时回收簇
+    unlinked: bool,
+}
+
+/// VFile对应的inode类型，检查符号链接需要读取文件内容
+fn inode_type(vfile: &VFile) -> InodeType {
+    if vfile.is_dir() {
+        InodeType::Directory
+    } else if vfile.is_symlink() {
+        InodeType::SymLink
+    } else {
+        InodeType::File
+    }
+}
+
+/// VFile在目录中的位置，即短目录项的<sector, offset>
+fn dirent_key(vfile: &VFile) -> (usize, usize) {
+    (vfile.short_sector, vfile.short_offset)
+}
+
+pub struct FatFileSystem {
+    manager: Arc<RwLock<FAT32Manager>>,
+    /// FAT32的缓存在自旋锁下读写设备，访问卷的任务先取得此锁，等待者睡眠而不是自旋
+    lock: SleepLock,
+    /// 仍在使用的inode，以短目录项位置为键，保证同一个文件只有一个inode
+    inodes: Mutex<BTreeMap<(usize, usize), Weak<FatInode>>>,
+    this: Mutex<Weak<FatFileSystem>>,
+}
+
+impl FatFileSystem {
+    /// 挂载设备上的FAT32文件系统
+    pub fn mount(device: Arc<dyn BlockDevice>) -> Result<Arc<Self>, MountError> {
+        let manager = FAT32Manager::open(device, BLOCK_CACHE_CAPACITY)?;
+        let manager_reader = manager.read();
+        match manager_reader.check_fat_mirrors(FAT_REPAIR_ON_MOUNT) {
+            Ok(0) => {}
+            Ok(diverged) if FAT_REPAIR_ON_MOUNT => {
+                println!(
+                    "[fs]: repaired {} FAT sectors from the primary copy",
+                    diverged
+                );
+            }
+            Ok(diverged) => {
+                println!("[fs]: {} FAT sectors differ between copies", diverged);
+            }
+            Err(_) => {
+                println!("[fs]: failed to check FAT copies");
+            }
+        }
+        if FAT_FREE_BITMAP {
+            manager_reader.enable_free_bitmap()?;
+        }
+        // 在正常卸载之前断电时，下次挂载会完整检查FAT
+        manager_reader.set_clean(false)?;
+        drop(manager_reader);
+        let fs = Arc::new(Self {
+            manager,
+            lock: SleepLock::new(),
+            inodes: Mutex::new(BTreeMap::new()),
+            this: Mutex::new(Weak::new()),
+        });
+        *fs.this.lock() = Arc::downgrade(&fs);
+        Ok(fs)
+    }
+
+    /// 持有卷的锁执行f，期间的磁盘I/O睡眠等待设备的中断
+    /// 不可重入，f中不能再调用会加锁的方法
+    fn locked<T>(&self, f: impl FnOnce() -> T) -> T {
+        let _guard = self.lock.lock();
+        with_sleepable_io(f)
+    }
+
+    /// 取得vfile对应的inode，文件已有inode时返回同一个
+    fn wrap(&self, vfile: Arc<VFile>) -> Arc<FatInode> {
+        let key = dirent_key(&vfile);
+        let mut inodes = self.inodes.lock();
+        if let Some(inode) = inodes.get(&key).and_then(Weak::upgrade) {
+            return inode;
+        }
+        let inode = Arc::new(FatInode {
+            fs: self.this.lock().upgrade().unwrap(),
+            vfile: RwLock::new(vfile),
+            open: Mutex::new(OpenCount::default()),
+        });
+        inodes.insert(key, Arc::downgrade(&inode));
+        inode
+    }
+
+    /// 返回(目录项缓存, 数据缓存)的命中统计
+    pub fn cache_stats(&self) -> (CacheStats, CacheStats) {
+        self.locked(|| self.manager.read().cache_stats())
+    }
+
+    /// 文件的目录项从from移动到了to，仍在使用的inode随之指向新的目录项
+    fn relocate(&self, moves: &[(&VFile, VFile)]) {
+        let mut inodes = self.inodes.lock();
+        let moved: Vec<_> = moves
+            .iter()
+            .map(|(from, to)| {
+                let inode = inodes.remove(&dirent_key(from)).and_then(|i| i.upgrade());
+                (inode, to)
+            })
+            .collect();
+        // 先全部取出再插入，交换时两个位置互换
+        for (inode, to) in moved.iter() {
+            if let Some(inode) = inode {
+                *inode.vfile.write() = Arc::new((*to).clone());
+                inodes.insert(dirent_key(to), Arc::downgrade(inode));
+            }
+        }
+        // 先释放锁，moved中的inode在此之后才可能被释放，其drop需要获取inodes
+        drop(inodes);
+    }
+
+    /// 删除文件或目录，如果仍有进程打开该文件，则推迟到最后一次关闭时回收簇
+    fn remove(&self, vfile: &VFile) -> Result<(), isize> {
+        let inode = self.inodes.lock().remove(&dirent_key(vfile));
+        let inode = match inode.and_then(|inode| inode.upgrade()) {
+            Some(inode) => inode,
+            None => return vfile.remove().map(|_| ()).map_err(io_errno),
+        };
+        // 目录项被删除后所在的簇可能随父目录一起被释放和重用，inode改用内存中的副本
+        let detached = Arc::new(vfile.detach());
+        *inode.vfile.write() = detached.clone();
+        vfile.unlink().map_err(io_errno)?;
+        let mut open = inode.open.lock();
+        if open.count > 0 {
+            open.unlinked = true;
+            return Ok(());
+        }
+        drop(open);
+        detached.free_clusters().map(|_| ()).map_err(io_errno)
+    }
+}
+
+impl FileSystem for FatFileSystem {
+    fn fs_type(&self) -> &'static str {
+        "vfat"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        let root = self.locked(|| self.manager.read().get_root_vfile(&self.manager));
+        self.wrap(Arc::new(root))
+    }
+
+    fn sync(&self) -> Result<(), isize> {
+        self.locked(|| self.manager.read().sync().map_err(io_errno))
+    }
+
+    fn unmount(&self) {
+        // 写回失败时卷保持为脏，下次挂载会完整检查FAT
+        let _ = self.locked(|| self.manager.read().set_clean(true));
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct FatInode {
+    fs: Arc<FatFileSystem>,
+    /// 文件被删除后替换为目录项只在内存中的VFile
+    vfile: RwLock<Arc<VFile>>,
+    open: Mutex<OpenCount>,
+}
+
+impl Drop for FatInode {
+    fn drop(&mut self) {
+        let key = dirent_key(&self.vfile.read());
+        let mut inodes = self.fs.inodes.lock();
+        // 同一位置可能已经是另一个文件的inode
+        if inodes
+            .get(&key)
+            .map_or(false, |inode| inode.as_ptr() == self as *const FatInode)
+        {
+            inodes.remove(&key);
+        }
+    }
+}
+
+impl FatInode {
+    fn vfile(&self) -> Arc<VFile> {
+        self.vfile.read().clone()
+    }
+
+    /// 在目录中查找name，不存在时返回None
+    fn find(&self, name: &str) -> Result<Option<VFile>, isize> {
+        let vfile = self.vfile();
+        if !vfile.is_dir() {
+            return Err(-ENOTDIR);
+        }
+        vfile.find_vfile_byname(name).map_err(io_errno)
+    }
+
+    /// 用当前时间设置新建文件的时间戳
+    fn stamp_new(&self, vfile: Arc<VFile>) -> Arc<dyn Inode> {
+        let now = get_wall_time();
+        vfile.init_times(now.sec as u64, now.nsec as u32);
+        self.fs.wrap(vfile)
+    }
+}
+
+impl Inode for FatInode {
+    fn fs(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    fn kind(&self) -> InodeType {
+        self.fs.locked(|| inode_type(&self.vfile()))
+    }
+
+    fn stat(&self) -> Result<InodeStat, isize> {
+        self.fs.locked(|| {
+            let vfile = self.vfile();
+            let (size, atime, mtime, ctime, first_cluster) = vfile.stat();
+            Ok(InodeStat {
+                ino: first_cluster,
+                kind: inode_type(&vfile),
+                mode: 0o755, // FAT32没有权限位
+                size: size as u64,
+                blocks: (size as u64 + 511) / 512,
+                nlink: 1,
+                rdev: 0,
+                atime: atime as u64,
+                mtime: mtime as u64,
+                ctime: ctime as u64,
+            })
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, isize> {
+        self.fs.locked(|| match self.find(name)? {
+            Some(vfile) => Ok(self.fs.wrap(Arc::new(vfile)) as Arc<dyn Inode>),
+            None => Err(-ENOENT),
+        })
+    }
+
+    fn create(&self, name: &str, kind: InodeType) -> Result<Arc<dyn Inode>, isize> {
+        self.fs.locked(|| {
+            let attribute = match kind {
+                InodeType::Directory => ATTRIBUTE_DIRECTORY,
+                InodeType::File => ATTRIBUTE_ARCHIVE,
+                _ => return Err(-EPERM), // FAT32无法表示特殊文件
+            };
+            if self.find(name)?.is_some() {
+                return Err(-EEXIST);
+            }
+            match self.vfile().create(name, attribute).map_err(io_errno)? {
+                Some(vfile) => Ok(self.stamp_new(vfile)),
+                None => Err(-ENOSPC),
+            }
+        })
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, isize> {
+        self.fs.locked(|| {
+            if self.find(name)?.is_some() {
+                return Err(-EEXIST);
+            }
+            match self
+                .vfile()
+                .create_symlink(name, target)
+                .map_err(io_errno)?
+            {
+                Some(vfile) => Ok(self.stamp_new(vfile)),
+                None => Err(-ENOSPC),
+            }
+        })
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), isize> {
+        self.fs.locked(|| {
+            let vfile = self.find(name)?.ok_or(-ENOENT)?;
+            if vfile.is_dir() {
+                return Err(-EISDIR);
+            }
+            self.fs.remove(&vfile)
+        })
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), isize> {
+        self.fs.locked(|| {
+            let vfile = self.find(name)?.ok_or(-ENOENT)?;
+            if !vfile.is_dir() {
+                return Err(-ENOTDIR);
+            }
+            if !vfile.is_empty_dir().map_err(io_errno)? {
+                return Err(-ENOTEMPTY);
+            }
+            self.fs.remove(&vfile)
+        })
+    }
+
+    fn rename(
+        &self,
+        old_name: &str,
+        new_dir: &Arc<dyn Inode>,
+        new_name: &str,
+        flags: u32,
+    ) -> Result<(), isize> {
+        self.fs.locked(|| {
+            let new_dir = match new_dir.as_any().downcast_ref::<FatInode>() {
+                Some(inode) => inode.vfile(),
+                None => return Err(-EXDEV),
+            };
+            let old = self.find(old_name)?.ok_or(-ENOENT)?;
+            let target = new_dir.find_vfile_byname(new_name).map_err(io_errno)?;
+            let same = |a: &VFile, b: &VFile| {
+                (a.short_sector, a.short_offset) == (b.short_sector, b.short_offset)
+            };
+
+            if flags & RENAME_EXCHANGE != 0 {
+                let target = target.ok_or(-ENOENT)?;
+                if same(&old, &target) {
+                    return Ok(());
+                }
+                // 交换的是目录项的内容，原来a处的文件现在在b处，反之亦然
+                let (new_old, new_target) =
+                    fexchange(&old, &self.vfile(), &target, &new_dir).map_err(io_errno)?;
+                self.fs.relocate(&[(&old, new_target), (&target, new_old)]);
+                return Ok(());
+            }
+
+            if let Some(target) = &target {
+                if same(&old, target) {
+                    return Ok(()); // 同一个文件
+                }
+                if flags & RENAME_NOREPLACE != 0 {
+                    return Err(-EEXIST);
+                }
+                if old.is_dir() {
+                    if !target.is_dir() {
+                        return Err(-ENOTDIR);
+                    }
+                    if !target.is_empty_dir().map_err(io_errno)? {
+                        return Err(-ENOTEMPTY);
+                    }
+                } else if target.is_dir() {
+                    return Err(-EISDIR);
+                }
+            }
+            let moved = match fmove(&old, &new_dir, new_name).map_err(io_errno)? {
+                Some(moved) => moved,
+                None => return Err(-ENOSPC),
+            };
+            // 打开的文件随之指向新目录项
+            self.fs.relocate(&[(&old, moved)]);
+            // 新目录项写入之后再删除被覆盖的目标，若目标仍被打开则推迟释放其数据
+            match target {
+                Some(target) => self.fs.remove(&target),
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn readdir(&self, offset: usize) -> Result<Option<(DirItem, usize)>, isize> {
+        self.fs.locked(|| {
+            let vfile = self.vfile();
+            if !vfile.is_dir() {
+                return Err(-ENOTDIR);
+            }
+            let (name, off, first_cluster, attribute) = match vfile.dirent_info(offset) {
+                Some(info) => info,
+                None => return Ok(None),
+            };
+            let kind = if attribute & ATTRIBUTE_DIRECTORY != 0 {
+                InodeType::Directory
+            } else if attribute & ATTRIBUTE_SYMLINK != 0 {
+                // 系统属性只是提示，还要检查文件内容才能确定是符号链接
+                match vfile.find_vfile_byname(&name).map_err(io_errno)? {
+                    Some(file) if file.is_symlink() => InodeType::SymLink,
+                    _ => InodeType::File,
+                }
+            } else {
+                InodeType::File
+            };
+            let item = DirItem {
+                name,
+                ino: first_cluster as u64,
+                kind,
+            };
+            Ok(Some((item, off as usize + DIRENT_SZ)))
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        self.fs
+            .locked(|| self.vfile().read_at(offset, buf).map_err(io_errno))
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, isize> {
+        self.fs.locked(|| {
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            if offset >= u32::MAX as usize {
+                return Err(-EFBIG); // FAT32单个文件不超过4GB
+            }
+            match self.vfile().write_at(offset, buf).map_err(io_errno)? {
+                0 => Err(-ENOSPC),
+                written => Ok(written),
+            }
+        })
+    }
+
+    fn truncate(&self, size: usize) -> Result<(), isize> {
+        self.fs.locked(|| {
+            if self.vfile().is_dir() {
+                return Err(-EISDIR);
+            }
+            if size > u32::MAX as usize {
+                return Err(-EINVAL); // FAT32单个文件不超过4GB
+            }
+            match self.vfile().truncate(size as u32).map_err(io_errno)? {
+                true => Ok(()),
+                false => Err(-ENOSPC),
+            }
+        })
+    }
+
+    fn read_link(&self) -> Result<String, isize> {
+        self.fs
+            .locked(|| match self.vfile().read_link().map_err(io_errno)? {
+                Some(target) => Ok(target),
+                None => Err(-EINVAL),
+            })
+    }
+
+    fn set_times(&self, atime: Option<u64>, mtime: Option<u64>) {
+        self.fs.locked(|| {
+            self.vfile().set_times(atime, mtime);
+        })
+    }
+
+    fn sync(&self, data_only: bool) -> Result<(), isize> {
+        self.fs
+            .locked(|| self.vfile().sync(data_only).map_err(io_errno))
+    }
+
+    fn open(&self) {
+        self.open.lock().count += 1;
+    }
+
+    fn release(&self) {
+        let mut open = self.open.lock();
+        open.count -= 1;
+        if open.count == 0 && open.unlinked {
+            open.unlinked = false;
+            drop(open);
+            // 最后一个打开者关闭，真正释放数据
+            self.fs.locked(|| {
+                let vfile = self.vfile();
+                if vfile.free_clusters().is_err() {
+                    println!("[fs]: failed to free clusters of {}", vfile.get_name());
+                }
+            });
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
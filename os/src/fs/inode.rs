@@ -1,13 +1,31 @@
-use crate::drivers::BLOCK_DEVICE;
+use crate::config::{TMPFS_MAX_PAGES, TMPFS_ON_TMP};
+use crate::drivers::ROOT_BLOCK_DEVICE;
 use crate::mm::UserBuffer;
+use crate::task::WaitQueue;
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
 use lazy_static::*;
 use spin::Mutex;
 
-use super::{DirEntry, File, DT_DIR, DT_REG, DT_UNKNOWN};
-use fat32::{FAT32Manager, VFile, ATTRIBUTE_ARCHIVE, ATTRIBUTE_DIRECTORY};
+use crate::timer::{get_time_ms, get_wall_time};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::dentry::{lookup_path, Dentry};
+use super::fat::FatFileSystem;
+use super::mount::{close_inode, mount, mounted_filesystems, open_inode};
+use super::tmpfs::TmpFs;
+use super::vfs::{FileSystem, Inode, InodeType};
+use super::{DirEntry, File, PollEvents};
+use fat32::CacheStats;
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+pub const SEEK_DATA: usize = 3;
+pub const SEEK_HOLE: usize = 4;
 
 pub enum DiskInodeType {
     File,
@@ -23,38 +41,59 @@ pub struct OSInode {
 pub struct OSInodeInner {
     /// 当前读写位置
     offset: usize,
-    inode: Arc<VFile>,
+    dentry: Arc<Dentry>,
+    inode: Arc<dyn Inode>,
 }
 
 impl OSInode {
-    pub fn new(readable: bool, writable: bool, inode: Arc<VFile>) -> Self {
+    pub fn new(readable: bool, writable: bool, dentry: Arc<Dentry>) -> Self {
+        let inode = dentry.inode();
+        open_inode(&inode);
         Self {
             readable,
             writable,
-            inner: Mutex::new(OSInodeInner { offset: 0, inode }),
+            inner: Mutex::new(OSInodeInner {
+                offset: 0,
+                dentry,
+                inode,
+            }),
         }
     }
 
-    pub fn is_dir(&self) -> bool {
+    pub fn dentry(&self) -> Arc<Dentry> {
+        self.inner.lock().dentry.clone()
+    }
+
+    pub fn inode(&self) -> Arc<dyn Inode> {
+        self.inner.lock().inode.clone()
+    }
+
+    /// inode和当前读写位置，inode的I/O可能睡眠等待磁盘，读写时不能持有锁
+    fn position(&self) -> (Arc<dyn Inode>, usize) {
         let inner = self.inner.lock();
-        inner.inode.is_dir()
+        (inner.inode.clone(), inner.offset)
     }
 
-    pub fn read_vec(&self, offset: isize, len: usize) -> Vec<u8> {
-        let mut inner = self.inner.lock();
+    pub fn is_dir(&self) -> bool {
+        self.inode().kind() == InodeType::Directory
+    }
+
+    pub fn read_vec(&self, offset: isize, len: usize) -> Result<Vec<u8>, isize> {
+        let (inode, ori_off) = self.position();
         let mut len = len;
-        let ori_off = inner.offset;
-        if offset >= 0 {
-            inner.offset = offset as usize;
-        }
+        let mut pos = if offset >= 0 {
+            offset as usize
+        } else {
+            ori_off
+        };
         let mut buffer = [0u8; 512];
         let mut v: Vec<u8> = Vec::new();
         loop {
-            let rlen = inner.inode.read_at(inner.offset, &mut buffer);
+            let rlen = inode.read_at(pos, &mut buffer)?;
             if rlen == 0 {
                 break;
             }
-            inner.offset += rlen;
+            pos += rlen;
             v.extend_from_slice(&buffer[..rlen.min(len)]);
             if len > rlen {
                 len -= rlen;
@@ -62,105 +101,204 @@ impl OSInode {
                 break;
             }
         }
-        if offset >= 0 {
-            inner.offset = ori_off;
+        if offset < 0 {
+            self.inner.lock().offset = pos;
         }
-        v
+        Ok(v)
     }
 
     /// 从文件中读出信息放入缓冲区中
-    pub fn read_all(&self) -> Vec<u8> {
-        let mut inner = self.inner.lock();
+    pub fn read_all(&self) -> Result<Vec<u8>, isize> {
+        let (inode, mut pos) = self.position();
         let mut buffer = [0u8; 512];
         let mut v: Vec<u8> = Vec::new();
         loop {
-            let len = inner.inode.read_at(inner.offset, &mut buffer);
+            let len = inode.read_at(pos, &mut buffer)?;
             if len == 0 {
                 break;
             }
-            inner.offset += len;
+            pos += len;
             v.extend_from_slice(&buffer[..len]);
         }
-        v
+        self.inner.lock().offset = pos;
+        Ok(v)
     }
 
-    pub fn write_all(&self, str_vec: &Vec<u8>) -> usize {
-        let mut inner = self.inner.lock();
+    pub fn write_all(&self, str_vec: &Vec<u8>) -> Result<usize, isize> {
+        let (inode, mut pos) = self.position();
         let mut remain = str_vec.len();
         let mut base = 0;
         loop {
             let len = remain.min(512);
-            inner
-                .inode
-                .write_at(inner.offset, &str_vec.as_slice()[base..base + len]);
-            inner.offset += len;
+            inode.write_at(pos, &str_vec.as_slice()[base..base + len])?;
+            pos += len;
             base += len;
             remain -= len;
             if remain == 0 {
                 break;
             }
         }
-        return base;
+        self.inner.lock().offset = pos;
+        return Ok(base);
     }
 
     pub fn find(&self, path: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
-        let inner = self.inner.lock();
-        let new_path: Vec<&str> = path.split('/').collect();
-        let vfile = inner.inode.find_vfile_bypath(new_path);
-        if vfile.is_none() {
-            return None;
-        } else {
-            let (readable, writable) = flags.read_write();
-            return Some(Arc::new(OSInode::new(readable, writable, vfile.unwrap())));
-        }
+        let dentry = self.dentry();
+        let dentry = lookup_path(&ROOT_DENTRY, &dentry, path, true).ok()?;
+        let (readable, writable) = flags.read_write();
+        Some(Arc::new(OSInode::new(readable, writable, dentry)))
     }
 
     pub fn get_dirent(&self, dir_entry: &mut DirEntry) -> Option<usize> {
-        let mut inner = self.inner.lock();
-        let offset = inner.offset as u32;
-        if let Some((name, off, first_clu, attri)) = inner.inode.dirent_info(offset as usize) {
-            let mut dtype: u8 = 0;
-            if attri & ATTRIBUTE_DIRECTORY != 0 {
-                dtype = DT_DIR;
-            } else if attri & ATTRIBUTE_ARCHIVE != 0 {
-                dtype = DT_REG;
-            } else {
-                dtype = DT_UNKNOWN;
+        let (inode, offset) = self.position();
+        let (item, next) = inode.readdir(offset).ok()??;
+        dir_entry.set(
+            item.name.as_str(),
+            item.ino as usize,
+            (next - offset) as isize,
+            item.name.len() as u16,
+            item.kind.dtype(),
+        );
+        self.inner.lock().offset = next;
+        Some(item.name.len() + 8 * 4)
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.inode()
+            .stat()
+            .map(|stat| stat.size as usize)
+            .unwrap_or(0)
+    }
+
+    /// 将文件写回磁盘，data_only为true时对应fdatasync
+    pub fn sync(&self, data_only: bool) -> Result<(), isize> {
+        self.inode().sync(data_only)
+    }
+
+    /// 从offset处读取，不改变读写位置
+    pub fn read_at(&self, offset: usize, mut buf: UserBuffer) -> Result<usize, isize> {
+        read_inode_at(&self.inode(), offset, &mut buf)
+    }
+
+    /// 写入offset处，不改变读写位置
+    pub fn write_at(&self, offset: usize, buf: UserBuffer) -> Result<usize, isize> {
+        write_inode_at(&self.inode(), offset, &buf)
+    }
+
+    /// 调整读写位置并返回新的位置，位置非法时返回None
+    pub fn lseek(&self, offset: isize, whence: usize) -> Option<usize> {
+        let (inode, cur) = self.position();
+        let size = inode.stat().ok()?.size as isize;
+        let new_offset = match whence {
+            SEEK_SET => offset,
+            SEEK_CUR => cur as isize + offset,
+            SEEK_END => size + offset,
+            SEEK_DATA | SEEK_HOLE => {
+                if offset < 0 {
+                    return None;
+                }
+                let found = inode.seek_data(offset as usize, whence == SEEK_HOLE);
+                found.ok()? as isize
             }
-            dir_entry.set(
-                name.as_str(),
-                first_clu as usize,
-                (off - offset) as isize,
-                name.len() as u16,
-                dtype,
-            );
-            inner.offset = off as usize;
-            let len = name.len() + 8 * 4;
-            Some(len)
-        } else {
-            None
+            _ => return None,
+        };
+        if new_offset < 0 {
+            return None;
         }
+        self.inner.lock().offset = new_offset as usize;
+        Some(new_offset as usize)
     }
+}
 
-    pub fn get_size(&self) -> usize {
-        let inner = self.inner.lock();
-        let (size, _, _, _, _) = inner.inode.stat();
-        return size as usize;
+impl Drop for OSInode {
+    fn drop(&mut self) {
+        // 最后一次关闭可能要释放文件的数据，不能持有锁
+        let inode = self.inode();
+        close_inode(&inode);
     }
 }
 
 lazy_static! {
-    pub static ref ROOT_VFILE: Arc<VFile> = {
-        let fat32_manager = FAT32Manager::open(BLOCK_DEVICE.clone()); // 打开设备
-        let manager_reader = fat32_manager.read();
-        Arc::new(manager_reader.get_root_vfile(&fat32_manager))
-    };
+    /// 根文件系统
+    pub static ref ROOT_FS: Arc<dyn FileSystem> = mount_root();
+    /// 根目录的目录项
+    pub static ref ROOT_DENTRY: Arc<Dentry> = Dentry::new_root(ROOT_FS.root());
+}
+
+/// 挂载根文件系统，没有磁盘或磁盘上的FAT32无法挂载时以tmpfs作为根
+fn mount_root() -> Arc<dyn FileSystem> {
+    match ROOT_BLOCK_DEVICE.clone() {
+        Some(device) => match FatFileSystem::mount(device) {
+            Ok(fs) => return fs,
+            Err(err) => {
+                println!("[fs]: failed to mount the root filesystem: {}", err);
+            }
+        },
+        None => {
+            println!("[fs]: no root block device");
+        }
+    }
+    println!("[fs]: using tmpfs as the root filesystem");
+    TmpFs::new(TMPFS_MAX_PAGES, 0o755)
+}
+
+/// 挂载根文件系统，并在/proc和/tmp上挂载伪文件系统，挂载点不存在时先创建
+pub fn init() {
+    let mut mounts = vec![("proc", "proc"), ("dev", "devtmpfs")];
+    if TMPFS_ON_TMP && ROOT_FS.fs_type() != "tmpfs" {
+        mounts.push(("tmp", "tmpfs"));
+    }
+    for (name, fs_type) in mounts {
+        if ROOT_DENTRY.lookup(name).is_err() {
+            let _ = ROOT_DENTRY.create(name, InodeType::Directory);
+        }
+        let target = String::from("/") + name;
+        if let Err(errno) = mount("/", fs_type, target.as_str(), fs_type, "") {
+            println!("[fs]: failed to mount {} on {}: {}", fs_type, target, errno);
+        }
+    }
+}
+
+/// 脏块在内存中停留的最长时间
+const WRITEBACK_INTERVAL_MS: usize = 3000;
+
+/// 下一次定期写回的时刻
+static NEXT_WRITEBACK_MS: AtomicUsize = AtomicUsize::new(WRITEBACK_INTERVAL_MS);
+
+/// 将所有已挂载文件系统的脏块写回磁盘，返回遇到的最后一个错误
+pub fn sync_all() -> Result<(), isize> {
+    let mut result = Ok(());
+    for fs in mounted_filesystems() {
+        if let Err(errno) = fs.sync() {
+            result = Err(errno);
+        }
+    }
+    result
+}
+
+/// 返回根文件系统(目录项缓存, 数据缓存)的命中统计，根文件系统不是FAT32时返回None
+pub fn block_cache_stats() -> Option<(CacheStats, CacheStats)> {
+    let fat = ROOT_FS.as_any().downcast_ref::<FatFileSystem>()?;
+    Some(fat.cache_stats())
+}
+
+/// 定期写回脏块，在时钟中断中调用
+pub fn periodic_writeback() {
+    let current_ms = get_time_ms();
+    if current_ms >= NEXT_WRITEBACK_MS.load(Ordering::Relaxed) {
+        NEXT_WRITEBACK_MS.store(current_ms + WRITEBACK_INTERVAL_MS, Ordering::Relaxed);
+        // 写回失败的块保持为脏，下一次继续尝试
+        let _ = sync_all();
+    }
 }
 
 pub fn list_apps() {
     println!("/**** APPS ****/");
-    for (name, _) in ROOT_VFILE.ls().unwrap() {
-        println!("{}", name);
+    let root = ROOT_DENTRY.inode();
+    let mut offset = 0;
+    while let Ok(Some((item, next))) = root.readdir(offset) {
+        println!("{}", item.name);
+        offset = next;
     }
     println!("**************/");
 }
@@ -192,54 +330,114 @@ impl OpenFlags {
     }
 }
 
+/// 获取工作目录对应的目录项
+fn work_dentry(work_path: &str) -> Arc<Dentry> {
+    lookup_path(&ROOT_DENTRY, &ROOT_DENTRY, work_path, true).unwrap()
+}
+
+/// 根据工作目录和路径查找目录项，不打开文件
+/// follow_last为false时路径最后一项的符号链接本身会被返回
+pub fn find_dentry(work_path: &str, path: &str, follow_last: bool) -> Result<Arc<Dentry>, isize> {
+    lookup_path(&ROOT_DENTRY, &work_dentry(work_path), path, follow_last)
+}
+
 pub fn open(
     work_path: &str,
     path: &str,
     flags: OpenFlags,
     dtype: DiskInodeType,
 ) -> Option<Arc<OSInode>> {
-    let cur_inode = {
-        if work_path == "/" {
-            ROOT_VFILE.clone()
-        } else {
-            let wpath: Vec<&str> = work_path.split('/').collect();
-            ROOT_VFILE.find_vfile_bypath(wpath).unwrap()
-        }
-    };
-    let mut pathv: Vec<&str> = path.split('/').collect();
-
+    let cur_dentry = work_dentry(work_path);
     let (readable, writeable) = flags.read_write(); // 权限
     if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = cur_inode.find_vfile_bypath(pathv.clone()) {
-            inode.remove();
-        }
-        {
-            // create file
-            let name = pathv.pop().unwrap();
-            if let Some(temp_inode) = cur_inode.find_vfile_bypath(pathv.clone()) {
-                let attribute = {
-                    match dtype {
-                        DiskInodeType::Directory => ATTRIBUTE_DIRECTORY,
-                        DiskInodeType::File => ATTRIBUTE_ARCHIVE,
-                    }
-                };
-                temp_inode
-                    .create(name, attribute)
-                    .map(|inode| Arc::new(OSInode::new(readable, writeable, inode)))
+        let (parent, name) = match path.rfind('/') {
+            Some(idx) => (&path[..idx + 1], &path[idx + 1..]),
+            None => (".", path),
+        };
+        let parent = lookup_path(&ROOT_DENTRY, &cur_dentry, parent, true).ok()?;
+        // 已经存在的文件先删除再重新创建
+        if let Ok(old) = parent.lookup(name) {
+            let removed = if old.is_dir() {
+                parent.rmdir(name)
             } else {
-                None
+                parent.unlink(name)
+            };
+            if removed.is_err() {
+                return None;
             }
         }
+        let kind = match dtype {
+            DiskInodeType::Directory => InodeType::Directory,
+            DiskInodeType::File => InodeType::File,
+        };
+        // 磁盘出错时与创建失败一样返回None
+        parent
+            .create(name, kind)
+            .ok()
+            .map(|dentry| Arc::new(OSInode::new(readable, writeable, dentry)))
     } else {
-        cur_inode.find_vfile_bypath(pathv).map(|inode| {
-            if flags.contains(OpenFlags::TRUNC) {
-                inode.clear();
-            }
-            Arc::new(OSInode::new(readable, writeable, inode))
-        })
+        let dentry = lookup_path(&ROOT_DENTRY, &cur_dentry, path, true).ok()?;
+        if flags.contains(OpenFlags::TRUNC)
+            && !dentry.is_dir()
+            && dentry.inode().truncate(0).is_err()
+        {
+            return None;
+        }
+        Some(Arc::new(OSInode::new(readable, writeable, dentry)))
     }
 }
 
+/// 从inode的offset处读取到buf中，返回读取的字节数
+/// 已经读出部分数据后出错时返回已读的字节数，否则返回EIO
+fn read_inode_at(
+    inode: &Arc<dyn Inode>,
+    mut offset: usize,
+    buf: &mut UserBuffer,
+) -> Result<usize, isize> {
+    let mut total_read_size = 0usize;
+    for slice in buf.buffers.iter_mut() {
+        let read_size = match inode.read_at(offset, *slice) {
+            Ok(read_size) => read_size,
+            Err(_) if total_read_size > 0 => break,
+            Err(errno) => return Err(errno),
+        };
+        offset += read_size;
+        total_read_size += read_size;
+        if read_size < slice.len() {
+            break; // 到达文件末尾
+        }
+    }
+    if total_read_size > 0 {
+        inode.set_times(Some(get_wall_time().sec as u64), None);
+    }
+    Ok(total_read_size)
+}
+
+/// 将buf写入inode的offset处，返回写入的字节数，出错时的处理与read_inode_at相同
+fn write_inode_at(
+    inode: &Arc<dyn Inode>,
+    mut offset: usize,
+    buf: &UserBuffer,
+) -> Result<usize, isize> {
+    let mut total_write_size = 0usize;
+    for slice in buf.buffers.iter() {
+        let write_size = match inode.write_at(offset, *slice) {
+            Ok(write_size) => write_size,
+            Err(_) if total_write_size > 0 => break,
+            Err(errno) => return Err(errno),
+        };
+        offset += write_size;
+        total_write_size += write_size;
+        if write_size < slice.len() {
+            break; // 空间不足
+        }
+    }
+    if total_write_size > 0 {
+        inode.set_times(None, Some(get_wall_time().sec as u64));
+    }
+    Ok(total_write_size)
+}
+
 impl File for OSInode {
     fn readable(&self) -> bool {
         self.readable
@@ -247,28 +445,47 @@ impl File for OSInode {
     fn writable(&self) -> bool {
         self.writable
     }
-    fn read(&self, mut buf: UserBuffer) -> usize {
-        let mut inner = self.inner.lock();
-        let mut total_read_size = 0usize;
-        for slice in buf.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, *slice);
-            if read_size == 0 {
-                break;
-            }
-            inner.offset += read_size;
-            total_read_size += read_size;
+    fn read(&self, mut buf: UserBuffer) -> Result<usize, isize> {
+        let (inode, offset) = self.position();
+        if inode.kind() == InodeType::CharDevice {
+            // 一次读入整个缓冲区，一行输入不会被拆开
+            let mut data = vec![0u8; buf.len()];
+            let len = inode.read_at(0, &mut data)?;
+            return Ok(buf.write(&data[..len]));
+        }
+        let read_size = read_inode_at(&inode, offset, &mut buf)?;
+        self.inner.lock().offset = offset + read_size;
+        Ok(read_size)
+    }
+    fn write(&self, buf: UserBuffer) -> Result<usize, isize> {
+        let (inode, offset) = self.position();
+        if inode.kind() == InodeType::CharDevice {
+            let data: Vec<u8> = buf
+                .buffers
+                .iter()
+                .flat_map(|buffer| buffer.iter().copied())
+                .collect();
+            return inode.write_at(0, &data);
+        }
+        let write_size = write_inode_at(&inode, offset, &buf)?;
+        self.inner.lock().offset = offset + write_size;
+        Ok(write_size)
+    }
+    fn poll(&self) -> PollEvents {
+        // 普通文件的读写总是就绪的，设备文件由inode决定
+        let mut events = PollEvents::empty();
+        if self.readable {
+            events |= PollEvents::POLLIN;
         }
-        total_read_size
-    }
-    fn write(&self, buf: UserBuffer) -> usize {
-        let mut inner = self.inner.lock();
-        let mut total_write_size = 0usize;
-        for slice in buf.buffers.iter() {
-            let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
-            inner.offset += write_size;
-            total_write_size += write_size;
+        if self.writable {
+            events |= PollEvents::POLLOUT;
         }
-        total_write_size
+        self.inode().poll(events)
+    }
+    fn wait_queue(&self) -> Option<Arc<WaitQueue>> {
+        self.inode().wait_queue()
+    }
+    fn ioctl(&self, cmd: usize, arg: usize) -> Result<isize, isize> {
+        self.inode().ioctl(cmd, arg)
     }
 }
@@ -1,11 +1,22 @@
+mod dentry;
+mod devfs;
 mod dir;
+mod fat;
 mod inode;
+mod mount;
 mod pipe;
+mod poll;
+mod procfs;
 mod stdio;
+mod tmpfs;
+mod tty;
+mod vfs;
 
 mod test; // 测试
 
 use crate::mm::UserBuffer;
+use crate::syscall::errno::ENOTTY;
+use crate::task::WaitQueue;
 use alloc::sync::Arc;
 
 #[derive(Clone)]
@@ -38,16 +49,55 @@ impl FileDescriptor {
 pub enum FileType {
     File(Arc<OSInode>),
     Abstr(Arc<dyn File + Send + Sync>),
+    Epoll(Arc<EventPoll>),
+}
+
+impl FileType {
+    /// 以File trait对象的形式访问
+    pub fn as_file(&self) -> Arc<dyn File + Send + Sync> {
+        match self {
+            FileType::File(inode) => inode.clone(),
+            FileType::Abstr(file) => file.clone(),
+            FileType::Epoll(epoll) => epoll.clone(),
+        }
+    }
 }
 
 pub trait File: Send + Sync {
     fn readable(&self) -> bool;
     fn writable(&self) -> bool;
-    fn read(&self, buf: UserBuffer) -> usize;
-    fn write(&self, buf: UserBuffer) -> usize;
+    /// 返回读取的字节数，出错时返回负的错误码
+    fn read(&self, buf: UserBuffer) -> Result<usize, isize>;
+    /// 返回写入的字节数，出错时返回负的错误码
+    fn write(&self, buf: UserBuffer) -> Result<usize, isize>;
+    /// 返回当前的就绪状态，不阻塞
+    fn poll(&self) -> PollEvents;
+    /// 就绪状态变化时唤醒的等待队列，为None时等待者只能定期重新检查
+    fn wait_queue(&self) -> Option<Arc<WaitQueue>> {
+        None
+    }
+    /// 设备相关的控制操作，不支持时返回ENOTTY
+    fn ioctl(&self, _cmd: usize, _arg: usize) -> Result<isize, isize> {
+        Err(-ENOTTY)
+    }
 }
 
-pub use dir::{DirEntry, DT_DIR, DT_REG, DT_UNKNOWN};
-pub use inode::{list_apps, open, DiskInodeType, OSInode, OpenFlags, ROOT_VFILE};
+pub use dentry::{lookup_path, Dentry, SYMLOOP_MAX};
+pub use devfs::DevFs;
+pub use dir::{DirEntry, DT_BLK, DT_CHR, DT_DIR, DT_FIFO, DT_LNK, DT_REG, DT_UNKNOWN};
+pub use fat::{FatFileSystem, FatInode};
+pub use inode::{
+    block_cache_stats, find_dentry, init, list_apps, open, periodic_writeback, sync_all,
+    DiskInodeType, OSInode, OpenFlags, ROOT_DENTRY, ROOT_FS, SEEK_CUR, SEEK_DATA, SEEK_END,
+    SEEK_HOLE, SEEK_SET,
+};
+pub use mount::{close_inode, mount, mount_list, open_inode, umount, unmount_all};
 pub use pipe::{make_pipe, Pipe};
+pub use poll::{EpollItem, EventPoll, PollEvents, PollWaiters};
+pub use procfs::ProcFs;
 pub use stdio::{Stdin, Stdout};
+pub use tmpfs::{TmpFs, TmpInode};
+pub use tty::TTY;
+pub use vfs::{
+    same_fs, DirItem, FileSystem, Inode, InodeStat, InodeType, RENAME_EXCHANGE, RENAME_NOREPLACE,
+};
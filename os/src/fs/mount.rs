@@ -0,0 +1,247 @@
+//! 挂载表
+use super::dentry::{purge_dentries, Dentry};
+use super::devfs::DevFs;
+use super::fat::FatFileSystem;
+use super::inode::{find_dentry, ROOT_DENTRY, ROOT_FS};
+use super::procfs::ProcFs;
+use super::tmpfs::TmpFs;
+use super::vfs::{fs_id, FileSystem, Inode, InodeType};
+use crate::board::BLOCK_DEVICE_NAME;
+use crate::config::TMPFS_MAX_PAGES;
+use crate::drivers::{block_device_by_name, root_device_name, LoopDevice};
+use crate::syscall::errno::*;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use fat32::{BlockDevice, MountError};
+use lazy_static::*;
+use spin::Mutex;
+
+/// umount2的标志
+pub const MNT_FORCE: u32 = 1;
+pub const MNT_DETACH: u32 = 2;
+pub const UMOUNT_NOFOLLOW: u32 = 8;
+
+/// 挂载表中的一项
+pub struct MountPoint {
+    /// 设备名、镜像文件路径或伪文件系统的名称
+    pub source: String,
+    pub fs: Arc<dyn FileSystem>,
+    /// 被覆盖的目录，根文件系统没有
+    mountpoint: Option<Arc<Dentry>>,
+    /// 文件系统的根目录项
+    root: Arc<Dentry>,
+}
+
+impl MountPoint {
+    /// 挂载点的绝对路径
+    pub fn path(&self) -> String {
+        self.root.path()
+    }
+}
+
+lazy_static! {
+    static ref MOUNT_TABLE: Mutex<Vec<MountPoint>> = Mutex::new(vec![MountPoint {
+        source: match root_device_name() {
+            Some(name) if ROOT_FS.fs_type() != "tmpfs" => String::from("/dev/") + name,
+            _ => String::from("tmpfs"),
+        },
+        fs: ROOT_FS.clone(),
+        mountpoint: None,
+        root: ROOT_DENTRY.clone(),
+    }]);
+    /// 各文件系统上打开的文件数，以文件系统的标识为键
+    static ref OPEN_FILES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+}
+
+/// 打开inode，文件系统在有文件打开时不能卸载
+pub fn open_inode(inode: &Arc<dyn Inode>) {
+    *OPEN_FILES.lock().entry(fs_id(&inode.fs())).or_insert(0) += 1;
+    inode.open();
+}
+
+/// 关闭由open_inode打开的inode
+pub fn close_inode(inode: &Arc<dyn Inode>) {
+    inode.release();
+    let id = fs_id(&inode.fs());
+    let mut open_files = OPEN_FILES.lock();
+    let count = open_files.get_mut(&id).unwrap();
+    *count -= 1;
+    if *count == 0 {
+        open_files.remove(&id);
+    }
+}
+
+/// dentry本身或其下的目录是否为挂载点
+pub fn has_mounts_under(dentry: &Arc<Dentry>) -> bool {
+    MOUNT_TABLE
+        .lock()
+        .iter()
+        .any(|mount| match &mount.mountpoint {
+            Some(mountpoint) => dentry.is_ancestor_of(mountpoint),
+            None => false,
+        })
+}
+
+/// 返回挂载表的副本：(来源, 挂载点路径, 文件系统类型)
+pub fn mount_list() -> Vec<(String, String, &'static str)> {
+    MOUNT_TABLE
+        .lock()
+        .iter()
+        .map(|mount| (mount.source.clone(), mount.path(), mount.fs.fs_type()))
+        .collect()
+}
+
+/// 挂载在块设备name上、与其重叠的文件系统：整个磁盘与其上的每个分区都重叠
+pub fn block_device_mounts(name: &str) -> Vec<Arc<dyn FileSystem>> {
+    let overlaps = |mounted: &str| {
+        mounted == name || mounted == BLOCK_DEVICE_NAME || name == BLOCK_DEVICE_NAME
+    };
+    MOUNT_TABLE
+        .lock()
+        .iter()
+        .filter(|mount| match mount.source.strip_prefix("/dev/") {
+            Some(mounted) => overlaps(mounted),
+            None => false,
+        })
+        .map(|mount| mount.fs.clone())
+        .collect()
+}
+
+fn mount_errno(err: MountError) -> isize {
+    match err {
+        MountError::Io(_) => -EIO,
+        err => {
+            println!("[fs]: mount failed: {}", err);
+            -EINVAL
+        }
+    }
+}
+
+/// 打开挂载的来源：/dev/下的块设备，或者作为loop设备的镜像文件
+fn open_block_source(work_path: &str, source: &str) -> Result<Arc<dyn BlockDevice>, isize> {
+    if let Some(name) = source.strip_prefix("/dev/") {
+        if let Some(device) = block_device_by_name(name) {
+            // 同一设备不能同时挂载两次，否则两份缓存会互相覆盖
+            if MOUNT_TABLE
+                .lock()
+                .iter()
+                .any(|mount| mount.source == source)
+            {
+                return Err(-EBUSY);
+            }
+            return Ok(device);
+        }
+    }
+    let file = find_dentry(work_path, source, true)?.inode();
+    match file.kind() {
+        InodeType::File => Ok(Arc::new(LoopDevice::new(file)?)),
+        InodeType::Directory => Err(-ENOTBLK),
+        _ => Err(-ENXIO),
+    }
+}
+
+/// 按类型创建文件系统实例，data为逗号分隔的挂载选项
+fn create_fs(
+    work_path: &str,
+    source: &str,
+    fs_type: &str,
+    data: &str,
+) -> Result<Arc<dyn FileSystem>, isize> {
+    match fs_type {
+        "vfat" | "fat32" => {
+            let device = open_block_source(work_path, source)?;
+            let fs = FatFileSystem::mount(device).map_err(mount_errno)?;
+            Ok(fs)
+        }
+        "tmpfs" => Ok(TmpFs::with_options(data, TMPFS_MAX_PAGES)?),
+        "proc" => Ok(ProcFs::new()),
+        "devtmpfs" => Ok(DevFs::new()),
+        _ => Err(-ENODEV),
+    }
+}
+
+/// 将source上类型为fs_type的文件系统挂载到target目录
+pub fn mount(
+    work_path: &str,
+    source: &str,
+    target: &str,
+    fs_type: &str,
+    data: &str,
+) -> Result<(), isize> {
+    let mountpoint = find_dentry(work_path, target, true)?;
+    if !mountpoint.is_dir() {
+        return Err(-ENOTDIR);
+    }
+    if Arc::ptr_eq(&mountpoint, &ROOT_DENTRY) {
+        return Err(-EBUSY);
+    }
+    let fs = create_fs(work_path, source, fs_type, data)?;
+    let root = Dentry::new_mount_root(fs.root(), &mountpoint);
+    mountpoint.set_mounted(Some(root.clone()));
+    MOUNT_TABLE.lock().push(MountPoint {
+        source: String::from(source),
+        fs,
+        mountpoint: Some(mountpoint),
+        root,
+    });
+    Ok(())
+}
+
+/// 卸载挂载在target上的文件系统，MNT_DETACH时即使仍在使用也立即从目录树中移除
+pub fn umount(work_path: &str, target: &str, flags: u32) -> Result<(), isize> {
+    if flags & !(MNT_FORCE | MNT_DETACH | UMOUNT_NOFOLLOW) != 0 {
+        return Err(-EINVAL);
+    }
+    let root = find_dentry(work_path, target, flags & UMOUNT_NOFOLLOW == 0)?;
+    let mut table = MOUNT_TABLE.lock();
+    let index = table
+        .iter()
+        .position(|mount| Arc::ptr_eq(&mount.root, &root))
+        .ok_or(-EINVAL)?;
+    let mount = &table[index];
+    let mountpoint = mount.mountpoint.clone().ok_or(-EBUSY)?; // 根文件系统
+    let id = fs_id(&mount.fs);
+    if flags & MNT_DETACH == 0 {
+        if OPEN_FILES.lock().contains_key(&id) {
+            return Err(-EBUSY);
+        }
+        // 其下还挂载着其他文件系统
+        let nested = table.iter().any(|other| match &other.mountpoint {
+            Some(other) => fs_id(&other.inode().fs()) == id,
+            None => false,
+        });
+        if nested {
+            return Err(-EBUSY);
+        }
+    }
+    let mount = table.remove(index);
+    drop(table);
+    mountpoint.set_mounted(None);
+    // 写回失败的数据无法再访问，与Linux一样仍然完成卸载
+    let _ = mount.fs.sync();
+    purge_dentries(&mount.fs);
+    mount.fs.unmount();
+    Ok(())
+}
+
+/// 所有已挂载的文件系统，按挂载的逆序排列
+/// loop设备上的文件系统在其镜像文件所在的文件系统之前，应按此顺序写回
+pub fn mounted_filesystems() -> Vec<Arc<dyn FileSystem>> {
+    MOUNT_TABLE
+        .lock()
+        .iter()
+        .rev()
+        .map(|mount| mount.fs.clone())
+        .collect()
+}
+
+/// 关机前写回并卸载所有文件系统
+pub fn unmount_all() {
+    for fs in mounted_filesystems() {
+        let _ = fs.sync();
+        fs.unmount();
+    }
+}
@@ -1,6 +1,7 @@
-use super::File;
+use super::{File, PollEvents};
 use crate::mm::UserBuffer;
-use crate::task::suspend_current_and_run_next;
+use crate::syscall::errno::EINTR;
+use crate::task::{has_pending_signal, WaitQueue};
 use alloc::sync::{Arc, Weak};
 use spin::Mutex;
 
@@ -8,21 +9,28 @@ pub struct Pipe {
     readable: bool,
     writable: bool,
     buffer: Arc<Mutex<PipeRingBuffer>>,
+    /// 两端共用，缓冲区状态变化或一端关闭时唤醒等待的读者、写者和poll
+    queue: Arc<WaitQueue>,
 }
 
 impl Pipe {
-    pub fn read_end_with_buffer(buffer: Arc<Mutex<PipeRingBuffer>>) -> Self {
+    pub fn read_end_with_buffer(buffer: Arc<Mutex<PipeRingBuffer>>, queue: Arc<WaitQueue>) -> Self {
         Self {
             readable: true,
             writable: false,
             buffer,
+            queue,
         }
     }
-    pub fn write_end_with_buffer(buffer: Arc<Mutex<PipeRingBuffer>>) -> Self {
+    pub fn write_end_with_buffer(
+        buffer: Arc<Mutex<PipeRingBuffer>>,
+        queue: Arc<WaitQueue>,
+    ) -> Self {
         Self {
             readable: false,
             writable: true,
             buffer,
+            queue,
         }
     }
 }
@@ -41,6 +49,7 @@ pub struct PipeRingBuffer {
     head: usize,
     tail: usize,
     status: RingBufferStatus,
+    read_end: Option<Weak<Pipe>>,
     write_end: Option<Weak<Pipe>>,
 }
 
@@ -51,9 +60,13 @@ impl PipeRingBuffer {
             head: 0,
             tail: 0,
             status: RingBufferStatus::EMPTY,
+            read_end: None,
             write_end: None,
         }
     }
+    pub fn set_read_end(&mut self, read_end: &Arc<Pipe>) {
+        self.read_end = Some(Arc::downgrade(read_end));
+    }
     pub fn set_write_end(&mut self, write_end: &Arc<Pipe>) {
         self.write_end = Some(Arc::downgrade(write_end));
     }
@@ -92,6 +105,9 @@ impl PipeRingBuffer {
             RING_BUFFER_SIZE - self.available_read()
         }
     }
+    pub fn all_read_ends_closed(&self) -> bool {
+        self.read_end.as_ref().unwrap().upgrade().is_none()
+    }
     pub fn all_write_ends_closed(&self) -> bool {
         self.write_end.as_ref().unwrap().upgrade().is_none()
     }
@@ -100,8 +116,10 @@ impl PipeRingBuffer {
 /// Return (read_end, write_end)
 pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
     let buffer = Arc::new(Mutex::new(PipeRingBuffer::new()));
-    let read_end = Arc::new(Pipe::read_end_with_buffer(buffer.clone()));
-    let write_end = Arc::new(Pipe::write_end_with_buffer(buffer.clone()));
+    let queue = Arc::new(WaitQueue::new());
+    let read_end = Arc::new(Pipe::read_end_with_buffer(buffer.clone(), queue.clone()));
+    let write_end = Arc::new(Pipe::write_end_with_buffer(buffer.clone(), queue));
+    buffer.lock().set_read_end(&read_end);
     buffer.lock().set_write_end(&write_end);
     (read_end, write_end)
 }
@@ -113,7 +131,7 @@ impl File for Pipe {
     fn writable(&self) -> bool {
         self.writable
     }
-    fn read(&self, buf: UserBuffer) -> usize {
+    fn read(&self, buf: UserBuffer) -> Result<usize, isize> {
         assert_eq!(self.readable(), true);
         let mut buf_iter = buf.into_iter();
         let mut read_size = 0usize;
@@ -122,12 +140,21 @@ impl File for Pipe {
             let loop_read = ring_buffer.available_read();
             if loop_read == 0 {
                 if ring_buffer.all_write_ends_closed() {
-                    return read_size;
+                    return Ok(read_size);
                 }
                 drop(ring_buffer);
-                suspend_current_and_run_next();
+                if has_pending_signal() {
+                    return if read_size > 0 {
+                        Ok(read_size)
+                    } else {
+                        Err(-EINTR)
+                    };
+                }
+                self.queue.wait(None);
                 continue;
             }
+            // 读出数据后写端可能变为可写
+            self.queue.wake_all();
             // read at most loop_read bytes
             for _ in 0..loop_read {
                 if let Some(byte_ref) = buf_iter.next() {
@@ -136,12 +163,12 @@ impl File for Pipe {
                     }
                     read_size += 1;
                 } else {
-                    return read_size;
+                    return Ok(read_size);
                 }
             }
         }
     }
-    fn write(&self, buf: UserBuffer) -> usize {
+    fn write(&self, buf: UserBuffer) -> Result<usize, isize> {
         assert_eq!(self.writable(), true);
         let mut buf_iter = buf.into_iter();
         let mut write_size = 0usize;
@@ -150,18 +177,58 @@ impl File for Pipe {
             let loop_write = ring_buffer.available_write();
             if loop_write == 0 {
                 drop(ring_buffer);
-                suspend_current_and_run_next();
+                if has_pending_signal() {
+                    return if write_size > 0 {
+                        Ok(write_size)
+                    } else {
+                        Err(-EINTR)
+                    };
+                }
+                self.queue.wait(None);
                 continue;
             }
+            // 写入数据后读端可能变为可读
+            self.queue.wake_all();
             // write at most loop_write bytes
             for _ in 0..loop_write {
                 if let Some(byte_ref) = buf_iter.next() {
                     ring_buffer.write_byte(unsafe { *byte_ref });
                     write_size += 1;
                 } else {
-                    return write_size;
+                    return Ok(write_size);
                 }
             }
         }
     }
+    fn poll(&self) -> PollEvents {
+        let ring_buffer = self.buffer.lock();
+        let mut events = PollEvents::empty();
+        if self.readable {
+            if ring_buffer.available_read() > 0 {
+                events |= PollEvents::POLLIN;
+            }
+            if ring_buffer.all_write_ends_closed() {
+                events |= PollEvents::POLLHUP;
+            }
+        }
+        if self.writable {
+            if ring_buffer.available_write() > 0 {
+                events |= PollEvents::POLLOUT;
+            }
+            if ring_buffer.all_read_ends_closed() {
+                events |= PollEvents::POLLERR;
+            }
+        }
+        events
+    }
+    fn wait_queue(&self) -> Option<Arc<WaitQueue>> {
+        Some(self.queue.clone())
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        // 一端关闭后另一端会收到POLLHUP/POLLERR，阻塞的读者会读到文件尾
+        self.queue.wake_all();
+    }
 }
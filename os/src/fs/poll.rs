@@ -0,0 +1,152 @@
+use super::File;
+use crate::mm::UserBuffer;
+use crate::task::{wait_any, WaitQueue};
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use bitflags::*;
+use spin::Mutex;
+
+bitflags! {
+    /// 文件的就绪状态，与poll的events/revents取值一致
+    pub struct PollEvents: u16 {
+        const POLLIN = 1 << 0; // 可读
+        const POLLPRI = 1 << 1; // 有紧急数据
+        const POLLOUT = 1 << 2; // 可写
+        const POLLERR = 1 << 3; // 出错
+        const POLLHUP = 1 << 4; // 对端已关闭
+        const POLLNVAL = 1 << 5; // 文件描述符无效
+    }
+}
+
+/// 一次等待中被检查的文件的等待队列，任一文件就绪状态变化时唤醒等待者
+pub struct PollWaiters {
+    queues: Vec<Arc<WaitQueue>>,
+    /// 有文件的状态变化不会唤醒等待者，只能定期重新检查
+    pub polling: bool,
+}
+
+impl PollWaiters {
+    pub fn new() -> Self {
+        Self {
+            queues: Vec::new(),
+            polling: false,
+        }
+    }
+
+    /// 登记file的等待队列，多个文件共用的队列只登记一次
+    pub fn register(&mut self, file: &dyn File) {
+        match file.wait_queue() {
+            Some(queue) => {
+                if !self.queues.iter().any(|q| Arc::ptr_eq(q, &queue)) {
+                    self.queues.push(queue);
+                }
+            }
+            None => self.polling = true,
+        }
+    }
+
+    /// 睡眠直到任一登记的队列被唤醒，expire_ms不为None时最迟在该时刻返回
+    pub fn wait(&self, expire_ms: Option<usize>) {
+        let queues: Vec<&WaitQueue> = self.queues.iter().map(|queue| &**queue).collect();
+        wait_any(&queues, expire_ms);
+    }
+}
+
+/// epoll关注的一个文件，文件被关闭后自动失效
+#[derive(Clone)]
+pub struct EpollItem {
+    pub file: Weak<dyn File + Send + Sync>,
+    pub events: PollEvents,
+    pub data: u64,
+}
+
+/// epoll_create1创建的文件，记录被关注的文件描述符
+pub struct EventPoll {
+    items: Mutex<BTreeMap<usize, EpollItem>>,
+}
+
+impl EventPoll {
+    pub fn new() -> Self {
+        Self {
+            items: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// 添加关注的文件，已存在时返回false
+    pub fn add(&self, fd: usize, item: EpollItem) -> bool {
+        let mut items = self.items.lock();
+        if items.contains_key(&fd) {
+            return false;
+        }
+        items.insert(fd, item);
+        true
+    }
+
+    /// 修改关注的事件，不存在时返回false
+    pub fn modify(&self, fd: usize, events: PollEvents, data: u64) -> bool {
+        match self.items.lock().get_mut(&fd) {
+            Some(item) => {
+                item.events = events;
+                item.data = data;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 删除关注的文件，不存在时返回false
+    pub fn remove(&self, fd: usize) -> bool {
+        self.items.lock().remove(&fd).is_some()
+    }
+
+    /// 登记所有被关注文件的等待队列
+    pub fn register(&self, waiters: &mut PollWaiters) {
+        for item in self.items.lock().values() {
+            if let Some(file) = item.file.upgrade() {
+                waiters.register(&*file);
+            }
+        }
+    }
+
+    /// 返回所有已就绪的(data, revents)，最多max个
+    pub fn ready_items(&self, max: usize) -> Vec<(u64, PollEvents)> {
+        self.items
+            .lock()
+            .values()
+            .filter_map(|item| {
+                // POLLERR和POLLHUP总是会被报告
+                let revents = item.file.upgrade()?.poll()
+                    & (item.events | PollEvents::POLLERR | PollEvents::POLLHUP);
+                if revents.is_empty() {
+                    None
+                } else {
+                    Some((item.data, revents))
+                }
+            })
+            .take(max)
+            .collect()
+    }
+}
+
+impl File for EventPoll {
+    fn readable(&self) -> bool {
+        false
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn read(&self, _buf: UserBuffer) -> Result<usize, isize> {
+        Ok(0)
+    }
+    fn write(&self, _buf: UserBuffer) -> Result<usize, isize> {
+        Ok(0)
+    }
+    fn poll(&self) -> PollEvents {
+        if self.ready_items(1).is_empty() {
+            PollEvents::empty()
+        } else {
+            PollEvents::POLLIN
+        }
+    }
+}
@@ -0,0 +1,495 @@
+//! 反映进程和内核状态的procfs
+//!
+//! 文件内容在每次读取时生成，进程目录随进程的创建和回收出现和消失。
+use super::mount::mount_list;
+use super::vfs::{DirItem, FileSystem, Inode, InodeStat, InodeType};
+use super::FileType;
+use crate::config::PAGE_SIZE;
+use crate::mm::{frame_stats, heap_stats, MapPermission};
+use crate::syscall::errno::*;
+use crate::task::{current_task, idle_time_us, pid2task, task_pids, TaskStatus};
+use crate::timer::get_time_us;
+use crate::trap::interrupt_counts;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::any::Any;
+use core::fmt::Write;
+use spin::Mutex;
+
+/// /proc/<pid>/下的文件
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PidEntry {
+    Stat,
+    Status,
+    Cmdline,
+    Maps,
+    Fd,
+    Cwd,
+    Exe,
+}
+
+const PID_ENTRIES: [(&str, PidEntry); 7] = [
+    ("stat", PidEntry::Stat),
+    ("status", PidEntry::Status),
+    ("cmdline", PidEntry::Cmdline),
+    ("maps", PidEntry::Maps),
+    ("fd", PidEntry::Fd),
+    ("cwd", PidEntry::Cwd),
+    ("exe", PidEntry::Exe),
+];
+
+/// /proc/下与进程无关的文件
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum KernelEntry {
+    SelfLink,
+    Meminfo,
+    Uptime,
+    Mounts,
+    Interrupts,
+}
+
+const KERNEL_ENTRIES: [(&str, KernelEntry); 5] = [
+    ("self", KernelEntry::SelfLink),
+    ("meminfo", KernelEntry::Meminfo),
+    ("uptime", KernelEntry::Uptime),
+    ("mounts", KernelEntry::Mounts),
+    ("interrupts", KernelEntry::Interrupts),
+];
+
+/// readdir中.和..之后的第一个offset
+const FIRST_ENTRY: usize = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ProcNode {
+    Root,
+    Kernel(KernelEntry),
+    Pid(usize),
+    PidEntry(usize, PidEntry),
+    Fd(usize, usize),
+}
+
+impl ProcNode {
+    fn kind(&self) -> InodeType {
+        match self {
+            ProcNode::Root | ProcNode::Pid(_) | ProcNode::PidEntry(_, PidEntry::Fd) => {
+                InodeType::Directory
+            }
+            ProcNode::Kernel(KernelEntry::SelfLink)
+            | ProcNode::PidEntry(_, PidEntry::Cwd)
+            | ProcNode::PidEntry(_, PidEntry::Exe)
+            | ProcNode::Fd(_, _) => InodeType::SymLink,
+            _ => InodeType::File,
+        }
+    }
+
+    /// 由节点推出的inode号，进程相关的节点以pid为高位
+    fn ino(&self) -> u64 {
+        let pid_base = |pid: usize| ((pid as u64) + 1) << 16;
+        match *self {
+            ProcNode::Root => 1,
+            ProcNode::Kernel(entry) => 2 + entry as u64,
+            ProcNode::Pid(pid) => pid_base(pid),
+            ProcNode::PidEntry(pid, entry) => pid_base(pid) + 1 + entry as u64,
+            ProcNode::Fd(pid, fd) => pid_base(pid) + 0x100 + fd as u64,
+        }
+    }
+
+    fn pid(&self) -> Option<usize> {
+        match *self {
+            ProcNode::Pid(pid) | ProcNode::PidEntry(pid, _) | ProcNode::Fd(pid, _) => Some(pid),
+            _ => None,
+        }
+    }
+}
+
+pub struct ProcFs {
+    this: Mutex<Weak<ProcFs>>,
+}
+
+impl ProcFs {
+    pub fn new() -> Arc<Self> {
+        let fs = Arc::new(Self {
+            this: Mutex::new(Weak::new()),
+        });
+        *fs.this.lock() = Arc::downgrade(&fs);
+        fs
+    }
+
+    fn node(&self, node: ProcNode) -> Arc<dyn Inode> {
+        Arc::new(ProcInode {
+            fs: self.this.lock().upgrade().unwrap(),
+            node,
+        })
+    }
+}
+
+impl FileSystem for ProcFs {
+    fn fs_type(&self) -> &'static str {
+        "proc"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.node(ProcNode::Root)
+    }
+
+    fn sync(&self) -> Result<(), isize> {
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct ProcInode {
+    fs: Arc<ProcFs>,
+    node: ProcNode,
+}
+
+/// 进程状态在stat和status中的表示
+fn state_name(status: TaskStatus) -> (char, &'static str) {
+    match status {
+        TaskStatus::Ready | TaskStatus::Running => ('R', "running"),
+        TaskStatus::Blocking => ('S', "sleeping"),
+        TaskStatus::Zombie => ('Z', "zombie"),
+        TaskStatus::Stopped => ('T', "stopped"),
+    }
+}
+
+/// 进程名为程序文件名，最长15个字符
+fn task_name(exe: &str) -> String {
+    let name = exe.rsplit('/').next().unwrap_or(exe);
+    name.chars().take(15).collect()
+}
+
+/// 以秒为单位、保留两位小数的时间
+fn format_secs(us: usize) -> String {
+    format!("{}.{:02}", us / 1_000_000, us % 1_000_000 / 10_000)
+}
+
+fn meminfo() -> String {
+    let (total_frames, free_frames) = frame_stats();
+    let (heap_total, heap_used) = heap_stats();
+    let kb = |pages: usize| pages * PAGE_SIZE / 1024;
+    let mut text = String::new();
+    let _ = writeln!(text, "MemTotal:       {:8} kB", kb(total_frames));
+    let _ = writeln!(text, "MemFree:        {:8} kB", kb(free_frames));
+    let _ = writeln!(text, "MemAvailable:   {:8} kB", kb(free_frames));
+    let _ = writeln!(text, "KernelHeap:     {:8} kB", heap_total / 1024);
+    let _ = writeln!(text, "KernelHeapUsed: {:8} kB", heap_used / 1024);
+    text
+}
+
+fn mounts() -> String {
+    let mut text = String::new();
+    for (source, path, fs_type) in mount_list() {
+        let _ = writeln!(text, "{} {} {} rw 0 0", source, path, fs_type);
+    }
+    text
+}
+
+fn interrupts() -> String {
+    let mut text = String::from("           CPU0\n");
+    for (irq, count, name) in interrupt_counts() {
+        let _ = writeln!(text, "{:4}: {:10}  {}", irq, count, name);
+    }
+    text
+}
+
+/// 生成进程文件的内容，进程已经被回收时返回ESRCH
+fn pid_file(pid: usize, entry: PidEntry) -> Result<String, isize> {
+    let task = pid2task(pid).ok_or(-ESRCH)?;
+    let inner = task.inner_exclusive_access();
+    let ppid = match inner.parent.as_ref().and_then(Weak::upgrade) {
+        Some(parent) => parent.getpid(),
+        None => 0,
+    };
+    let areas = inner.memory_set.area_info();
+    let vm_pages: usize = areas
+        .iter()
+        .map(|(start, end, _, _)| (end.0 - start.0) / PAGE_SIZE)
+        .sum();
+    let rss_pages: usize = areas.iter().map(|(_, _, _, resident)| resident).sum();
+    let name = task_name(inner.exe.as_str());
+    let (state, state_desc) = state_name(inner.task_status);
+    let mut text = String::new();
+    match entry {
+        PidEntry::Stat => {
+            // 没有统计的字段填0
+            let _ = writeln!(
+                text,
+                "{} ({}) {} {} {} {} 0 0 0 0 0 0 0 0 0 0 0 0 20 0 1 0 0 {} {}",
+                pid,
+                name,
+                state,
+                ppid,
+                inner.pgid,
+                pid,
+                vm_pages * PAGE_SIZE,
+                rss_pages
+            );
+        }
+        PidEntry::Status => {
+            let _ = writeln!(text, "Name:\t{}", name);
+            let _ = writeln!(text, "State:\t{} ({})", state, state_desc);
+            let _ = writeln!(text, "Pid:\t{}", pid);
+            let _ = writeln!(text, "PPid:\t{}", ppid);
+            let _ = writeln!(text, "FDSize:\t{}", inner.fd_table.len());
+            let _ = writeln!(text, "VmSize:\t{:8} kB", vm_pages * PAGE_SIZE / 1024);
+            let _ = writeln!(text, "VmRSS:\t{:8} kB", rss_pages * PAGE_SIZE / 1024);
+            let _ = writeln!(text, "Threads:\t1");
+        }
+        PidEntry::Cmdline => {
+            // 各参数以'\0'结尾，僵尸进程为空
+            if inner.task_status != TaskStatus::Zombie {
+                for arg in inner.args.iter() {
+                    text.push_str(arg);
+                    text.push('\0');
+                }
+            }
+        }
+        PidEntry::Maps => {
+            for (start, end, perm, _) in areas {
+                let flag = |bit: MapPermission, c: char| if perm.contains(bit) { c } else { '-' };
+                let _ = writeln!(
+                    text,
+                    "{:08x}-{:08x} {}{}{}p 00000000 00:00 0",
+                    start.0,
+                    end.0,
+                    flag(MapPermission::R, 'r'),
+                    flag(MapPermission::W, 'w'),
+                    flag(MapPermission::X, 'x'),
+                );
+            }
+        }
+        _ => return Err(-EISDIR),
+    }
+    Ok(text)
+}
+
+/// 进程的文件描述符表中已使用的描述符
+fn task_fds(pid: usize) -> Result<Vec<usize>, isize> {
+    let task = pid2task(pid).ok_or(-ESRCH)?;
+    let inner = task.inner_exclusive_access();
+    Ok(inner
+        .fd_table
+        .iter()
+        .enumerate()
+        .filter(|(_, fd)| fd.is_some())
+        .map(|(fd, _)| fd)
+        .collect())
+}
+
+impl ProcInode {
+    /// 生成普通文件的内容
+    fn content(&self) -> Result<String, isize> {
+        match self.node {
+            ProcNode::Kernel(KernelEntry::Meminfo) => Ok(meminfo()),
+            ProcNode::Kernel(KernelEntry::Uptime) => Ok(format!(
+                "{} {}\n",
+                format_secs(get_time_us()),
+                format_secs(idle_time_us())
+            )),
+            ProcNode::Kernel(KernelEntry::Mounts) => Ok(mounts()),
+            ProcNode::Kernel(KernelEntry::Interrupts) => Ok(interrupts()),
+            ProcNode::PidEntry(pid, entry) => pid_file(pid, entry),
+            _ => Err(-EISDIR),
+        }
+    }
+
+    /// 进程已经被回收时，其下的文件全部失效
+    fn check_alive(&self) -> Result<(), isize> {
+        match self.node.pid() {
+            Some(pid) if pid2task(pid).is_none() => Err(-ENOENT),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Inode for ProcInode {
+    fn fs(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    fn kind(&self) -> InodeType {
+        self.node.kind()
+    }
+
+    fn stat(&self) -> Result<InodeStat, isize> {
+        self.check_alive()?;
+        let kind = self.kind();
+        Ok(InodeStat {
+            ino: self.node.ino(),
+            kind,
+            mode: match kind {
+                InodeType::Directory => 0o555,
+                InodeType::SymLink => 0o777,
+                _ => 0o444,
+            },
+            size: 0, // 内容在读取时生成，与Linux一样报告为0
+            blocks: 0,
+            nlink: if kind == InodeType::Directory { 2 } else { 1 },
+            rdev: 0,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, isize> {
+        self.check_alive()?;
+        let node = match self.node {
+            ProcNode::Root => {
+                if let Some((_, entry)) = KERNEL_ENTRIES.iter().find(|(n, _)| *n == name) {
+                    ProcNode::Kernel(*entry)
+                } else {
+                    let pid = name.parse::<usize>().map_err(|_| -ENOENT)?;
+                    pid2task(pid).ok_or(-ENOENT)?;
+                    ProcNode::Pid(pid)
+                }
+            }
+            ProcNode::Pid(pid) => match PID_ENTRIES.iter().find(|(n, _)| *n == name) {
+                Some((_, entry)) => ProcNode::PidEntry(pid, *entry),
+                None => return Err(-ENOENT),
+            },
+            ProcNode::PidEntry(pid, PidEntry::Fd) => {
+                let fd = name.parse::<usize>().map_err(|_| -ENOENT)?;
+                if !task_fds(pid)?.contains(&fd) {
+                    return Err(-ENOENT);
+                }
+                ProcNode::Fd(pid, fd)
+            }
+            _ => return Err(-ENOTDIR),
+        };
+        Ok(self.fs.node(node))
+    }
+
+    fn create(&self, _name: &str, _kind: InodeType) -> Result<Arc<dyn Inode>, isize> {
+        Err(-EACCES)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<(), isize> {
+        Err(-EACCES)
+    }
+
+    fn rmdir(&self, _name: &str) -> Result<(), isize> {
+        Err(-EACCES)
+    }
+
+    fn rename(
+        &self,
+        _old_name: &str,
+        _new_dir: &Arc<dyn Inode>,
+        _new_name: &str,
+        _flags: u32,
+    ) -> Result<(), isize> {
+        Err(-EACCES)
+    }
+
+    fn readdir(&self, offset: usize) -> Result<Option<(DirItem, usize)>, isize> {
+        self.check_alive()?;
+        if self.kind() != InodeType::Directory {
+            return Err(-ENOTDIR);
+        }
+        let dot = |name: &str| DirItem {
+            name: String::from(name),
+            ino: self.node.ino(),
+            kind: InodeType::Directory,
+        };
+        if offset < FIRST_ENTRY {
+            let name = if offset == 0 { "." } else { ".." };
+            return Ok(Some((dot(name), offset + 1)));
+        }
+        let index = offset - FIRST_ENTRY;
+        let item = |name: String, node: ProcNode| DirItem {
+            name,
+            ino: node.ino(),
+            kind: node.kind(),
+        };
+        match self.node {
+            ProcNode::Root => {
+                if let Some((name, entry)) = KERNEL_ENTRIES.get(index) {
+                    let node = ProcNode::Kernel(*entry);
+                    return Ok(Some((item(String::from(*name), node), offset + 1)));
+                }
+                // 之后的offset为pid加上固定项数，进程退出后也能从原位置继续
+                let base = FIRST_ENTRY + KERNEL_ENTRIES.len();
+                let pid = task_pids().into_iter().find(|pid| pid + base >= offset);
+                Ok(pid.map(|pid| {
+                    let node = ProcNode::Pid(pid);
+                    (item(pid.to_string(), node), base + pid + 1)
+                }))
+            }
+            ProcNode::Pid(pid) => Ok(PID_ENTRIES.get(index).map(|(name, entry)| {
+                let node = ProcNode::PidEntry(pid, *entry);
+                (item(String::from(*name), node), offset + 1)
+            })),
+            ProcNode::PidEntry(pid, _) => {
+                // offset为描述符加上固定项数
+                let fd = task_fds(pid)?.into_iter().find(|fd| *fd >= index);
+                Ok(fd.map(|fd| {
+                    let node = ProcNode::Fd(pid, fd);
+                    (item(fd.to_string(), node), FIRST_ENTRY + fd + 1)
+                }))
+            }
+            _ => Err(-ENOTDIR),
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        let content = self.content()?;
+        let content = content.as_bytes();
+        if offset >= content.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(content.len() - offset);
+        buf[..len].copy_from_slice(&content[offset..offset + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize, isize> {
+        Err(-EACCES)
+    }
+
+    fn truncate(&self, _size: usize) -> Result<(), isize> {
+        Err(-EACCES)
+    }
+
+    fn read_link(&self) -> Result<String, isize> {
+        match self.node {
+            ProcNode::Kernel(KernelEntry::SelfLink) => {
+                let task = current_task().ok_or(-ENOENT)?;
+                Ok(task.getpid().to_string())
+            }
+            ProcNode::PidEntry(pid, entry) => {
+                let task = pid2task(pid).ok_or(-ENOENT)?;
+                let inner = task.inner_exclusive_access();
+                match entry {
+                    PidEntry::Cwd => Ok(inner.current_path.clone()),
+                    PidEntry::Exe => Ok(inner.exe.clone()),
+                    _ => Err(-EINVAL),
+                }
+            }
+            ProcNode::Fd(pid, fd) => {
+                let task = pid2task(pid).ok_or(-ENOENT)?;
+                let ftype = match task.inner_exclusive_access().fd_table.get(fd) {
+                    Some(Some(descriptor)) => descriptor.ftype.clone(),
+                    _ => return Err(-ENOENT),
+                };
+                // 在释放进程的锁之后访问文件
+                Ok(match ftype {
+                    FileType::File(inode) => inode.dentry().path(),
+                    FileType::Abstr(_) => format!("anon_inode:[{}]", fd),
+                    FileType::Epoll(_) => String::from("anon_inode:[eventpoll]"),
+                })
+            }
+            _ => Err(-EINVAL),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
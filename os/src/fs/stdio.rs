@@ -1,8 +1,22 @@
-use super::File;
+use super::tty::TTY;
+use super::{File, PollEvents};
+use crate::drivers::{console_getchar_nb, console_input_queue, console_putbytes};
 use crate::mm::UserBuffer;
-use crate::sbi::console_getchar;
-use crate::task::suspend_current_and_run_next;
+use crate::task::WaitQueue;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
 
+/// 从控制台读取一个字符，没有输入时返回None
+pub fn console_try_read() -> Option<u8> {
+    console_getchar_nb()
+}
+
+pub fn console_write(bytes: &[u8]) {
+    console_putbytes(bytes);
+}
+
+/// 没有/dev/console时使用的标准输入输出，读写都经过控制台终端
 pub struct Stdin;
 
 pub struct Stdout;
@@ -14,28 +28,28 @@ impl File for Stdin {
     fn writable(&self) -> bool {
         false
     }
-    fn read(&self, mut user_buf: UserBuffer) -> usize {
-        assert_eq!(user_buf.len(), 1);
-        // busy loop
-        let mut c: usize;
-        loop {
-            c = console_getchar();
-            if c == 0 {
-                suspend_current_and_run_next();
-                continue;
-            } else {
-                break;
-            }
-        }
-        let ch = c as u8;
-        unsafe {
-            user_buf.buffers[0].as_mut_ptr().write_volatile(ch);
-        }
-        1
+    fn read(&self, mut user_buf: UserBuffer) -> Result<usize, isize> {
+        // 一次读入整个缓冲区，终端的一行不会被拆开
+        let mut buf = vec![0u8; user_buf.len()];
+        let len = TTY.read(&mut buf)?;
+        Ok(user_buf.write(&buf[..len]))
     }
-    fn write(&self, _user_buf: UserBuffer) -> usize {
+    fn write(&self, _user_buf: UserBuffer) -> Result<usize, isize> {
         panic!("Cannot write to stdin!");
     }
+    fn poll(&self) -> PollEvents {
+        if TTY.readable() {
+            PollEvents::POLLIN
+        } else {
+            PollEvents::empty()
+        }
+    }
+    fn wait_queue(&self) -> Option<Arc<WaitQueue>> {
+        console_input_queue()
+    }
+    fn ioctl(&self, cmd: usize, arg: usize) -> Result<isize, isize> {
+        TTY.ioctl(cmd, arg)
+    }
 }
 
 impl File for Stdout {
@@ -45,13 +59,21 @@ impl File for Stdout {
     fn writable(&self) -> bool {
         true
     }
-    fn read(&self, _user_buf: UserBuffer) -> usize {
+    fn read(&self, _user_buf: UserBuffer) -> Result<usize, isize> {
         panic!("Cannot read from stdout!");
     }
-    fn write(&self, user_buf: UserBuffer) -> usize {
-        for buffer in user_buf.buffers.iter() {
-            print!("{}", core::str::from_utf8(*buffer).unwrap());
-        }
-        user_buf.len()
+    fn write(&self, user_buf: UserBuffer) -> Result<usize, isize> {
+        let buf: Vec<u8> = user_buf
+            .buffers
+            .iter()
+            .flat_map(|buffer| buffer.iter().copied())
+            .collect();
+        Ok(TTY.write(&buf))
+    }
+    fn poll(&self) -> PollEvents {
+        PollEvents::POLLOUT
+    }
+    fn ioctl(&self, cmd: usize, arg: usize) -> Result<isize, isize> {
+        TTY.ioctl(cmd, arg)
     }
 }
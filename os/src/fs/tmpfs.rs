@@ -0,0 +1,594 @@
+//! 以内存页保存数据的tmpfs
+//!
+//! 文件数据按页存放在frame_alloc分配的物理页中，没有写过的页不分配，读出为0。
+//! 目录直接以名称到inode的映射保存子项，硬链接即多个名称指向同一个inode。
+use super::vfs::{
+    DirItem, FileSystem, Inode, InodeStat, InodeType, RENAME_EXCHANGE, RENAME_NOREPLACE,
+};
+use crate::config::PAGE_SIZE;
+use crate::mm::{frame_alloc, FrameTracker};
+use crate::syscall::errno::*;
+use crate::timer::get_wall_time;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use core::any::Any;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use spin::{Mutex, MutexGuard};
+
+/// 目录的大小按每个目录项20字节计算，与Linux一致
+const BOGO_DIRENT_SIZE: u64 = 20;
+
+fn now() -> u64 {
+    get_wall_time().sec as u64
+}
+
+pub struct TmpFs {
+    /// 文件数据最多占用的页数
+    max_pages: usize,
+    used_pages: AtomicUsize,
+    next_ino: AtomicU64,
+    /// 卸载时置为None，打破根目录与文件系统之间的循环引用
+    root: Mutex<Option<Arc<TmpInode>>>,
+}
+
+impl TmpFs {
+    /// 新建一个最多使用max_pages页的tmpfs，根目录的权限为root_mode
+    pub fn new(max_pages: usize, root_mode: u32) -> Arc<Self> {
+        let fs = Arc::new(Self {
+            max_pages,
+            used_pages: AtomicUsize::new(0),
+            next_ino: AtomicU64::new(1),
+            root: Mutex::new(None),
+        });
+        let root = TmpInode::new(&fs, InodeType::Directory, root_mode, 0);
+        root.inner.lock().parent_ino = root.ino; // 根目录的..是它自己
+        *fs.root.lock() = Some(root);
+        fs
+    }
+
+    /// 按挂载选项新建tmpfs，支持size=<字节数>[k|m|g]和mode=<八进制权限>
+    pub fn with_options(data: &str, default_pages: usize) -> Result<Arc<Self>, isize> {
+        let mut max_pages = default_pages;
+        let mut root_mode = 0o1777;
+        for option in data.split(',').filter(|option| !option.is_empty()) {
+            let (key, value) = option.split_once('=').ok_or(-EINVAL)?;
+            match key {
+                "size" => {
+                    let (digits, shift) = match value.as_bytes().last() {
+                        Some(b'k') | Some(b'K') => (&value[..value.len() - 1], 10),
+                        Some(b'm') | Some(b'M') => (&value[..value.len() - 1], 20),
+                        Some(b'g') | Some(b'G') => (&value[..value.len() - 1], 30),
+                        _ => (value, 0),
+                    };
+                    let bytes = digits.parse::<usize>().map_err(|_| -EINVAL)?;
+                    let bytes = bytes.checked_mul(1 << shift).ok_or(-EINVAL)?;
+                    max_pages = (bytes + PAGE_SIZE - 1) / PAGE_SIZE;
+                }
+                "mode" => {
+                    root_mode = u32::from_str_radix(value, 8).map_err(|_| -EINVAL)? & 0o7777;
+                }
+                _ => return Err(-EINVAL),
+            }
+        }
+        Ok(Self::new(max_pages, root_mode))
+    }
+
+    fn alloc_ino(&self) -> u64 {
+        self.next_ino.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// 为一页数据记账并分配物理页，超出大小限制时返回ENOSPC
+    fn alloc_page(&self) -> Result<FrameTracker, isize> {
+        let charged = self
+            .used_pages
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                if used < self.max_pages {
+                    Some(used + 1)
+                } else {
+                    None
+                }
+            });
+        if charged.is_err() {
+            return Err(-ENOSPC);
+        }
+        frame_alloc().ok_or_else(|| {
+            self.used_pages.fetch_sub(1, Ordering::Relaxed);
+            -ENOMEM
+        })
+    }
+
+    /// 释放pages页后撤销记账，物理页由FrameTracker自行回收
+    fn free_pages(&self, pages: usize) {
+        self.used_pages.fetch_sub(pages, Ordering::Relaxed);
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn fs_type(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.lock().clone().unwrap()
+    }
+
+    fn sync(&self) -> Result<(), isize> {
+        Ok(()) // 数据只在内存中
+    }
+
+    fn unmount(&self) {
+        // 仍被打开的文件各自持有自己的inode，可以继续访问
+        self.root.lock().take();
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct TmpInode {
+    fs: Arc<TmpFs>,
+    /// 自身的弱引用，建立硬链接时使用
+    this: Weak<TmpInode>,
+    ino: u64,
+    kind: InodeType,
+    inner: Mutex<TmpInodeInner>,
+}
+
+struct TmpInodeInner {
+    mode: u32,
+    nlink: u32,
+    size: usize,
+    atime: u64,
+    mtime: u64,
+    ctime: u64,
+    /// 文件数据，以页号为键，没有写过的页不在其中
+    pages: BTreeMap<usize, FrameTracker>,
+    /// 目录的子项
+    children: BTreeMap<String, Arc<TmpInode>>,
+    /// 父目录的inode号，readdir返回..时使用
+    parent_ino: u64,
+    /// 符号链接的目标
+    target: String,
+}
+
+impl TmpInode {
+    fn new(fs: &Arc<TmpFs>, kind: InodeType, mode: u32, parent_ino: u64) -> Arc<Self> {
+        let time = now();
+        Arc::new_cyclic(|this| Self {
+            fs: fs.clone(),
+            this: this.clone(),
+            ino: fs.alloc_ino(),
+            kind,
+            inner: Mutex::new(TmpInodeInner {
+                mode,
+                nlink: if kind == InodeType::Directory { 2 } else { 1 },
+                size: 0,
+                atime: time,
+                mtime: time,
+                ctime: time,
+                pages: BTreeMap::new(),
+                children: BTreeMap::new(),
+                parent_ino,
+                target: String::new(),
+            }),
+        })
+    }
+
+    /// 锁住目录，self不是目录时返回ENOTDIR
+    fn lock_dir(&self) -> Result<MutexGuard<'_, TmpInodeInner>, isize> {
+        if self.kind != InodeType::Directory {
+            return Err(-ENOTDIR);
+        }
+        Ok(self.inner.lock())
+    }
+
+    /// 在目录中加入新建的inode
+    fn add(&self, name: &str, kind: InodeType, mode: u32) -> Result<Arc<TmpInode>, isize> {
+        let mut inner = self.lock_dir()?;
+        if inner.children.contains_key(name) {
+            return Err(-EEXIST);
+        }
+        let inode = TmpInode::new(&self.fs, kind, mode, self.ino);
+        if kind == InodeType::Directory {
+            inner.nlink += 1; // 子目录的..
+        }
+        inner.children.insert(String::from(name), inode.clone());
+        inner.touch_dir();
+        Ok(inode)
+    }
+}
+
+impl TmpInodeInner {
+    /// 目录内容改变时更新时间
+    fn touch_dir(&mut self) {
+        let time = now();
+        self.mtime = time;
+        self.ctime = time;
+    }
+}
+
+/// 名称被删除或覆盖后减少inode的链接数
+fn drop_link(inode: &TmpInode) {
+    let mut inner = inode.inner.lock();
+    if inode.kind == InodeType::Directory {
+        inner.nlink = 0;
+    } else {
+        inner.nlink -= 1;
+    }
+    inner.ctime = now();
+}
+
+impl Inode for TmpInode {
+    fn fs(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    fn kind(&self) -> InodeType {
+        self.kind
+    }
+
+    fn stat(&self) -> Result<InodeStat, isize> {
+        let inner = self.inner.lock();
+        let size = match self.kind {
+            InodeType::Directory => (inner.children.len() as u64 + 2) * BOGO_DIRENT_SIZE,
+            _ => inner.size as u64,
+        };
+        Ok(InodeStat {
+            ino: self.ino,
+            kind: self.kind,
+            mode: inner.mode,
+            size,
+            blocks: (inner.pages.len() * PAGE_SIZE / 512) as u64,
+            nlink: inner.nlink,
+            rdev: 0,
+            atime: inner.atime,
+            mtime: inner.mtime,
+            ctime: inner.ctime,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, isize> {
+        match self.lock_dir()?.children.get(name) {
+            Some(inode) => Ok(inode.clone()),
+            None => Err(-ENOENT),
+        }
+    }
+
+    fn create(&self, name: &str, kind: InodeType) -> Result<Arc<dyn Inode>, isize> {
+        let mode = match kind {
+            InodeType::Directory => 0o755,
+            InodeType::SymLink => return Err(-EINVAL), // 符号链接由symlink创建
+            _ => 0o644,
+        };
+        Ok(self.add(name, kind, mode)?)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, isize> {
+        let inode = self.add(name, InodeType::SymLink, 0o777)?;
+        let mut inner = inode.inner.lock();
+        inner.target = String::from(target);
+        inner.size = target.len();
+        drop(inner);
+        Ok(inode)
+    }
+
+    fn link(&self, name: &str, target: &Arc<dyn Inode>) -> Result<(), isize> {
+        let target = match target.as_any().downcast_ref::<TmpInode>() {
+            Some(inode) => inode.this.upgrade().unwrap(),
+            None => return Err(-EXDEV),
+        };
+        if target.kind == InodeType::Directory {
+            return Err(-EPERM);
+        }
+        let mut inner = self.lock_dir()?;
+        if inner.children.contains_key(name) {
+            return Err(-EEXIST);
+        }
+        let mut target_inner = target.inner.lock();
+        if target_inner.nlink == 0 {
+            return Err(-ENOENT); // 已经被删除的文件不能再链接
+        }
+        target_inner.nlink += 1;
+        target_inner.ctime = now();
+        drop(target_inner);
+        inner.children.insert(String::from(name), target);
+        inner.touch_dir();
+        Ok(())
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), isize> {
+        let mut inner = self.lock_dir()?;
+        match inner.children.get(name) {
+            Some(inode) if inode.kind == InodeType::Directory => return Err(-EISDIR),
+            Some(_) => {}
+            None => return Err(-ENOENT),
+        }
+        // 仍被打开的文件在最后一个引用释放时回收数据页
+        let inode = inner.children.remove(name).unwrap();
+        inner.touch_dir();
+        drop(inner);
+        drop_link(&inode);
+        Ok(())
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), isize> {
+        let mut inner = self.lock_dir()?;
+        match inner.children.get(name) {
+            Some(inode) if inode.kind != InodeType::Directory => return Err(-ENOTDIR),
+            Some(inode) if !inode.inner.lock().children.is_empty() => return Err(-ENOTEMPTY),
+            Some(_) => {}
+            None => return Err(-ENOENT),
+        }
+        let inode = inner.children.remove(name).unwrap();
+        inner.nlink -= 1;
+        inner.touch_dir();
+        drop(inner);
+        drop_link(&inode);
+        Ok(())
+    }
+
+    fn rename(
+        &self,
+        old_name: &str,
+        new_dir: &Arc<dyn Inode>,
+        new_name: &str,
+        flags: u32,
+    ) -> Result<(), isize> {
+        let new_dir = match new_dir.as_any().downcast_ref::<TmpInode>() {
+            Some(inode) => inode,
+            None => return Err(-EXDEV),
+        };
+        if new_dir.kind != InodeType::Directory {
+            return Err(-ENOTDIR);
+        }
+        // 两个目录按inode号从小到大加锁，同一目录只锁一次
+        let same_dir = self.ino == new_dir.ino;
+        let (mut old_inner, mut new_inner) = if same_dir {
+            (self.lock_dir()?, None)
+        } else if self.ino < new_dir.ino {
+            let old_inner = self.lock_dir()?;
+            (old_inner, Some(new_dir.inner.lock()))
+        } else {
+            let new_inner = new_dir.inner.lock();
+            (self.lock_dir()?, Some(new_inner))
+        };
+        macro_rules! new_inner {
+            () => {
+                match new_inner.as_deref_mut() {
+                    Some(inner) => inner,
+                    None => &mut *old_inner,
+                }
+            };
+        }
+
+        let old = old_inner.children.get(old_name).cloned().ok_or(-ENOENT)?;
+        let target = new_inner!().children.get(new_name).cloned();
+        let old_is_dir = old.kind == InodeType::Directory;
+
+        if flags & RENAME_EXCHANGE != 0 {
+            let target = target.ok_or(-ENOENT)?;
+            let target_is_dir = target.kind == InodeType::Directory;
+            if !same_dir && old_is_dir != target_is_dir {
+                // 子目录的..随之移动
+                if old_is_dir {
+                    old_inner.nlink -= 1;
+                    new_inner!().nlink += 1;
+                } else {
+                    old_inner.nlink += 1;
+                    new_inner!().nlink -= 1;
+                }
+            }
+            if old_is_dir {
+                old.inner.lock().parent_ino = new_dir.ino;
+            }
+            if target_is_dir {
+                target.inner.lock().parent_ino = self.ino;
+            }
+            old_inner.children.insert(String::from(old_name), target);
+            new_inner!().children.insert(String::from(new_name), old);
+            old_inner.touch_dir();
+            new_inner!().touch_dir();
+            return Ok(());
+        }
+
+        if let Some(target) = target.as_ref() {
+            if Arc::ptr_eq(&old, target) {
+                return Ok(()); // 同一个文件的两个硬链接
+            }
+            if flags & RENAME_NOREPLACE != 0 {
+                return Err(-EEXIST);
+            }
+            if target.ino == self.ino {
+                // 目标是原目录本身，它至少包含old
+                return Err(if old_is_dir { -ENOTEMPTY } else { -EISDIR });
+            }
+            if old_is_dir {
+                if target.kind != InodeType::Directory {
+                    return Err(-ENOTDIR);
+                }
+                if !target.inner.lock().children.is_empty() {
+                    return Err(-ENOTEMPTY);
+                }
+                new_inner!().nlink -= 1; // 被覆盖的目录的..
+            } else if target.kind == InodeType::Directory {
+                return Err(-EISDIR);
+            }
+        }
+        old_inner.children.remove(old_name);
+        if old_is_dir {
+            old_inner.nlink -= 1;
+            new_inner!().nlink += 1;
+            old.inner.lock().parent_ino = new_dir.ino;
+        }
+        old.inner.lock().ctime = now();
+        new_inner!().children.insert(String::from(new_name), old);
+        old_inner.touch_dir();
+        new_inner!().touch_dir();
+        drop(old_inner);
+        drop(new_inner);
+        if let Some(target) = target {
+            drop_link(&target);
+        }
+        Ok(())
+    }
+
+    fn readdir(&self, offset: usize) -> Result<Option<(DirItem, usize)>, isize> {
+        let inner = self.lock_dir()?;
+        let item = match offset {
+            0 => DirItem {
+                name: String::from("."),
+                ino: self.ino,
+                kind: InodeType::Directory,
+            },
+            1 => DirItem {
+                name: String::from(".."),
+                ino: inner.parent_ino,
+                kind: InodeType::Directory,
+            },
+            _ => match inner.children.iter().nth(offset - 2) {
+                Some((name, inode)) => DirItem {
+                    name: name.clone(),
+                    ino: inode.ino,
+                    kind: inode.kind,
+                },
+                None => return Ok(None),
+            },
+        };
+        Ok(Some((item, offset + 1)))
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        if self.kind == InodeType::Directory {
+            return Err(-EISDIR);
+        }
+        let inner = self.inner.lock();
+        if offset >= inner.size {
+            return Ok(0);
+        }
+        let end = inner.size.min(offset + buf.len());
+        let mut pos = offset;
+        while pos < end {
+            let page_off = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_off).min(end - pos);
+            let dst = &mut buf[pos - offset..pos - offset + len];
+            match inner.pages.get(&(pos / PAGE_SIZE)) {
+                Some(frame) => {
+                    dst.copy_from_slice(&frame.ppn.get_bytes_array()[page_off..page_off + len])
+                }
+                None => dst.fill(0), // 空洞
+            }
+            pos += len;
+        }
+        Ok(end - offset)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, isize> {
+        if self.kind == InodeType::Directory {
+            return Err(-EISDIR);
+        }
+        let end = offset.checked_add(buf.len()).ok_or(-EINVAL)?;
+        let mut inner = self.inner.lock();
+        let mut pos = offset;
+        while pos < end {
+            let page_off = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_off).min(end - pos);
+            let index = pos / PAGE_SIZE;
+            if !inner.pages.contains_key(&index) {
+                match self.fs.alloc_page() {
+                    Ok(frame) => {
+                        inner.pages.insert(index, frame);
+                    }
+                    Err(_) if pos > offset => break, // 返回已写入的部分
+                    Err(errno) => return Err(errno),
+                }
+            }
+            let page = inner.pages[&index].ppn.get_bytes_array();
+            page[page_off..page_off + len].copy_from_slice(&buf[pos - offset..pos - offset + len]);
+            pos += len;
+        }
+        inner.size = inner.size.max(pos);
+        Ok(pos - offset)
+    }
+
+    fn truncate(&self, size: usize) -> Result<(), isize> {
+        match self.kind {
+            InodeType::Directory => return Err(-EISDIR),
+            InodeType::File => {}
+            _ => return Err(-EINVAL),
+        }
+        let mut inner = self.inner.lock();
+        if size < inner.size {
+            // 释放新末尾之后的整页，并将末尾所在页的剩余部分清零
+            let removed = inner.pages.split_off(&((size + PAGE_SIZE - 1) / PAGE_SIZE));
+            self.fs.free_pages(removed.len());
+            if let Some(frame) = inner.pages.get(&(size / PAGE_SIZE)) {
+                frame.ppn.get_bytes_array()[size % PAGE_SIZE..].fill(0);
+            }
+        }
+        inner.size = size;
+        let time = now();
+        inner.mtime = time;
+        inner.ctime = time;
+        Ok(())
+    }
+
+    fn read_link(&self) -> Result<String, isize> {
+        if self.kind != InodeType::SymLink {
+            return Err(-EINVAL);
+        }
+        Ok(self.inner.lock().target.clone())
+    }
+
+    fn set_mode(&self, mode: u32) -> Result<(), isize> {
+        let mut inner = self.inner.lock();
+        inner.mode = mode & 0o7777;
+        inner.ctime = now();
+        Ok(())
+    }
+
+    fn seek_data(&self, offset: usize, hole: bool) -> Result<usize, isize> {
+        let inner = self.inner.lock();
+        if offset >= inner.size {
+            return Err(-ENXIO);
+        }
+        let index = offset / PAGE_SIZE;
+        if hole {
+            // 从offset所在页开始跳过连续的已分配页
+            let mut next = index;
+            while inner.pages.contains_key(&next) {
+                next += 1;
+            }
+            Ok((next * PAGE_SIZE).max(offset).min(inner.size))
+        } else {
+            match inner.pages.range(index..).next() {
+                Some((&next, _)) if next * PAGE_SIZE < inner.size => {
+                    Ok((next * PAGE_SIZE).max(offset))
+                }
+                _ => Err(-ENXIO), // 之后只有空洞
+            }
+        }
+    }
+
+    fn set_times(&self, atime: Option<u64>, mtime: Option<u64>) {
+        let mut inner = self.inner.lock();
+        if let Some(atime) = atime {
+            inner.atime = atime;
+        }
+        if let Some(mtime) = mtime {
+            inner.mtime = mtime;
+            inner.ctime = mtime;
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Drop for TmpInode {
+    fn drop(&mut self) {
+        self.fs.free_pages(self.inner.get_mut().pages.len());
+    }
+}
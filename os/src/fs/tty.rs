@@ -0,0 +1,430 @@
+//! 控制台终端及其行规程
+//!
+//! 规范模式下输入按行缓冲，支持回显、退格、^U删除整行和^W删除单词，
+//! ISIG打开时^C、^\、^Z向前台进程组发送SIGINT、SIGQUIT、SIGTSTP。
+//! 读取时在串口的等待队列上睡眠，时钟中断时也会处理输入，以便及时发送信号。
+use super::stdio::{console_try_read, console_write};
+use crate::drivers::console_wait_input;
+use crate::mm::{translated_ref, translated_refmut};
+use crate::syscall::errno::*;
+use crate::task::{
+    current_user_token, has_pending_signal, send_signal_to_pgrp, SIGINT, SIGQUIT, SIGTSTP, SIGWINCH,
+};
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+pub const TCGETS: usize = 0x5401;
+pub const TCSETS: usize = 0x5402;
+pub const TCSETSW: usize = 0x5403;
+pub const TCSETSF: usize = 0x5404;
+pub const TIOCGPGRP: usize = 0x540f;
+pub const TIOCSPGRP: usize = 0x5410;
+pub const TIOCGWINSZ: usize = 0x5413;
+pub const TIOCSWINSZ: usize = 0x5414;
+
+// c_iflag
+const INLCR: u32 = 0o100;
+const IGNCR: u32 = 0o200;
+const ICRNL: u32 = 0o400;
+// c_oflag
+const OPOST: u32 = 0o1;
+const ONLCR: u32 = 0o4;
+// c_cflag
+const CS8: u32 = 0o60;
+const CREAD: u32 = 0o200;
+// c_lflag
+const ISIG: u32 = 0o1;
+const ICANON: u32 = 0o2;
+const ECHO: u32 = 0o10;
+const ECHOE: u32 = 0o20;
+const ECHOK: u32 = 0o40;
+const ECHONL: u32 = 0o100;
+const NOFLSH: u32 = 0o200;
+const ECHOCTL: u32 = 0o1000;
+const ECHOKE: u32 = 0o4000;
+const IEXTEN: u32 = 0o100000;
+
+// c_cc的下标
+const VINTR: usize = 0;
+const VQUIT: usize = 1;
+const VERASE: usize = 2;
+const VKILL: usize = 3;
+const VEOF: usize = 4;
+const VMIN: usize = 6;
+const VSUSP: usize = 10;
+const VEOL: usize = 11;
+const VWERASE: usize = 14;
+const NCCS: usize = 19;
+
+/// 用户态的struct termios
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Termios {
+    pub c_iflag: u32,
+    pub c_oflag: u32,
+    pub c_cflag: u32,
+    pub c_lflag: u32,
+    pub c_line: u8,
+    pub c_cc: [u8; NCCS],
+}
+
+impl Termios {
+    fn new() -> Self {
+        let mut c_cc = [0u8; NCCS];
+        c_cc[VINTR] = 0x03; // ^C
+        c_cc[VQUIT] = 0x1c; // ^\
+        c_cc[VERASE] = 0x7f; // DEL
+        c_cc[VKILL] = 0x15; // ^U
+        c_cc[VEOF] = 0x04; // ^D
+        c_cc[VMIN] = 1;
+        c_cc[VSUSP] = 0x1a; // ^Z
+        c_cc[VWERASE] = 0x17; // ^W
+        Self {
+            c_iflag: ICRNL,
+            c_oflag: OPOST | ONLCR,
+            c_cflag: CS8 | CREAD,
+            c_lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | ECHOKE | IEXTEN,
+            c_line: 0,
+            c_cc,
+        }
+    }
+
+    fn lflag(&self, flag: u32) -> bool {
+        self.c_lflag & flag != 0
+    }
+
+    /// c_cc中的特殊字符，值为0表示禁用
+    fn is_cc(&self, index: usize, ch: u8) -> bool {
+        self.c_cc[index] != 0 && self.c_cc[index] == ch
+    }
+}
+
+/// 用户态的struct winsize
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct WinSize {
+    pub ws_row: u16,
+    pub ws_col: u16,
+    pub ws_xpixel: u16,
+    pub ws_ypixel: u16,
+}
+
+struct TtyInner {
+    termios: Termios,
+    winsize: WinSize,
+    /// 前台进程组，初始为initproc所在的进程组
+    fg_pgid: usize,
+    /// 规范模式下正在编辑的行
+    line: Vec<u8>,
+    /// 可以被读取的输入，规范模式下每一项是一行，空行表示文件结束
+    ready: VecDeque<Vec<u8>>,
+    /// 等待写到控制台的回显
+    echo: Vec<u8>,
+}
+
+/// 回显时控制字符显示为^X
+fn is_ctl(ch: u8) -> bool {
+    (ch < 0x20 && ch != b'\n' && ch != b'\t') || ch == 0x7f
+}
+
+impl TtyInner {
+    fn echo(&mut self, ch: u8) {
+        if !self.termios.lflag(ECHO) {
+            if ch == b'\n' && self.termios.lflag(ECHONL) && self.termios.lflag(ICANON) {
+                self.echo.push(ch);
+            }
+            return;
+        }
+        if is_ctl(ch) && self.termios.lflag(ECHOCTL) {
+            self.echo.extend_from_slice(&[b'^', ch ^ 0x40]);
+        } else {
+            self.echo.push(ch);
+        }
+    }
+
+    /// 删除正在编辑的行的最后一个字符，并在屏幕上擦除
+    fn erase(&mut self) -> Option<u8> {
+        let ch = self.line.pop()?;
+        if self.termios.lflag(ECHO) && self.termios.lflag(ECHOE) {
+            let width = if is_ctl(ch) && self.termios.lflag(ECHOCTL) {
+                2
+            } else {
+                1
+            };
+            for _ in 0..width {
+                self.echo.extend_from_slice(b"\x08 \x08");
+            }
+        }
+        Some(ch)
+    }
+
+    fn commit_line(&mut self) {
+        let line = core::mem::take(&mut self.line);
+        self.ready.push_back(line);
+    }
+
+    /// 处理一个输入字符，需要发送信号时返回信号编号
+    fn input(&mut self, mut ch: u8) -> Option<usize> {
+        let termios = self.termios;
+        if ch == b'\r' {
+            if termios.c_iflag & IGNCR != 0 {
+                return None;
+            }
+            if termios.c_iflag & ICRNL != 0 {
+                ch = b'\n';
+            }
+        } else if ch == b'\n' && termios.c_iflag & INLCR != 0 {
+            ch = b'\r';
+        }
+        if termios.lflag(ISIG) {
+            let signum = if termios.is_cc(VINTR, ch) {
+                Some(SIGINT)
+            } else if termios.is_cc(VQUIT, ch) {
+                Some(SIGQUIT)
+            } else if termios.is_cc(VSUSP, ch) {
+                Some(SIGTSTP)
+            } else {
+                None
+            };
+            if signum.is_some() {
+                if !termios.lflag(NOFLSH) {
+                    self.line.clear();
+                    self.ready.clear();
+                }
+                self.echo(ch);
+                return signum;
+            }
+        }
+        if !termios.lflag(ICANON) {
+            match self.ready.back_mut() {
+                Some(chunk) => chunk.push(ch),
+                None => self.ready.push_back(vec![ch]),
+            }
+            self.echo(ch);
+            return None;
+        }
+        if termios.is_cc(VERASE, ch) {
+            self.erase();
+        } else if termios.is_cc(VKILL, ch) {
+            if termios.lflag(ECHOKE) {
+                while self.erase().is_some() {}
+            } else {
+                self.line.clear();
+                self.echo(ch);
+                if termios.lflag(ECHOK) {
+                    self.echo.push(b'\n');
+                }
+            }
+        } else if termios.is_cc(VWERASE, ch) && termios.lflag(IEXTEN) {
+            // 先删除单词后的空白，再删除单词
+            while matches!(self.line.last(), Some(b' ') | Some(b'\t')) {
+                self.erase();
+            }
+            while matches!(self.line.last(), Some(c) if *c != b' ' && *c != b'\t') {
+                self.erase();
+            }
+        } else if termios.is_cc(VEOF, ch) {
+            self.commit_line();
+        } else {
+            self.line.push(ch);
+            self.echo(ch);
+            if ch == b'\n' || termios.is_cc(VEOL, ch) {
+                self.commit_line();
+            }
+        }
+        None
+    }
+
+    /// 从ready中取出可以读取的数据，没有满足读取条件的输入时返回None
+    fn take_input(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if self.termios.lflag(ICANON) {
+            // 一次最多读取一行
+            let mut line = self.ready.pop_front()?;
+            let len = buf.len().min(line.len());
+            buf[..len].copy_from_slice(&line[..len]);
+            if len < line.len() {
+                self.ready.push_front(line.split_off(len));
+            }
+            return Some(len);
+        }
+        // 非规范模式下至少等到VMIN个字符，VMIN为0时立即返回，VTIME暂不支持
+        let available: usize = self.ready.iter().map(Vec::len).sum();
+        let min = (self.termios.c_cc[VMIN] as usize).min(buf.len());
+        if available < min {
+            return None;
+        }
+        let mut len = 0;
+        while len < buf.len() {
+            let mut chunk = match self.ready.pop_front() {
+                Some(chunk) => chunk,
+                None => break,
+            };
+            let n = (buf.len() - len).min(chunk.len());
+            buf[len..len + n].copy_from_slice(&chunk[..n]);
+            len += n;
+            if n < chunk.len() {
+                self.ready.push_front(chunk.split_off(n));
+            }
+        }
+        Some(len)
+    }
+
+    fn set_termios(&mut self, termios: Termios, flush: bool) {
+        if flush {
+            self.line.clear();
+            self.ready.clear();
+        }
+        // 离开规范模式时，正在编辑的行立即可读
+        if self.termios.lflag(ICANON) && !termios.lflag(ICANON) && !self.line.is_empty() {
+            self.commit_line();
+        }
+        self.termios = termios;
+    }
+}
+
+pub struct Tty {
+    inner: Mutex<TtyInner>,
+}
+
+lazy_static! {
+    /// 系统控制台，/dev/tty、/dev/console和初始的标准输入输出都指向它
+    pub static ref TTY: Tty = Tty::new();
+}
+
+impl Tty {
+    fn new() -> Self {
+        Self {
+            inner: Mutex::new(TtyInner {
+                termios: Termios::new(),
+                winsize: WinSize {
+                    ws_row: 24,
+                    ws_col: 80,
+                    ws_xpixel: 0,
+                    ws_ypixel: 0,
+                },
+                fg_pgid: 0,
+                line: Vec::new(),
+                ready: VecDeque::new(),
+                echo: Vec::new(),
+            }),
+        }
+    }
+
+    /// 处理控制台上所有已到达的输入，回显并发送产生的信号
+    /// 时钟中断时也会调用，使没有在读终端的前台进程也能被^C打断
+    pub fn receive(&self) {
+        let mut signals = Vec::new();
+        let (echo, fg_pgid, oflag) = {
+            let mut inner = self.inner.lock();
+            while let Some(ch) = console_try_read() {
+                if let Some(signum) = inner.input(ch) {
+                    signals.push(signum);
+                }
+            }
+            (
+                core::mem::take(&mut inner.echo),
+                inner.fg_pgid,
+                inner.termios.c_oflag,
+            )
+        };
+        output(&echo, oflag);
+        // 发送信号需要访问进程，不能持有终端的锁
+        for signum in signals {
+            send_signal_to_pgrp(fg_pgid, signum);
+        }
+    }
+
+    /// 读取输入，没有输入时等待，等待期间收到信号返回EINTR
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, isize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            self.receive();
+            if let Some(len) = self.inner.lock().take_input(buf) {
+                return Ok(len);
+            }
+            if has_pending_signal() {
+                return Err(-EINTR);
+            }
+            console_wait_input();
+        }
+    }
+
+    /// 写到控制台，数据可以是任意字节
+    pub fn write(&self, buf: &[u8]) -> usize {
+        let oflag = self.inner.lock().termios.c_oflag;
+        output(buf, oflag);
+        buf.len()
+    }
+
+    /// 是否有可以读取的输入
+    pub fn readable(&self) -> bool {
+        self.receive();
+        let inner = self.inner.lock();
+        if inner.termios.lflag(ICANON) {
+            !inner.ready.is_empty()
+        } else {
+            inner.ready.iter().any(|chunk| !chunk.is_empty()) || inner.termios.c_cc[VMIN] == 0
+        }
+    }
+
+    pub fn ioctl(&self, cmd: usize, arg: usize) -> Result<isize, isize> {
+        let token = current_user_token();
+        match cmd {
+            TCGETS => {
+                *translated_refmut(token, arg as *mut Termios) = self.inner.lock().termios;
+            }
+            TCSETS | TCSETSW | TCSETSF => {
+                // 输出是同步的，TCSETSW不需要等待
+                let termios = *translated_ref(token, arg as *const Termios);
+                self.inner.lock().set_termios(termios, cmd == TCSETSF);
+            }
+            TIOCGWINSZ => {
+                *translated_refmut(token, arg as *mut WinSize) = self.inner.lock().winsize;
+            }
+            TIOCSWINSZ => {
+                let winsize = *translated_ref(token, arg as *const WinSize);
+                let mut inner = self.inner.lock();
+                if inner.winsize != winsize {
+                    inner.winsize = winsize;
+                    let fg_pgid = inner.fg_pgid;
+                    drop(inner);
+                    send_signal_to_pgrp(fg_pgid, SIGWINCH);
+                }
+            }
+            TIOCGPGRP => {
+                *translated_refmut(token, arg as *mut i32) = self.inner.lock().fg_pgid as i32;
+            }
+            TIOCSPGRP => {
+                let pgid = *translated_ref(token, arg as *const i32);
+                if pgid < 0 {
+                    return Err(-EINVAL);
+                }
+                self.inner.lock().fg_pgid = pgid as usize;
+            }
+            _ => return Err(-ENOTTY),
+        }
+        Ok(0)
+    }
+}
+
+/// 按c_oflag处理后写到控制台
+fn output(buf: &[u8], oflag: u32) {
+    if oflag & OPOST == 0 || oflag & ONLCR == 0 {
+        console_write(buf);
+        return;
+    }
+    for line in buf.split_inclusive(|ch| *ch == b'\n') {
+        match line.split_last() {
+            Some((b'\n', text)) => {
+                console_write(text);
+                console_write(b"\r\n");
+            }
+            _ => console_write(line),
+        }
+    }
+}
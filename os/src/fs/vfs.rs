@@ -0,0 +1,178 @@
+//! 与具体文件系统无关的虚拟文件系统接口
+use super::{PollEvents, DT_BLK, DT_CHR, DT_DIR, DT_FIFO, DT_LNK, DT_REG};
+use crate::syscall::errno::{EINVAL, ENOTTY, ENXIO, EPERM};
+use crate::task::WaitQueue;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::any::Any;
+
+/// inode的类型
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InodeType {
+    File,
+    Directory,
+    SymLink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+}
+
+impl InodeType {
+    /// getdents中d_type的取值
+    pub fn dtype(&self) -> u8 {
+        match self {
+            InodeType::File => DT_REG,
+            InodeType::Directory => DT_DIR,
+            InodeType::SymLink => DT_LNK,
+            InodeType::CharDevice => DT_CHR,
+            InodeType::BlockDevice => DT_BLK,
+            InodeType::Fifo => DT_FIFO,
+        }
+    }
+}
+
+/// 由主设备号和次设备号组成设备号，编码与Linux相同
+pub fn makedev(major: u32, minor: u32) -> u64 {
+    let (major, minor) = (major as u64, minor as u64);
+    ((major & 0xffff_f000) << 32)
+        | ((major & 0xfff) << 8)
+        | ((minor & 0xffff_ff00) << 12)
+        | (minor & 0xff)
+}
+
+/// inode的元数据，时间均为Unix时间戳(秒)
+#[derive(Clone, Copy, Debug)]
+pub struct InodeStat {
+    pub ino: u64,
+    pub kind: InodeType,
+    /// 权限位，不含文件类型
+    pub mode: u32,
+    pub size: u64,
+    /// 占用的512字节块数
+    pub blocks: u64,
+    pub nlink: u32,
+    /// 设备文件的设备号，其他文件为0
+    pub rdev: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+/// readdir返回的目录项
+pub struct DirItem {
+    pub name: String,
+    pub ino: u64,
+    pub kind: InodeType,
+}
+
+/// renameat2的标志
+pub const RENAME_NOREPLACE: u32 = 1 << 0;
+pub const RENAME_EXCHANGE: u32 = 1 << 1;
+
+/// 一个挂载的文件系统实例
+pub trait FileSystem: Send + Sync {
+    /// 文件系统的类型名，如"vfat"
+    fn fs_type(&self) -> &'static str;
+    /// 根目录的inode
+    fn root(&self) -> Arc<dyn Inode>;
+    /// 将文件系统的脏数据全部写回
+    fn sync(&self) -> Result<(), isize>;
+    /// 从挂载表中移除时调用，释放文件系统自身持有的资源
+    fn unmount(&self) {}
+    /// 取得具体的文件系统类型
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// 文件系统中的一个文件、目录或特殊文件，出错时返回负的错误码
+/// 目录操作的name都是单个路径分量，不含'/'，也不会是"."或".."
+pub trait Inode: Send + Sync {
+    /// 所在的文件系统
+    fn fs(&self) -> Arc<dyn FileSystem>;
+    fn kind(&self) -> InodeType;
+    fn stat(&self) -> Result<InodeStat, isize>;
+
+    /// 在目录中查找name
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, isize>;
+    /// 在目录中新建文件或目录，name已存在时返回EEXIST
+    fn create(&self, name: &str, kind: InodeType) -> Result<Arc<dyn Inode>, isize>;
+    /// 在目录中新建指向target的符号链接
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, isize> {
+        Err(-EPERM)
+    }
+    /// 在目录中新建指向target的硬链接，target与self在同一文件系统中
+    fn link(&self, _name: &str, _target: &Arc<dyn Inode>) -> Result<(), isize> {
+        Err(-EPERM)
+    }
+    /// 删除目录中的非目录文件
+    fn unlink(&self, name: &str) -> Result<(), isize>;
+    /// 删除目录中的空目录
+    fn rmdir(&self, name: &str) -> Result<(), isize>;
+    /// 将目录中的old_name移动到new_dir下的new_name，new_dir与self在同一文件系统中
+    fn rename(
+        &self,
+        old_name: &str,
+        new_dir: &Arc<dyn Inode>,
+        new_name: &str,
+        flags: u32,
+    ) -> Result<(), isize>;
+    /// 读取目录中offset处开始的第一个目录项，返回目录项和下一项的offset，读完时返回None
+    fn readdir(&self, offset: usize) -> Result<Option<(DirItem, usize)>, isize>;
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, isize>;
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, isize>;
+    fn truncate(&self, size: usize) -> Result<(), isize>;
+    /// 读取符号链接的目标
+    fn read_link(&self) -> Result<String, isize> {
+        Err(-EINVAL)
+    }
+    /// 修改权限位
+    fn set_mode(&self, _mode: u32) -> Result<(), isize> {
+        Err(-EPERM)
+    }
+    /// 从offset开始查找下一段数据(hole为false)或空洞(hole为true)的起始位置
+    /// 默认文件内没有空洞，唯一的空洞在文件末尾
+    fn seek_data(&self, offset: usize, hole: bool) -> Result<usize, isize> {
+        let size = self.stat()?.size as usize;
+        if offset >= size {
+            return Err(-ENXIO);
+        }
+        Ok(if hole { size } else { offset })
+    }
+    /// 在events中去掉尚未就绪的事件，默认读写总是就绪
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        events
+    }
+    /// 设备文件就绪状态变化时唤醒的等待队列
+    fn wait_queue(&self) -> Option<Arc<WaitQueue>> {
+        None
+    }
+    /// 设置访问时间和修改时间，None表示不修改
+    fn set_times(&self, _atime: Option<u64>, _mtime: Option<u64>) {}
+    /// 将文件写回，data_only为true时对应fdatasync
+    fn sync(&self, _data_only: bool) -> Result<(), isize> {
+        Ok(())
+    }
+
+    /// 设备文件的ioctl
+    fn ioctl(&self, _cmd: usize, _arg: usize) -> Result<isize, isize> {
+        Err(-ENOTTY)
+    }
+
+    /// 文件被打开时调用
+    fn open(&self) {}
+    /// 文件的一个打开实例被关闭时调用
+    fn release(&self) {}
+
+    /// 同一文件系统内的操作(如rename)需要取得具体类型
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// 文件系统实例的标识
+pub fn fs_id(fs: &Arc<dyn FileSystem>) -> usize {
+    Arc::as_ptr(fs) as *const u8 as usize
+}
+
+/// 两个inode是否属于同一个文件系统实例
+pub fn same_fs(a: &Arc<dyn Inode>, b: &Arc<dyn Inode>) -> bool {
+    fs_id(&a.fs()) == fs_id(&b.fs())
+}
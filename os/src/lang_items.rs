@@ -1,4 +1,5 @@
 //! The panic handler
+use crate::drivers::console_flush;
 use crate::sbi::shutdown;
 use core::panic::PanicInfo;
 
@@ -14,5 +15,6 @@ fn panic(info: &PanicInfo) -> ! {
     } else {
         println!("[kernel] Panicked: {}", info.message().unwrap());
     }
+    console_flush();
     shutdown()
 }
@@ -23,6 +23,7 @@ pub mod fs;
 pub mod lang_items;
 pub mod loader;
 pub mod mm;
+pub mod random;
 pub mod sbi;
 pub mod sync;
 pub mod syscall;
@@ -52,10 +53,12 @@ pub fn rust_main() -> ! {
     clear_bss(); // 清除数据段
                  // println!("[kernel] Hello, world!");
     mm::init();
+    drivers::init();
     // mm::remap_test();
     trap::init();
     trap::enable_timer_interrupt();
     timer::set_next_trigger();
+    fs::init();
     // fs::list_apps();
     task::add_initproc();
     task::run_tasks();
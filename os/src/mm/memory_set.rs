@@ -272,6 +272,20 @@ impl MemorySet {
         self.page_table.translate(vpn)
     }
     ///Remove all `MapArea`
+    /// (start, end, permission, resident pages) of every area, used by /proc/<pid>/maps
+    pub fn area_info(&self) -> Vec<(VirtAddr, VirtAddr, MapPermission, usize)> {
+        self.areas
+            .iter()
+            .map(|area| {
+                (
+                    area.vpn_range.get_start().into(),
+                    area.vpn_range.get_end().into(),
+                    area.map_perm,
+                    area.data_frames.len(),
+                )
+            })
+            .collect()
+    }
     pub fn recycle_data_pages(&mut self) {
         //*self = Self::new_bare();
         self.areas.clear();
@@ -0,0 +1,133 @@
+//! 基于ChaCha20的内核随机数生成器
+//!
+//! 熵来自时钟中断到来时的计数器值等不可预测的时刻，先混入熵池，
+//! 生成随机数前再以熵池更新密钥。每次生成之后都用新的输出替换密钥，
+//! 即使密钥泄露也无法推出之前的输出。
+use crate::timer::get_time;
+use lazy_static::*;
+use spin::Mutex;
+
+const KEY_WORDS: usize = 8;
+const BLOCK_WORDS: usize = 16;
+/// "expand 32-byte k"
+const CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+fn quarter_round(s: &mut [u32; BLOCK_WORDS], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+/// 计算一个64字节的ChaCha20块，使用64位计数器，nonce为0
+fn chacha20_block(key: &[u32; KEY_WORDS], counter: u64) -> [u32; BLOCK_WORDS] {
+    let mut input = [0u32; BLOCK_WORDS];
+    input[..4].copy_from_slice(&CONSTANTS);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+    let mut state = input;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+    for (word, input) in state.iter_mut().zip(input.iter()) {
+        *word = word.wrapping_add(*input);
+    }
+    state
+}
+
+struct ChaChaRng {
+    key: [u32; KEY_WORDS],
+    counter: u64,
+    /// 尚未用于更新密钥的熵
+    pool: [u32; KEY_WORDS],
+    /// 下一个熵样本混入pool的位置
+    pool_pos: usize,
+    /// 自上次更新密钥以来混入的熵样本数
+    pending: usize,
+}
+
+impl ChaChaRng {
+    fn new() -> Self {
+        let mut rng = Self {
+            key: [0; KEY_WORDS],
+            counter: 0,
+            pool: [0; KEY_WORDS],
+            pool_pos: 0,
+            pending: 0,
+        };
+        // 启动时可用的熵很少：开机以来的计数器和内核栈的位置
+        let stack_marker = 0u8;
+        rng.add_entropy(get_time() as u64);
+        rng.add_entropy(&stack_marker as *const u8 as u64);
+        rng.reseed();
+        rng
+    }
+
+    fn add_entropy(&mut self, sample: u64) {
+        let pos = self.pool_pos;
+        let mixed = self.pool[pos].rotate_left(7) ^ sample as u32;
+        self.pool[pos] = mixed ^ (sample >> 32) as u32;
+        self.pool_pos = (pos + 1) % KEY_WORDS;
+        self.pending += 1;
+    }
+
+    /// 将熵池与当前密钥一起生成新的密钥
+    fn reseed(&mut self) {
+        let mut key = self.key;
+        for (word, entropy) in key.iter_mut().zip(self.pool.iter()) {
+            *word ^= *entropy;
+        }
+        self.rekey(&key);
+        self.pool = [0; KEY_WORDS];
+        self.pending = 0;
+    }
+
+    /// 用key生成一块输出作为新的密钥
+    fn rekey(&mut self, key: &[u32; KEY_WORDS]) {
+        let block = chacha20_block(key, self.counter);
+        self.counter = self.counter.wrapping_add(1);
+        self.key.copy_from_slice(&block[..KEY_WORDS]);
+    }
+
+    fn fill_bytes(&mut self, buf: &mut [u8]) {
+        if self.pending > 0 {
+            self.reseed();
+        }
+        for chunk in buf.chunks_mut(BLOCK_WORDS * 4) {
+            let block = chacha20_block(&self.key, self.counter);
+            self.counter = self.counter.wrapping_add(1);
+            for (bytes, word) in chunk.chunks_mut(4).zip(block.iter()) {
+                bytes.copy_from_slice(&word.to_le_bytes()[..bytes.len()]);
+            }
+        }
+        // 立即更换密钥，之前的输出无法再被恢复
+        let key = self.key;
+        self.rekey(&key);
+    }
+}
+
+lazy_static! {
+    static ref RNG: Mutex<ChaChaRng> = Mutex::new(ChaChaRng::new());
+}
+
+/// 混入一个熵样本，如中断到来时的计数器值
+pub fn add_entropy(sample: u64) {
+    RNG.lock().add_entropy(sample);
+}
+
+/// 用随机字节填满buf
+pub fn fill_random(buf: &mut [u8]) {
+    RNG.lock().fill_bytes(buf);
+}
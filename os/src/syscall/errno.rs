@@ -0,0 +1,30 @@
+//! Error numbers returned (negated) by syscalls, same values as Linux
+#![allow(unused)]
+
+pub const EPERM: isize = 1;
+pub const ENOENT: isize = 2;
+pub const ESRCH: isize = 3;
+pub const EINTR: isize = 4;
+pub const EIO: isize = 5;
+pub const ENXIO: isize = 6;
+pub const EBADF: isize = 9;
+pub const EAGAIN: isize = 11;
+pub const ENOMEM: isize = 12;
+pub const EACCES: isize = 13;
+pub const EFAULT: isize = 14;
+pub const ENOTBLK: isize = 15;
+pub const EBUSY: isize = 16;
+pub const EEXIST: isize = 17;
+pub const EXDEV: isize = 18;
+pub const ENODEV: isize = 19;
+pub const ENOTDIR: isize = 20;
+pub const EISDIR: isize = 21;
+pub const EINVAL: isize = 22;
+pub const ENOTTY: isize = 25;
+pub const EFBIG: isize = 27;
+pub const ENOSPC: isize = 28;
+pub const ESPIPE: isize = 29;
+pub const ENAMETOOLONG: isize = 36;
+pub const ENOSYS: isize = 38;
+pub const ENOTEMPTY: isize = 39;
+pub const ELOOP: isize = 40;
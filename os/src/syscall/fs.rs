@@ -1,12 +1,77 @@
 //! File and filesystem-related syscalls
-use crate::mm::{translated_byte_buffer, translated_str, UserBuffer};
-use crate::sbi::console_getchar;
-use crate::task::{current_task, current_user_token, suspend_current_and_run_next};
+use crate::config::PAGE_SIZE;
+use crate::mm::{
+    translated_byte_buffer, translated_ref, translated_refmut, translated_str, UserBuffer,
+};
+use crate::task::{current_task, current_user_token};
+use crate::timer::{get_wall_time, TimeSpec, NSEC_PER_SEC};
 
-use crate::fs::{open, DiskInodeType, FileDescriptor, FileType, OpenFlags};
+use super::errno::*;
+use crate::fs::{
+    find_dentry, lookup_path, mount, open, sync_all, umount, Dentry, DiskInodeType, File,
+    FileDescriptor, FileType, OpenFlags, RENAME_EXCHANGE, RENAME_NOREPLACE, ROOT_DENTRY, SEEK_DATA,
+    SEEK_HOLE,
+};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
 
-const FD_STDIN: usize = 0;
-const FD_STDOUT: usize = 1;
+const AT_FDCWD: isize = -100;
+const AT_REMOVEDIR: u32 = 0x200;
+
+const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+const AT_SYMLINK_FOLLOW: u32 = 0x400;
+
+const UTIME_NOW: usize = (1 << 30) - 1;
+const UTIME_OMIT: usize = (1 << 30) - 2;
+
+/// FAT32长文件名的最大长度
+const NAME_MAX: usize = 255;
+
+/// readv/writev一次最多处理的iovec个数
+const IOV_MAX: usize = 1024;
+
+/// 用户态的iovec结构
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct IoVec {
+    base: *mut u8,
+    len: usize,
+}
+
+/// 将读写的结果转换为系统调用的返回值
+fn size_or_errno(result: Result<usize, isize>) -> isize {
+    match result {
+        Ok(size) => size as isize,
+        Err(errno) => errno,
+    }
+}
+
+/// 相对于dirfd查找路径对应的文件，失败时返回负的错误码
+/// follow_last为false时不跟随路径最后一项的符号链接
+fn lookup_at(dirfd: isize, path: &str, follow_last: bool) -> Result<Arc<Dentry>, isize> {
+    // 查找时不能持有进程的锁，/proc/self等路径需要读取当前进程的信息
+    if dirfd == AT_FDCWD || path.starts_with('/') {
+        let work_path = current_task()
+            .unwrap()
+            .inner_exclusive_access()
+            .get_work_path();
+        return find_dentry(work_path.as_str(), path, follow_last);
+    }
+    if dirfd < 0 {
+        return Err(-EBADF);
+    }
+    match get_fd(dirfd as usize)?.ftype {
+        FileType::File(osinode) => {
+            if !osinode.is_dir() {
+                return Err(-ENOTDIR);
+            }
+            lookup_path(&ROOT_DENTRY, &osinode.dentry(), path, follow_last)
+        }
+        _ => Err(-ENOTDIR),
+    }
+}
 
 pub fn sys_getcwd(buf: *mut u8, size: usize) -> isize {
     let task = current_task().unwrap();
@@ -32,13 +97,14 @@ pub fn sys_openat(path: *const u8, flags: u32) -> isize {
     let token = current_user_token();
     let path = translated_str(token, path);
     let open_flags = OpenFlags::from_bits(flags).unwrap();
-    let mut inner = task.inner_exclusive_access();
+    let work_path = task.inner_exclusive_access().get_work_path();
     if let Some(inode) = open(
-        inner.get_work_path().as_str(),
+        work_path.as_str(),
         path.as_str(),
         open_flags,
         DiskInodeType::File,
     ) {
+        let mut inner = task.inner_exclusive_access();
         let fd = inner.alloc_fd();
         inner.fd_table[fd] = Some(FileDescriptor::new(
             open_flags.contains(OpenFlags::CLOEXEC),
@@ -50,6 +116,276 @@ pub fn sys_openat(path: *const u8, flags: u32) -> isize {
     }
 }
 
+/// 将路径拆分为父目录和最后一项名称，并查找父目录
+fn lookup_parent_at(dirfd: isize, path: &str) -> Result<(Arc<Dentry>, String), isize> {
+    let path = path.trim_end_matches('/');
+    let (parent, name) = match path.rfind('/') {
+        Some(idx) => (&path[..idx + 1], &path[idx + 1..]),
+        None => (".", path),
+    };
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(-EINVAL);
+    }
+    if name.len() > NAME_MAX {
+        return Err(-ENAMETOOLONG);
+    }
+    let parent = lookup_at(dirfd, parent, true)?;
+    if !parent.is_dir() {
+        return Err(-ENOTDIR);
+    }
+    Ok((parent, String::from(name)))
+}
+
+pub fn sys_unlinkat(dirfd: isize, path: *const u8, flags: u32) -> isize {
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    if let Some(last) = path.trim_end_matches('/').rsplit('/').next() {
+        if last == "." || last == ".." {
+            return -EINVAL;
+        }
+    }
+    if path.starts_with('/') && path.trim_matches('/').is_empty() {
+        return -EBUSY; // 根目录
+    }
+    let (dir, name) = match lookup_parent_at(dirfd, path.as_str()) {
+        Ok(pair) => pair,
+        Err(errno) => return errno,
+    };
+    let result = if flags & AT_REMOVEDIR != 0 {
+        dir.rmdir(name.as_str())
+    } else {
+        dir.unlink(name.as_str())
+    };
+    match result {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_renameat2(
+    olddirfd: isize,
+    oldpath: *const u8,
+    newdirfd: isize,
+    newpath: *const u8,
+    flags: u32,
+) -> isize {
+    let token = current_user_token();
+    let oldpath = translated_str(token, oldpath);
+    let newpath = translated_str(token, newpath);
+    if flags & !(RENAME_NOREPLACE | RENAME_EXCHANGE) != 0
+        || flags == RENAME_NOREPLACE | RENAME_EXCHANGE
+    {
+        return -EINVAL;
+    }
+    let (old_dir, old_name) = match lookup_parent_at(olddirfd, oldpath.as_str()) {
+        Ok(pair) => pair,
+        Err(errno) => return errno,
+    };
+    let (new_dir, new_name) = match lookup_parent_at(newdirfd, newpath.as_str()) {
+        Ok(pair) => pair,
+        Err(errno) => return errno,
+    };
+    match old_dir.rename(old_name.as_str(), &new_dir, new_name.as_str(), flags) {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_symlinkat(target: *const u8, newdirfd: isize, linkpath: *const u8) -> isize {
+    let token = current_user_token();
+    let target = translated_str(token, target);
+    let linkpath = translated_str(token, linkpath);
+    if target.is_empty() {
+        return -ENOENT;
+    }
+    let (dir, name) = match lookup_parent_at(newdirfd, linkpath.as_str()) {
+        Ok(res) => res,
+        Err(errno) => return errno,
+    };
+    match dir.symlink(name.as_str(), target.as_str()) {
+        Ok(_) => 0,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_readlinkat(dirfd: isize, path: *const u8, buf: *mut u8, bufsiz: usize) -> isize {
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    if bufsiz == 0 {
+        return -EINVAL;
+    }
+    let dentry = match lookup_at(dirfd, path.as_str(), false) {
+        Ok(dentry) => dentry,
+        Err(errno) => return errno,
+    };
+    let target = match dentry.inode().read_link() {
+        Ok(target) => target,
+        Err(errno) => return errno,
+    };
+    // 结果不以'\0'结尾，超出bufsiz的部分被截断
+    let len = target.len().min(bufsiz);
+    let mut user_buf = UserBuffer::new(translated_byte_buffer(token, buf, len));
+    user_buf.write(&target.as_bytes()[..len]);
+    len as isize
+}
+
+pub fn sys_utimensat(dirfd: isize, path: *const u8, times: *const TimeSpec, flags: u32) -> isize {
+    let token = current_user_token();
+    if flags & !AT_SYMLINK_NOFOLLOW != 0 {
+        return -EINVAL;
+    }
+    // path为NULL时修改dirfd本身(futimens)
+    let inode = if path.is_null() {
+        match get_fd(dirfd as usize) {
+            Ok(FileDescriptor {
+                ftype: FileType::File(inode),
+                ..
+            }) => Ok(inode.inode()),
+            Ok(_) => Err(-EPERM),
+            Err(errno) => Err(errno),
+        }
+    } else {
+        let path = translated_str(token, path);
+        lookup_at(dirfd, path.as_str(), flags & AT_SYMLINK_NOFOLLOW == 0).map(|d| d.inode())
+    };
+    let inode = match inode {
+        Ok(inode) => inode,
+        Err(errno) => return errno,
+    };
+    let now = get_wall_time().sec as u64;
+    // times为NULL时两者均设为当前时间
+    let (atime, mtime) = if times.is_null() {
+        (Some(now), Some(now))
+    } else {
+        let convert = |ts: TimeSpec| match ts.nsec {
+            UTIME_NOW => Ok(Some(now)),
+            UTIME_OMIT => Ok(None),
+            nsec if nsec < NSEC_PER_SEC => Ok(Some(ts.sec as u64)),
+            _ => Err(-EINVAL),
+        };
+        let atime = convert(*translated_ref(token, times));
+        let mtime = convert(*translated_ref(token, unsafe { times.add(1) }));
+        match (atime, mtime) {
+            (Ok(atime), Ok(mtime)) => (atime, mtime),
+            _ => return -EINVAL,
+        }
+    };
+    inode.set_times(atime, mtime);
+    0
+}
+
+/// 新建硬链接，不支持硬链接的文件系统(如FAT32)返回EPERM
+pub fn sys_linkat(
+    olddirfd: isize,
+    oldpath: *const u8,
+    newdirfd: isize,
+    newpath: *const u8,
+    flags: u32,
+) -> isize {
+    let token = current_user_token();
+    let oldpath = translated_str(token, oldpath);
+    let newpath = translated_str(token, newpath);
+    if flags & !AT_SYMLINK_FOLLOW != 0 {
+        return -EINVAL;
+    }
+    let old = match lookup_at(olddirfd, oldpath.as_str(), flags & AT_SYMLINK_FOLLOW != 0) {
+        Ok(dentry) => dentry,
+        Err(errno) => return errno,
+    };
+    let (dir, name) = match lookup_parent_at(newdirfd, newpath.as_str()) {
+        Ok(pair) => pair,
+        Err(errno) => return errno,
+    };
+    match dir.link(name.as_str(), &old) {
+        Ok(_) => 0,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_fchmodat(dirfd: isize, path: *const u8, mode: u32) -> isize {
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    match lookup_at(dirfd, path.as_str(), true).and_then(|dentry| dentry.inode().set_mode(mode)) {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
+}
+
+/// 挂载文件系统，source为块设备、镜像文件或伪文件系统名，data为逗号分隔的挂载选项
+pub fn sys_mount(
+    source: *const u8,
+    target: *const u8,
+    fs_type: *const u8,
+    _flags: u32,
+    data: *const u8,
+) -> isize {
+    let token = current_user_token();
+    let source = translated_str(token, source);
+    let target = translated_str(token, target);
+    let fs_type = translated_str(token, fs_type);
+    let data = if data.is_null() {
+        String::new()
+    } else {
+        translated_str(token, data)
+    };
+    let work_path = current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .get_work_path();
+    match mount(
+        work_path.as_str(),
+        source.as_str(),
+        target.as_str(),
+        fs_type.as_str(),
+        data.as_str(),
+    ) {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_umount2(target: *const u8, flags: u32) -> isize {
+    let token = current_user_token();
+    let target = translated_str(token, target);
+    let work_path = current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .get_work_path();
+    match umount(work_path.as_str(), target.as_str(), flags) {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_sync() -> isize {
+    // 与Linux一致，sync总是成功，写回失败的块保持为脏
+    let _ = sync_all();
+    0
+}
+
+pub fn sys_fsync(fd: usize) -> isize {
+    do_fsync(fd, false)
+}
+
+pub fn sys_fdatasync(fd: usize) -> isize {
+    do_fsync(fd, true)
+}
+
+fn do_fsync(fd: usize, data_only: bool) -> isize {
+    match get_fd(fd) {
+        Ok(FileDescriptor {
+            ftype: FileType::File(inode),
+            ..
+        }) => match inode.sync(data_only) {
+            Ok(()) => 0,
+            Err(errno) => errno,
+        },
+        // 管道等文件没有需要写回的数据
+        Ok(_) => -EINVAL,
+        Err(errno) => errno,
+    }
+}
+
 pub fn sys_close(fd: usize) -> isize {
     let task = current_task().unwrap();
     let mut inner = task.inner_exclusive_access();
@@ -59,48 +395,331 @@ pub fn sys_close(fd: usize) -> isize {
     if inner.fd_table[fd].is_none() {
         return -1;
     }
-    inner.fd_table[fd].take();
+    let file = inner.fd_table[fd].take();
+    // 关闭文件可能唤醒其他任务，需先释放当前任务的锁
+    drop(inner);
+    drop(file);
     0
 }
 
+/// 获取fd对应的文件描述符
+pub fn get_fd(fd: usize) -> Result<FileDescriptor, isize> {
+    let task = current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    match inner.fd_table.get(fd) {
+        Some(Some(file_descriptor)) => Ok(file_descriptor.clone()),
+        _ => Err(-EBADF),
+    }
+}
+
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
-    match fd {
-        FD_STDOUT => {
-            let buffers = translated_byte_buffer(current_user_token(), buf, len);
-            for buffer in buffers {
-                print!("{}", core::str::from_utf8(buffer).unwrap());
-            }
-            len as isize
+    let token = current_user_token();
+    let file = match get_fd(fd) {
+        Ok(file_descriptor) => file_descriptor.ftype.as_file(),
+        Err(errno) => return errno,
+    };
+    if !file.writable() {
+        return -EBADF;
+    }
+    size_or_errno(file.write(UserBuffer::new(translated_byte_buffer(token, buf, len))))
+}
+
+pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
+    let token = current_user_token();
+    let file = match get_fd(fd) {
+        Ok(file_descriptor) => file_descriptor.ftype.as_file(),
+        Err(errno) => return errno,
+    };
+    if !file.readable() {
+        return -EBADF;
+    }
+    size_or_errno(file.read(UserBuffer::new(translated_byte_buffer(token, buf, len))))
+}
+
+/// 设备控制，目前只有终端支持
+pub fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+    let file = match get_fd(fd) {
+        Ok(file_descriptor) => file_descriptor.ftype.as_file(),
+        Err(errno) => return errno,
+    };
+    match file.ioctl(cmd, arg) {
+        Ok(ret) => ret,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    let inode = match get_fd(fd) {
+        Ok(FileDescriptor {
+            ftype: FileType::File(inode),
+            ..
+        }) => inode,
+        Ok(_) => return -ESPIPE,
+        Err(errno) => return errno,
+    };
+    match inode.lseek(offset, whence) {
+        Some(new_offset) => new_offset as isize,
+        None if whence == SEEK_DATA || whence == SEEK_HOLE => -ENXIO,
+        None => -EINVAL,
+    }
+}
+
+pub fn sys_pread64(fd: usize, buf: *mut u8, len: usize, offset: isize) -> isize {
+    let token = current_user_token();
+    let inode = match get_fd(fd) {
+        Ok(FileDescriptor {
+            ftype: FileType::File(inode),
+            ..
+        }) => inode,
+        Ok(_) => return -ESPIPE,
+        Err(errno) => return errno,
+    };
+    if offset < 0 {
+        return -EINVAL;
+    }
+    if !inode.readable() {
+        return -EBADF;
+    }
+    if inode.is_dir() {
+        return -EISDIR;
+    }
+    let buf = UserBuffer::new(translated_byte_buffer(token, buf, len));
+    size_or_errno(inode.read_at(offset as usize, buf))
+}
+
+pub fn sys_pwrite64(fd: usize, buf: *const u8, len: usize, offset: isize) -> isize {
+    let token = current_user_token();
+    let inode = match get_fd(fd) {
+        Ok(FileDescriptor {
+            ftype: FileType::File(inode),
+            ..
+        }) => inode,
+        Ok(_) => return -ESPIPE,
+        Err(errno) => return errno,
+    };
+    if offset < 0 {
+        return -EINVAL;
+    }
+    if !inode.writable() {
+        return -EBADF;
+    }
+    let buf = UserBuffer::new(translated_byte_buffer(token, buf, len));
+    size_or_errno(inode.write_at(offset as usize, buf))
+}
+
+/// 将用户的iovec数组转换为UserBuffer
+fn translated_iovec(token: usize, iov: *const IoVec, iovcnt: usize) -> Result<UserBuffer, isize> {
+    if iovcnt > IOV_MAX {
+        return Err(-EINVAL);
+    }
+    let mut buffers = Vec::new();
+    for i in 0..iovcnt {
+        let iovec = *translated_ref(token, unsafe { iov.add(i) });
+        if iovec.len == 0 {
+            continue;
+        }
+        buffers.append(&mut translated_byte_buffer(token, iovec.base, iovec.len));
+    }
+    Ok(UserBuffer::new(buffers))
+}
+
+pub fn sys_readv(fd: usize, iov: *const IoVec, iovcnt: usize) -> isize {
+    let token = current_user_token();
+    let file = match get_fd(fd) {
+        Ok(file_descriptor) => file_descriptor.ftype.as_file(),
+        Err(errno) => return errno,
+    };
+    if !file.readable() {
+        return -EBADF;
+    }
+    match translated_iovec(token, iov, iovcnt) {
+        Ok(buf) => size_or_errno(file.read(buf)),
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_writev(fd: usize, iov: *const IoVec, iovcnt: usize) -> isize {
+    let token = current_user_token();
+    let file = match get_fd(fd) {
+        Ok(file_descriptor) => file_descriptor.ftype.as_file(),
+        Err(errno) => return errno,
+    };
+    if !file.writable() {
+        return -EBADF;
+    }
+    match translated_iovec(token, iov, iovcnt) {
+        Ok(buf) => size_or_errno(file.write(buf)),
+        Err(errno) => errno,
+    }
+}
+
+/// 内核缓冲区包装成UserBuffer，调用者需保证buf在UserBuffer使用期间有效
+fn kernel_buffer(buf: &mut [u8]) -> UserBuffer {
+    let slice = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr(), buf.len()) };
+    UserBuffer::new(vec![slice])
+}
+
+/// 数据传输的一端：Some(offset)表示在指定位置读写且不改变文件的读写位置
+struct TransferEnd {
+    ftype: FileType,
+    offset: Option<usize>,
+}
+
+impl TransferEnd {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, isize> {
+        let size = match (&self.ftype, self.offset) {
+            (FileType::File(inode), Some(offset)) => inode.read_at(offset, kernel_buffer(buf)),
+            (ftype, _) => ftype.as_file().read(kernel_buffer(buf)),
+        }?;
+        if let Some(offset) = self.offset.as_mut() {
+            *offset += size;
         }
-        _ => {
-            panic!("Unsupported fd in sys_write!");
+        Ok(size)
+    }
+
+    fn write(&mut self, buf: &mut [u8]) -> Result<usize, isize> {
+        let size = match (&self.ftype, self.offset) {
+            (FileType::File(inode), Some(offset)) => inode.write_at(offset, kernel_buffer(buf)),
+            (ftype, _) => ftype.as_file().write(kernel_buffer(buf)),
+        }?;
+        if let Some(offset) = self.offset.as_mut() {
+            *offset += size;
         }
+        Ok(size)
     }
 }
 
-pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
-    match fd {
-        FD_STDIN => {
-            assert_eq!(len, 1, "Only support len = 1 in sys_read!");
-            let mut c: usize;
-            loop {
-                c = console_getchar();
-                if c == 0 {
-                    suspend_current_and_run_next();
-                    continue;
-                } else {
-                    break;
-                }
-            }
-            let ch = c as u8;
-            let mut buffers = translated_byte_buffer(current_user_token(), buf, len);
-            unsafe {
-                buffers[0].as_mut_ptr().write_volatile(ch);
-            }
-            1
+/// 在内核中将数据从src搬运到dst，最多len字节，返回实际搬运的字节数
+/// 已经搬运部分数据后出错时返回已搬运的字节数
+fn transfer(src: &mut TransferEnd, dst: &mut TransferEnd, len: usize) -> Result<usize, isize> {
+    let mut buf = vec![0u8; PAGE_SIZE.min(len)];
+    let mut total = 0usize;
+    while total < len {
+        let chunk = (len - total).min(buf.len());
+        let write_size = src
+            .read(&mut buf[..chunk])
+            .and_then(|read_size| Ok((read_size, dst.write(&mut buf[..read_size])?)));
+        let (read_size, write_size) = match write_size {
+            Ok(sizes) => sizes,
+            Err(_) if total > 0 => break,
+            Err(errno) => return Err(errno),
+        };
+        total += write_size;
+        if read_size == 0 || write_size < read_size || read_size < chunk {
+            break;
+        }
+    }
+    Ok(total)
+}
+
+/// 构造传输的一端，offset指针非空时从用户态读入起始位置
+fn transfer_end(
+    token: usize,
+    fd: usize,
+    offset: *const isize,
+    read: bool,
+) -> Result<TransferEnd, isize> {
+    let ftype = get_fd(fd)?.ftype;
+    let file = ftype.as_file();
+    if (read && !file.readable()) || (!read && !file.writable()) {
+        return Err(-EBADF);
+    }
+    if let FileType::File(inode) = &ftype {
+        if inode.is_dir() {
+            return Err(-EISDIR);
         }
-        _ => {
-            panic!("Unsupported fd in sys_read!");
+    }
+    let offset = if offset.is_null() {
+        None
+    } else {
+        if !matches!(ftype, FileType::File(_)) {
+            return Err(-ESPIPE);
+        }
+        let offset = *translated_ref(token, offset);
+        if offset < 0 {
+            return Err(-EINVAL);
         }
+        Some(offset as usize)
+    };
+    Ok(TransferEnd { ftype, offset })
+}
+
+/// 将新的位置写回用户态的offset指针
+fn update_offset(token: usize, ptr: *mut isize, end: &TransferEnd) {
+    if let Some(offset) = end.offset {
+        *translated_refmut(token, ptr) = offset as isize;
+    }
+}
+
+pub fn sys_sendfile(out_fd: usize, in_fd: usize, offset: *mut isize, count: usize) -> isize {
+    let token = current_user_token();
+    let mut src = match transfer_end(token, in_fd, offset, true) {
+        Ok(end) => end,
+        Err(errno) => return errno,
+    };
+    let mut dst = match transfer_end(token, out_fd, core::ptr::null(), false) {
+        Ok(end) => end,
+        Err(errno) => return errno,
+    };
+    let size = transfer(&mut src, &mut dst, count);
+    update_offset(token, offset, &src);
+    size_or_errno(size)
+}
+
+pub fn sys_copy_file_range(
+    fd_in: usize,
+    off_in: *mut isize,
+    fd_out: usize,
+    off_out: *mut isize,
+    len: usize,
+    flags: u32,
+) -> isize {
+    let token = current_user_token();
+    if flags != 0 {
+        return -EINVAL;
+    }
+    let mut src = match transfer_end(token, fd_in, off_in, true) {
+        Ok(end) => end,
+        Err(errno) => return errno,
+    };
+    let mut dst = match transfer_end(token, fd_out, off_out, false) {
+        Ok(end) => end,
+        Err(errno) => return errno,
+    };
+    // 只支持普通文件之间的复制
+    match (&src.ftype, &dst.ftype) {
+        (FileType::File(_), FileType::File(_)) => {}
+        _ => return -EINVAL,
+    }
+    let size = transfer(&mut src, &mut dst, len);
+    update_offset(token, off_in, &src);
+    update_offset(token, off_out, &dst);
+    size_or_errno(size)
+}
+
+pub fn sys_splice(
+    fd_in: usize,
+    off_in: *mut isize,
+    fd_out: usize,
+    off_out: *mut isize,
+    len: usize,
+    _flags: u32,
+) -> isize {
+    let token = current_user_token();
+    let mut src = match transfer_end(token, fd_in, off_in, true) {
+        Ok(end) => end,
+        Err(errno) => return errno,
+    };
+    let mut dst = match transfer_end(token, fd_out, off_out, false) {
+        Ok(end) => end,
+        Err(errno) => return errno,
+    };
+    // 至少有一端是管道
+    if let (FileType::File(_), FileType::File(_)) = (&src.ftype, &dst.ftype) {
+        return -EINVAL;
     }
+    let size = transfer(&mut src, &mut dst, len);
+    update_offset(token, off_in, &src);
+    update_offset(token, off_out, &dst);
+    size_or_errno(size)
 }
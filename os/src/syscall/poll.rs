@@ -0,0 +1,306 @@
+//! I/O multiplexing syscalls: pselect6, ppoll and epoll
+use super::errno::*;
+use super::fs::get_fd;
+use crate::fs::{EpollItem, EventPoll, FileDescriptor, FileType, PollEvents, PollWaiters};
+use crate::mm::{translated_ref, translated_refmut};
+use crate::task::{current_task, current_user_token, has_pending_signal};
+use crate::timer::{get_time_ms, TimeSpec, MSEC_PER_SEC, NSEC_PER_SEC, TICKS_PER_SEC};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+const EPOLL_CLOEXEC: usize = 0x80000;
+const EPOLL_CTL_ADD: usize = 1;
+const EPOLL_CTL_DEL: usize = 2;
+const EPOLL_CTL_MOD: usize = 3;
+
+const FD_SET_BITS: usize = 64;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PollFd {
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct EpollEvent {
+    events: u32,
+    data: u64,
+}
+
+/// 将用户给出的相对超时时间转换为到期时刻，NULL表示永不超时
+fn expire_ms(token: usize, timeout: *const TimeSpec) -> Result<Option<usize>, isize> {
+    if timeout.is_null() {
+        return Ok(None);
+    }
+    let timeout = *translated_ref(token, timeout);
+    if timeout.nsec >= NSEC_PER_SEC {
+        return Err(-EINVAL);
+    }
+    let ms = timeout.sec * MSEC_PER_SEC + (timeout.nsec + 999_999) / 1_000_000;
+    Ok(Some(get_time_ms() + ms))
+}
+
+/// 反复调用check直到其返回非零或超时，返回check最后一次的结果，等待期间收到信号返回EINTR
+/// check检查文件时登记其等待队列，之后在这些队列上睡眠直到有文件的状态变化
+fn wait_ready<F: FnMut(&mut PollWaiters) -> usize>(
+    expire_ms: Option<usize>,
+    mut check: F,
+) -> Result<usize, isize> {
+    loop {
+        let mut waiters = PollWaiters::new();
+        let ready = check(&mut waiters);
+        if ready > 0 {
+            return Ok(ready);
+        }
+        let current_ms = get_time_ms();
+        if let Some(expire_ms) = expire_ms {
+            if current_ms >= expire_ms {
+                return Ok(0);
+            }
+        }
+        if has_pending_signal() {
+            return Err(-EINTR);
+        }
+        // 状态变化没有通知的文件(如只能轮询的SBI控制台)最多等待一个时钟周期后重新检查
+        let wait_ms = if waiters.polling {
+            let tick_ms = current_ms + MSEC_PER_SEC / TICKS_PER_SEC;
+            Some(expire_ms.map_or(tick_ms, |e| e.min(tick_ms)))
+        } else {
+            expire_ms
+        };
+        waiters.wait(wait_ms);
+    }
+}
+
+/// 检查fd的就绪状态并登记其等待队列，fd无效时返回None
+fn poll_fd(fd: usize, waiters: &mut PollWaiters) -> Option<PollEvents> {
+    let file = get_fd(fd).ok()?.ftype.as_file();
+    waiters.register(&*file);
+    Some(file.poll())
+}
+
+pub fn sys_pselect6(
+    nfds: usize,
+    readfds: *mut u64,
+    writefds: *mut u64,
+    exceptfds: *mut u64,
+    timeout: *const TimeSpec,
+    _sigmask: usize,
+) -> isize {
+    let token = current_user_token();
+    let expire_ms = match expire_ms(token, timeout) {
+        Ok(expire_ms) => expire_ms,
+        Err(errno) => return errno,
+    };
+    let words = (nfds + FD_SET_BITS - 1) / FD_SET_BITS;
+    let read_set = |set: *mut u64| -> Vec<u64> {
+        if set.is_null() {
+            Vec::new()
+        } else {
+            (0..words)
+                .map(|i| *translated_ref(token, unsafe { set.add(i) }))
+                .collect()
+        }
+    };
+    let sets = [read_set(readfds), read_set(writefds), read_set(exceptfds)];
+    let interested = |set: &Vec<u64>, fd: usize| {
+        !set.is_empty() && set[fd / FD_SET_BITS] & (1 << (fd % FD_SET_BITS)) != 0
+    };
+    for fd in 0..nfds {
+        if sets.iter().any(|set| interested(set, fd)) && get_fd(fd).is_err() {
+            return -EBADF;
+        }
+    }
+    // 每个集合中对应位满足任一事件即为就绪
+    let conditions = [
+        PollEvents::POLLIN | PollEvents::POLLHUP | PollEvents::POLLERR,
+        PollEvents::POLLOUT | PollEvents::POLLERR,
+        PollEvents::POLLPRI,
+    ];
+    let mut results = [Vec::new(), Vec::new(), Vec::new()];
+    let ready = match wait_ready(expire_ms, |waiters| {
+        let mut ready = 0;
+        for (i, set) in sets.iter().enumerate() {
+            results[i] = alloc::vec![0u64; set.len()];
+        }
+        for fd in 0..nfds {
+            if !sets.iter().any(|set| interested(set, fd)) {
+                continue;
+            }
+            let events = match poll_fd(fd, waiters) {
+                Some(events) => events,
+                None => continue,
+            };
+            for i in 0..3 {
+                if interested(&sets[i], fd) && events.intersects(conditions[i]) {
+                    results[i][fd / FD_SET_BITS] |= 1 << (fd % FD_SET_BITS);
+                    ready += 1;
+                }
+            }
+        }
+        ready
+    }) {
+        Ok(ready) => ready,
+        Err(errno) => return errno,
+    };
+    for (set, result) in [readfds, writefds, exceptfds].iter().zip(results.iter()) {
+        for (i, word) in result.iter().enumerate() {
+            *translated_refmut(token, unsafe { set.add(i) }) = *word;
+        }
+    }
+    ready as isize
+}
+
+pub fn sys_ppoll(
+    fds: *mut PollFd,
+    nfds: usize,
+    timeout: *const TimeSpec,
+    _sigmask: usize,
+) -> isize {
+    let token = current_user_token();
+    let expire_ms = match expire_ms(token, timeout) {
+        Ok(expire_ms) => expire_ms,
+        Err(errno) => return errno,
+    };
+    let ready = match wait_ready(expire_ms, |waiters| {
+        let mut ready = 0;
+        for i in 0..nfds {
+            let poll_fd_ref = translated_refmut(token, unsafe { fds.add(i) });
+            poll_fd_ref.revents = 0;
+            if poll_fd_ref.fd < 0 {
+                continue;
+            }
+            // POLLERR/POLLHUP/POLLNVAL不需要关注也会返回
+            let revents = match poll_fd(poll_fd_ref.fd as usize, waiters) {
+                Some(events) => {
+                    events
+                        & (PollEvents::from_bits_truncate(poll_fd_ref.events as u16)
+                            | PollEvents::POLLERR
+                            | PollEvents::POLLHUP)
+                }
+                None => PollEvents::POLLNVAL,
+            };
+            if !revents.is_empty() {
+                poll_fd_ref.revents = revents.bits() as i16;
+                ready += 1;
+            }
+        }
+        ready
+    }) {
+        Ok(ready) => ready,
+        Err(errno) => return errno,
+    };
+    ready as isize
+}
+
+pub fn sys_epoll_create1(flags: usize) -> isize {
+    if flags & !EPOLL_CLOEXEC != 0 {
+        return -EINVAL;
+    }
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let fd = inner.alloc_fd();
+    inner.fd_table[fd] = Some(FileDescriptor::new(
+        flags & EPOLL_CLOEXEC != 0,
+        FileType::Epoll(Arc::new(EventPoll::new())),
+    ));
+    fd as isize
+}
+
+/// 获取epfd对应的epoll实例
+fn get_epoll(epfd: usize) -> Result<Arc<EventPoll>, isize> {
+    match get_fd(epfd)?.ftype {
+        FileType::Epoll(epoll) => Ok(epoll),
+        _ => Err(-EINVAL),
+    }
+}
+
+pub fn sys_epoll_ctl(epfd: usize, op: usize, fd: usize, event: *const EpollEvent) -> isize {
+    let token = current_user_token();
+    let epoll = match get_epoll(epfd) {
+        Ok(epoll) => epoll,
+        Err(errno) => return errno,
+    };
+    let ftype = match get_fd(fd) {
+        Ok(file_descriptor) => file_descriptor.ftype,
+        Err(errno) => return errno,
+    };
+    if fd == epfd {
+        return -EINVAL;
+    }
+    // 与Linux一致，普通文件总是就绪，不能被epoll关注
+    if let FileType::File(_) = ftype {
+        return -EPERM;
+    }
+    let done = match op {
+        EPOLL_CTL_ADD => {
+            let event = *translated_ref(token, event);
+            if !epoll.add(
+                fd,
+                EpollItem {
+                    file: Arc::downgrade(&ftype.as_file()),
+                    events: PollEvents::from_bits_truncate(event.events as u16),
+                    data: event.data,
+                },
+            ) {
+                return -EEXIST;
+            }
+            true
+        }
+        EPOLL_CTL_MOD => {
+            let event = *translated_ref(token, event);
+            epoll.modify(
+                fd,
+                PollEvents::from_bits_truncate(event.events as u16),
+                event.data,
+            )
+        }
+        EPOLL_CTL_DEL => epoll.remove(fd),
+        _ => return -EINVAL,
+    };
+    if done {
+        0
+    } else {
+        -ENOENT
+    }
+}
+
+pub fn sys_epoll_pwait(
+    epfd: usize,
+    events: *mut EpollEvent,
+    maxevents: isize,
+    timeout: isize,
+    _sigmask: usize,
+) -> isize {
+    let token = current_user_token();
+    let epoll = match get_epoll(epfd) {
+        Ok(epoll) => epoll,
+        Err(errno) => return errno,
+    };
+    if maxevents <= 0 {
+        return -EINVAL;
+    }
+    let expire_ms = if timeout < 0 {
+        None
+    } else {
+        Some(get_time_ms() + timeout as usize)
+    };
+    let mut ready_items = Vec::new();
+    if let Err(errno) = wait_ready(expire_ms, |waiters| {
+        epoll.register(waiters);
+        ready_items = epoll.ready_items(maxevents as usize);
+        ready_items.len()
+    }) {
+        return errno;
+    }
+    for (i, (data, revents)) in ready_items.iter().enumerate() {
+        *translated_refmut(token, unsafe { events.add(i) }) = EpollEvent {
+            events: revents.bits() as u32,
+            data: *data,
+        };
+    }
+    ready_items.len() as isize
+}
@@ -1,12 +1,13 @@
 use core::arch::asm;
 
+use super::errno::*;
 use crate::loader::*;
 use crate::mm::{translated_ref, translated_refmut, translated_str};
 use crate::task::{
-    add_task, current_task, current_user_token, exit_current_and_run_next,
-    suspend_current_and_run_next,
+    add_task, current_task, current_user_token, exit_current_and_run_next, pgrp_tasks, pid2task,
+    send_signal, suspend_current_and_run_next, task_pids, TaskControlBlock, SIGMAX,
 };
-use crate::timer::{get_time_ms, get_time_us, USEC_PER_SEC};
+use crate::timer::{get_time_ms, get_wall_time};
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
@@ -25,9 +26,10 @@ pub fn sys_sched_yield() -> isize {
 
 pub fn sys_gettimeofday(ts: *mut u64, _tz: usize) -> isize {
     let token = current_user_token();
-    let curtime = get_time_us();
-    *translated_refmut(token, ts) = (curtime / USEC_PER_SEC) as u64;
-    *translated_refmut(token, unsafe { ts.add(1) }) = (curtime % USEC_PER_SEC) as u64;
+    // 与文件时间使用同一个墙上时钟
+    let now = get_wall_time();
+    *translated_refmut(token, ts) = now.sec as u64;
+    *translated_refmut(token, unsafe { ts.add(1) }) = (now.nsec / 1000) as u64;
     0
 }
 
@@ -67,14 +69,14 @@ pub fn sys_execve(path: *const u8, mut args: *const usize) -> isize {
         }
     }
     let task = current_task().unwrap();
-    let inner = task.inner_exclusive_access();
-    let current_path = inner.current_path.as_str();
+    // 查找文件时不能持有进程的锁，/proc/self等路径需要读取当前进程的信息
+    let current_path = task.inner_exclusive_access().get_work_path();
+    let current_path = current_path.as_str();
 
     /********** 测试开始 *****************/
     // DOING test_all 测试时暂时使用
     if current_path == "/" && path == "test_all" {
-        drop(inner); // 释放锁，否则无法继续进行
-        task.exec(get_test_binary(), args_vec);
+        task.exec(String::from("/test_all"), get_test_binary(), args_vec);
         unsafe {
             asm!("sfence.vma");
             asm!("fence.i"); // 清除TLB
@@ -89,21 +91,27 @@ pub fn sys_execve(path: *const u8, mut args: *const usize) -> isize {
         OpenFlags::RDONLY,
         DiskInodeType::File,
     ) {
-        let all_data = app_inode.read_all();
-        let task = current_task().unwrap();
+        let all_data = match app_inode.read_all() {
+            Ok(data) => data,
+            Err(errno) => return errno,
+        };
         let argc = args_vec.len();
-        drop(inner);
-        task.exec(all_data.as_slice(), args_vec);
+        let exe = app_inode.dentry().path();
+        drop(app_inode);
+        task.exec(exe, all_data.as_slice(), args_vec);
         argc as isize
     } else {
         -1
     }
 }
 
+/// wait4的options，同时报告被信号停止的子进程
+const WUNTRACED: usize = 2;
+
 /// If there is not a child process whose pid is same as given, return -1.
 /// Else if there is a child process but it is still running, return -2.
 /// 如果有子进程正在运行，返回 -2， 如果不存在返回 -1， 否则返回子进程 pid
-pub fn sys_wait4(pid: isize, exit_code_ptr: *mut i32) -> isize {
+pub fn sys_wait4(pid: isize, exit_code_ptr: *mut i32, options: usize) -> isize {
     let task = current_task().unwrap();
     // find a child process
 
@@ -132,8 +140,91 @@ pub fn sys_wait4(pid: isize, exit_code_ptr: *mut i32) -> isize {
         // ++++ release child PCB
         *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
         found_pid as isize
+    } else if options & WUNTRACED != 0 {
+        let stopped = inner.children.iter().find_map(|p| {
+            let child_pid = p.getpid();
+            if pid != -1 && pid as usize != child_pid {
+                return None;
+            }
+            // 每次停止只报告一次
+            let signum = p.inner_exclusive_access().stop_signal.take()?;
+            Some((child_pid, signum))
+        });
+        match stopped {
+            Some((child_pid, signum)) => {
+                // 与Linux相同，低8位为0x7f表示停止，次低8位为信号
+                *translated_refmut(inner.memory_set.token(), exit_code_ptr) =
+                    ((signum << 8) | 0x7f) as i32;
+                child_pid as isize
+            }
+            None => -2,
+        }
     } else {
         -2
     }
     // ---- release current PCB lock automatically
 }
+
+/// 发送信号，pid为0时发送给当前进程组，为-1时发送给除initproc外的所有进程，
+/// 小于-1时发送给进程组-pid。信号为0时只检查目标是否存在
+pub fn sys_kill(pid: isize, signum: usize) -> isize {
+    if signum > SIGMAX {
+        return -EINVAL;
+    }
+    let pgid = current_task().unwrap().inner_exclusive_access().pgid;
+    let targets: Vec<Arc<TaskControlBlock>> = match pid {
+        0 => pgrp_tasks(pgid),
+        -1 => task_pids()
+            .into_iter()
+            .filter(|pid| *pid != 0)
+            .filter_map(pid2task)
+            .collect(),
+        pid if pid > 0 => pid2task(pid as usize).into_iter().collect(),
+        pgid => pgrp_tasks((-pgid) as usize),
+    };
+    if targets.is_empty() {
+        return -ESRCH;
+    }
+    if signum != 0 {
+        for task in targets.iter() {
+            send_signal(task, signum);
+        }
+    }
+    0
+}
+
+/// 设置进程组，只能设置当前进程或其子进程，pgid为0时使用该进程的pid
+pub fn sys_setpgid(pid: usize, pgid: usize) -> isize {
+    if (pgid as isize) < 0 {
+        return -EINVAL;
+    }
+    let current = current_task().unwrap();
+    let task = if pid == 0 || pid == current.getpid() {
+        current.clone()
+    } else {
+        let inner = current.inner_exclusive_access();
+        match inner.children.iter().find(|child| child.getpid() == pid) {
+            Some(child) => child.clone(),
+            None => return -ESRCH,
+        }
+    };
+    let pgid = if pgid == 0 { task.getpid() } else { pgid };
+    // 只能加入已经存在的进程组，或者以自己的pid新建进程组
+    if pgid != task.getpid() && pgrp_tasks(pgid).is_empty() {
+        return -EPERM;
+    }
+    task.inner_exclusive_access().pgid = pgid;
+    0
+}
+
+pub fn sys_getpgid(pid: usize) -> isize {
+    let task = if pid == 0 {
+        current_task()
+    } else {
+        pid2task(pid)
+    };
+    match task {
+        Some(task) => task.inner_exclusive_access().pgid as isize,
+        None => -ESRCH,
+    }
+}
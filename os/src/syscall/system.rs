@@ -1,11 +1,16 @@
 use k210_soc::sleep::usleep;
 
+use crate::drivers::console_flush;
+use crate::fs::unmount_all;
 use crate::mm::{translated_ref, translated_refmut};
 use crate::task::suspend_current_and_run_next;
 use crate::timer::*;
 use crate::{sbi::shutdown, task::current_user_token};
 
 pub fn sys_shutdown() -> ! {
+    // 关机前将脏块写回磁盘并标记各卷为正常卸载，失败也只能继续关机
+    unmount_all();
+    console_flush();
     shutdown();
 }
 
@@ -1,7 +1,8 @@
 //!Implementation of [`TaskManager`]
 use super::TaskControlBlock;
-use alloc::collections::VecDeque;
-use alloc::sync::Arc;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
 use lazy_static::*;
 use spin::Mutex;
 ///A array of `TaskControlBlock` that is thread-safe
@@ -29,6 +30,29 @@ impl TaskManager {
 
 lazy_static! {
     pub static ref TASK_MANAGER: Mutex<TaskManager> = Mutex::new(TaskManager::new());
+    /// 所有未被回收的进程，以pid为键
+    static ref PID2TASK: Mutex<BTreeMap<usize, Weak<TaskControlBlock>>> =
+        Mutex::new(BTreeMap::new());
+}
+///Register a new task so that it can be found by pid
+pub fn insert_task(task: &Arc<TaskControlBlock>) {
+    let mut pid2task = PID2TASK.lock();
+    // 顺便清理已经被回收的进程，其pid可能已被复用
+    pid2task.retain(|_, task| task.strong_count() > 0);
+    pid2task.insert(task.getpid(), Arc::downgrade(task));
+}
+///Find a task that has not been reaped by its pid
+pub fn pid2task(pid: usize) -> Option<Arc<TaskControlBlock>> {
+    PID2TASK.lock().get(&pid).and_then(Weak::upgrade)
+}
+///Pids of all tasks that have not been reaped, in ascending order
+pub fn task_pids() -> Vec<usize> {
+    PID2TASK
+        .lock()
+        .iter()
+        .filter(|(_, task)| task.strong_count() > 0)
+        .map(|(pid, _)| *pid)
+        .collect()
 }
 ///Interface offered to add task
 pub fn add_task(task: Arc<TaskControlBlock>) {
@@ -18,16 +18,19 @@ mod context;
 mod manager;
 mod pid;
 mod processor;
+mod signal;
+mod sleep_lock;
 mod switch;
 #[allow(clippy::module_inception)]
 mod task;
+mod wait_queue;
 
 // use crate::fs::{open, OpenFlags};
 use alloc::sync::Arc;
 use lazy_static::*;
-pub use manager::{fetch_task, TaskManager};
+pub use manager::{fetch_task, pid2task, task_pids, TaskManager};
 use switch::__switch;
-use task::{TaskControlBlock, TaskStatus};
+pub use task::{TaskControlBlock, TaskStatus};
 
 use crate::loader::*;
 
@@ -35,9 +38,16 @@ pub use context::TaskContext;
 pub use manager::add_task;
 pub use pid::{pid_alloc, KernelStack, PidAllocator, PidHandle};
 pub use processor::{
-    current_task, current_trap_cx, current_user_token, run_tasks, schedule, take_current_task,
-    Processor,
+    current_task, current_trap_cx, current_user_token, idle_time_us, run_tasks, schedule,
+    take_current_task, Processor,
 };
+pub use signal::{
+    default_action, handle_signals, has_pending_signal, pgrp_tasks, send_signal,
+    send_signal_to_pgrp, SignalAction, SignalFlags, SIGCONT, SIGINT, SIGMAX, SIGQUIT, SIGTSTP,
+    SIGWINCH,
+};
+pub use sleep_lock::{SleepLock, SleepLockGuard};
+pub use wait_queue::{add_timer, check_timer, has_timer, wait_any, WaitQueue};
 /// Suspend the current 'Running' task and run the next task in task list.
 pub fn suspend_current_and_run_next() {
     // There must be an application running.
@@ -56,8 +66,53 @@ pub fn suspend_current_and_run_next() {
     // jump to scheduling cycle
     schedule(task_cx_ptr);
 }
+/// Block the current 'Running' task until it is woken up by [`wakeup_task`].
+pub fn block_current_and_run_next() {
+    let task = take_current_task().unwrap();
+
+    // ---- access current TCB exclusively
+    let mut task_inner = task.inner_exclusive_access();
+    let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
+    // Change status to Blocking, the waker keeps a reference to the task
+    task_inner.task_status = TaskStatus::Blocking;
+    drop(task_inner);
+    // ---- release current PCB
+
+    drop(task);
+    // jump to scheduling cycle
+    schedule(task_cx_ptr);
+}
+/// Stop the current task on signal `signum` until it receives SIGCONT.
+pub fn stop_current_and_run_next(signum: usize) {
+    let task = take_current_task().unwrap();
+
+    // ---- access current TCB exclusively
+    let mut task_inner = task.inner_exclusive_access();
+    let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
+    // Change status to Stopped, the task is only reachable through the pid table
+    task_inner.task_status = TaskStatus::Stopped;
+    task_inner.stop_signal = Some(signum);
+    drop(task_inner);
+    // ---- release current PCB
+
+    drop(task);
+    // jump to scheduling cycle
+    schedule(task_cx_ptr);
+}
+/// Put a 'Blocking' task back to the ready queue, other tasks are left untouched.
+pub fn wakeup_task(task: Arc<TaskControlBlock>) {
+    let mut task_inner = task.inner_exclusive_access();
+    if task_inner.task_status == TaskStatus::Blocking {
+        task_inner.task_status = TaskStatus::Ready;
+        drop(task_inner);
+        add_task(task);
+    }
+}
 /// Exit the current 'Running' task and run the next task in task list.
 pub fn exit_current_and_run_next(exit_code: i32) {
+    // 关闭文件可能睡眠等待磁盘，要在任务仍在运行且不持有其锁时进行
+    let files = core::mem::take(&mut current_task().unwrap().inner_exclusive_access().fd_table);
+    drop(files);
     // take from Processor
     let task = take_current_task().unwrap();
     // **** access current TCB exclusively
@@ -98,6 +153,7 @@ lazy_static! {
 }
 /// Add init process to the manager
 pub fn add_initproc() {
+    manager::insert_task(&INITPROC);
     add_task(INITPROC.clone());
     // add_task(Arc::new(TaskControlBlock::new(get_hello_binary())));
     // add_task(Arc::new(TaskControlBlock::new("test", get_test_binary())));
@@ -1,9 +1,11 @@
 //!Implementation of [`Processor`] and Intersection of control flow
 use super::__switch;
-use super::{fetch_task, TaskStatus};
+use super::{check_timer, fetch_task, has_timer, TaskStatus};
 use super::{TaskContext, TaskControlBlock};
+use crate::timer::get_time_us;
 use crate::trap::TrapContext;
 use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
 use spin::Mutex;
 ///Processor management structure
@@ -39,6 +41,14 @@ impl Processor {
 lazy_static! {
     pub static ref PROCESSOR: Mutex<Processor> = Mutex::new(Processor::new());
 }
+
+/// 没有任务可运行的累计时间(微秒)
+static IDLE_TIME_US: AtomicUsize = AtomicUsize::new(0);
+
+///Total time the processor has spent idle, in microseconds
+pub fn idle_time_us() -> usize {
+    IDLE_TIME_US.load(Ordering::Relaxed)
+}
 ///The main part of process execution and scheduling
 ///Loop `fetch_task` to get the process that needs to run, and switch the process through `__switch`
 pub fn run_tasks() {
@@ -61,8 +71,19 @@ pub fn run_tasks() {
                 __switch(idle_task_cx_ptr, next_task_cx_ptr);
             }
         } else {
-            println!("No app Run");
+            drop(processor);
+            let idle_start = get_time_us();
+            // 所有任务都在等待时由定时器唤醒
+            check_timer();
+            // 内核中不响应中断，空闲时轮询外部中断和控制台，唤醒等待的任务
+            crate::drivers::irq::handle_irqs();
+            let console_waiting = crate::drivers::console_poll();
+            // 睡眠等待磁盘的任务由设备的中断唤醒
+            if !has_timer() && !console_waiting && !crate::drivers::block_io_waiting() {
+                println!("No app Run");
+            }
             usleep(1000);
+            IDLE_TIME_US.fetch_add(get_time_us() - idle_start, Ordering::Relaxed);
         }
     }
 }
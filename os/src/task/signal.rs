@@ -0,0 +1,175 @@
+//! Signals with their default actions
+//!
+//! User-level handlers are not supported yet, so every signal takes its
+//! default action when the receiving task returns to user space.
+use super::manager::{pid2task, task_pids};
+use super::{
+    add_task, current_task, exit_current_and_run_next, stop_current_and_run_next, wakeup_task,
+    TaskControlBlock, TaskStatus, INITPROC,
+};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bitflags::*;
+
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGWINCH: usize = 28;
+/// 最大的信号编号，实时信号暂不支持
+pub const SIGMAX: usize = 31;
+
+bitflags! {
+    /// 待处理的信号集合，第n位对应n号信号
+    pub struct SignalFlags: u64 {
+        const SIGHUP = 1 << 1;
+        const SIGINT = 1 << 2;
+        const SIGQUIT = 1 << 3;
+        const SIGILL = 1 << 4;
+        const SIGTRAP = 1 << 5;
+        const SIGABRT = 1 << 6;
+        const SIGBUS = 1 << 7;
+        const SIGFPE = 1 << 8;
+        const SIGKILL = 1 << 9;
+        const SIGUSR1 = 1 << 10;
+        const SIGSEGV = 1 << 11;
+        const SIGUSR2 = 1 << 12;
+        const SIGPIPE = 1 << 13;
+        const SIGALRM = 1 << 14;
+        const SIGTERM = 1 << 15;
+        const SIGSTKFLT = 1 << 16;
+        const SIGCHLD = 1 << 17;
+        const SIGCONT = 1 << 18;
+        const SIGSTOP = 1 << 19;
+        const SIGTSTP = 1 << 20;
+        const SIGTTIN = 1 << 21;
+        const SIGTTOU = 1 << 22;
+        const SIGURG = 1 << 23;
+        const SIGXCPU = 1 << 24;
+        const SIGXFSZ = 1 << 25;
+        const SIGVTALRM = 1 << 26;
+        const SIGPROF = 1 << 27;
+        const SIGWINCH = 1 << 28;
+        const SIGIO = 1 << 29;
+        const SIGPWR = 1 << 30;
+        const SIGSYS = 1 << 31;
+    }
+}
+
+/// 信号的默认处理方式
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SignalAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+/// Default action of `signum` as described in signal(7).
+pub fn default_action(signum: usize) -> SignalAction {
+    match signum {
+        SIGCHLD | SIGWINCH | 23 => SignalAction::Ignore, // 23为SIGURG
+        SIGCONT => SignalAction::Continue,
+        SIGSTOP | SIGTSTP | 21 | 22 => SignalAction::Stop, // 21、22为SIGTTIN、SIGTTOU
+        _ => SignalAction::Terminate,
+    }
+}
+
+fn signal_bit(signum: usize) -> u64 {
+    1 << signum
+}
+
+/// Post `signum` to `task`.
+///
+/// Stopped tasks are resumed by SIGCONT and by signals that would kill them;
+/// blocked tasks are woken so that they can notice the signal.
+/// initproc only receives SIGCONT, as it has no way to install handlers.
+pub fn send_signal(task: &Arc<TaskControlBlock>, signum: usize) {
+    if Arc::ptr_eq(task, &INITPROC) && signum != SIGCONT {
+        return;
+    }
+    let action = default_action(signum);
+    if action == SignalAction::Ignore {
+        return;
+    }
+    let mut inner = task.inner_exclusive_access();
+    if inner.task_status == TaskStatus::Zombie {
+        return;
+    }
+    match action {
+        // 继续运行会取消尚未处理的停止信号，反之亦然
+        SignalAction::Continue => inner.signals &= !stop_signals(),
+        SignalAction::Stop => inner.signals &= !SignalFlags::SIGCONT,
+        _ => {}
+    }
+    inner.signals |= SignalFlags::from_bits_truncate(signal_bit(signum));
+    match inner.task_status {
+        TaskStatus::Stopped if action != SignalAction::Stop => {
+            inner.task_status = TaskStatus::Ready;
+            inner.stop_signal = None;
+            drop(inner);
+            add_task(task.clone());
+        }
+        TaskStatus::Blocking => {
+            drop(inner);
+            wakeup_task(task.clone());
+        }
+        _ => {}
+    }
+}
+
+fn stop_signals() -> SignalFlags {
+    SignalFlags::SIGSTOP | SignalFlags::SIGTSTP | SignalFlags::SIGTTIN | SignalFlags::SIGTTOU
+}
+
+/// 进程组pgid中的所有进程
+pub fn pgrp_tasks(pgid: usize) -> Vec<Arc<TaskControlBlock>> {
+    task_pids()
+        .into_iter()
+        .filter_map(pid2task)
+        .filter(|task| task.inner_exclusive_access().pgid == pgid)
+        .collect()
+}
+
+/// 向进程组发送信号，返回收到信号的进程数
+pub fn send_signal_to_pgrp(pgid: usize, signum: usize) -> usize {
+    let tasks = pgrp_tasks(pgid);
+    for task in tasks.iter() {
+        send_signal(task, signum);
+    }
+    tasks.len()
+}
+
+/// 当前进程是否有待处理的信号，阻塞中的系统调用据此返回EINTR
+pub fn has_pending_signal() -> bool {
+    match current_task() {
+        Some(task) => !task.inner_exclusive_access().signals.is_empty(),
+        None => false,
+    }
+}
+
+/// Take the default action of every pending signal of the current task.
+/// Called right before returning to user space.
+pub fn handle_signals() {
+    loop {
+        let task = current_task().unwrap();
+        let mut inner = task.inner_exclusive_access();
+        let signals = inner.signals.bits();
+        if signals == 0 {
+            return;
+        }
+        let signum = signals.trailing_zeros() as usize;
+        inner
+            .signals
+            .remove(SignalFlags::from_bits_truncate(signal_bit(signum)));
+        drop(inner);
+        drop(task);
+        match default_action(signum) {
+            SignalAction::Terminate => exit_current_and_run_next(-(signum as i32)),
+            SignalAction::Stop => stop_current_and_run_next(signum),
+            SignalAction::Ignore | SignalAction::Continue => {}
+        }
+    }
+}
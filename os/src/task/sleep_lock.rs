@@ -0,0 +1,44 @@
+//! A lock whose waiters sleep instead of spinning
+use super::WaitQueue;
+use spin::Mutex;
+
+/// 睡眠锁，持有者可以在持锁期间睡眠(如等待磁盘I/O)，等待者睡眠而不是自旋
+/// 不可重入，同一任务重复加锁会永远睡眠
+pub struct SleepLock {
+    locked: Mutex<bool>,
+    waiters: WaitQueue,
+}
+
+pub struct SleepLockGuard<'a> {
+    lock: &'a SleepLock,
+}
+
+impl SleepLock {
+    pub fn new() -> Self {
+        Self {
+            locked: Mutex::new(false),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// 加锁，锁被占用时睡眠等待持有者释放
+    pub fn lock(&self) -> SleepLockGuard<'_> {
+        loop {
+            let mut locked = self.locked.lock();
+            if !*locked {
+                *locked = true;
+                return SleepLockGuard { lock: self };
+            }
+            drop(locked);
+            // 内核中不响应中断，检查与睡眠之间持有者不会释放锁，不会错过唤醒
+            self.waiters.wait(None);
+        }
+    }
+}
+
+impl Drop for SleepLockGuard<'_> {
+    fn drop(&mut self) {
+        *self.lock.locked.lock() = false;
+        self.lock.waiters.wake_all();
+    }
+}
@@ -1,6 +1,7 @@
 //!Implementation of [`TaskControlBlock`]
-use super::TaskContext;
+use super::manager::insert_task;
 use super::{pid_alloc, KernelStack, PidHandle};
+use super::{SignalFlags, TaskContext};
 use crate::config::TRAP_CONTEXT;
 use crate::mm::{translated_refmut, MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
 use crate::trap::{trap_handler, TrapContext};
@@ -10,7 +11,7 @@ use alloc::vec;
 use alloc::vec::Vec;
 use spin::{Mutex, MutexGuard};
 
-use crate::fs::{FileDescriptor, FileType, Stdin, Stdout};
+use crate::fs::{open, DiskInodeType, FileDescriptor, FileType, OpenFlags, Stdin, Stdout};
 pub type FileDescriptorTable = Vec<Option<FileDescriptor>>;
 
 pub struct TaskControlBlock {
@@ -33,6 +34,18 @@ pub struct TaskControlBlockInner {
 
     pub fd_table: FileDescriptorTable,
     pub current_path: String,
+    /// 正在运行的程序的路径
+    pub exe: String,
+    /// exec时的参数
+    pub args: Vec<String>,
+    /// 进程组号
+    pub pgid: usize,
+    /// 待处理的信号
+    pub signals: SignalFlags,
+    /// 使进程停止、尚未被wait4报告的信号
+    pub stop_signal: Option<usize>,
+    /// 块设备I/O是否可以睡眠等待，调用者不持有自旋锁时才能置位
+    pub io_may_sleep: bool,
 }
 
 impl TaskControlBlockInner {
@@ -68,6 +81,29 @@ impl TaskControlBlockInner {
     }
 }
 
+/// 初始进程的0、1、2号文件描述符，优先打开/dev/console，没有devfs时使用Stdin/Stdout
+fn console_fds() -> Vec<Option<FileDescriptor>> {
+    match open("/", "/dev/console", OpenFlags::RDWR, DiskInodeType::File) {
+        Some(console) => (0..3)
+            .map(|_| Some(FileDescriptor::new(false, FileType::File(console.clone()))))
+            .collect(),
+        None => vec![
+            // 0 -> stdin
+            Some(FileDescriptor::new(false, FileType::Abstr(Arc::new(Stdin)))),
+            // 1 -> stdout
+            Some(FileDescriptor::new(
+                false,
+                FileType::Abstr(Arc::new(Stdout)),
+            )),
+            // 2 -> stderr
+            Some(FileDescriptor::new(
+                false,
+                FileType::Abstr(Arc::new(Stdout)),
+            )),
+        ],
+    }
+}
+
 impl TaskControlBlock {
     pub fn inner_exclusive_access(&self) -> MutexGuard<'_, TaskControlBlockInner> {
         self.inner.lock()
@@ -86,6 +122,8 @@ impl TaskControlBlock {
 
         // alloc a pid and a kernel stack in kernel space
         let pid = pid_alloc();
+        // 初始进程自成一个进程组
+        let pgid = pid.0;
         let kernel_stack = KernelStack::new(&pid);
         let kernel_stack_top = kernel_stack.get_top();
         // push a task context which goes to trap_return to the top of kernel stack
@@ -101,21 +139,14 @@ impl TaskControlBlock {
                 parent: None,
                 children: Vec::new(),
                 exit_code: 0,
-                fd_table: vec![
-                    // 0 -> stdin
-                    Some(FileDescriptor::new(false, FileType::Abstr(Arc::new(Stdin)))),
-                    // 1 -> stdout
-                    Some(FileDescriptor::new(
-                        false,
-                        FileType::Abstr(Arc::new(Stdout)),
-                    )),
-                    // 2 -> stderr
-                    Some(FileDescriptor::new(
-                        false,
-                        FileType::Abstr(Arc::new(Stdout)),
-                    )),
-                ],
+                fd_table: console_fds(),
                 current_path: String::from("/"), // TODO 路径
+                exe: String::from("/initproc"),
+                args: vec![String::from("initproc")],
+                pgid,
+                signals: SignalFlags::empty(),
+                stop_signal: None,
+                io_may_sleep: false,
             })),
         };
         // prepare TrapContext in user space
@@ -129,7 +160,8 @@ impl TaskControlBlock {
         );
         task_control_block
     }
-    pub fn exec(&self, elf_data: &[u8], args: Vec<String>) {
+    /// 以exe处读出的elf_data替换当前程序
+    pub fn exec(&self, exe: String, elf_data: &[u8], args: Vec<String>) {
         // println!("Enter exec handler.");
         // memory_set with elf program headers/trampoline/trap context/user stack
         let (memory_set, mut user_sp, entry_point) = MemorySet::from_elf(elf_data);
@@ -172,6 +204,7 @@ impl TaskControlBlock {
         // update trap_cx ppn
         // println!("update trap_cx ppn.");
         inner.trap_cx_ppn = trap_cx_ppn;
+        inner.exe = exe;
         // println!("initialize trap context.");
         // initialize trap_cx
         let mut trap_cx = TrapContext::app_init_context(
@@ -184,6 +217,7 @@ impl TaskControlBlock {
         trap_cx.x[10] = args.len();
         trap_cx.x[11] = argv_base;
         *inner.get_trap_cx() = trap_cx;
+        inner.args = args;
         // **** release current PCB lock
     }
     pub fn fork(self: &Arc<TaskControlBlock>) -> Arc<TaskControlBlock> {
@@ -214,8 +248,15 @@ impl TaskControlBlock {
                 exit_code: 0,
                 fd_table: new_fd_table,
                 current_path: parent_inner.current_path.clone(),
+                exe: parent_inner.exe.clone(),
+                args: parent_inner.args.clone(),
+                pgid: parent_inner.pgid,
+                signals: SignalFlags::empty(),
+                stop_signal: None,
+                io_may_sleep: false,
             })),
         });
+        insert_task(&task_control_block);
         // add child
         parent_inner.children.push(task_control_block.clone());
         // modify kernel_sp in trap_cx
@@ -238,4 +279,5 @@ pub enum TaskStatus {
     Running,
     Zombie,
     Blocking,
+    Stopped,
 }
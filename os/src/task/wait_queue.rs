@@ -0,0 +1,97 @@
+//! Wait queues and timer-driven wakeups for blocked tasks
+use super::{block_current_and_run_next, current_task, wakeup_task, TaskControlBlock};
+use crate::timer::get_time_ms;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+use spin::Mutex;
+
+/// 等待队列，被唤醒的任务需要自行检查等待的条件是否满足
+pub struct WaitQueue {
+    queue: Mutex<VecDeque<Arc<TaskControlBlock>>>,
+}
+
+impl WaitQueue {
+    pub fn new() -> Self {
+        Self {
+            queue: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// 阻塞当前任务直到被唤醒，expire_ms不为None时最迟在该时刻被定时器唤醒
+    pub fn wait(&self, expire_ms: Option<usize>) {
+        wait_any(&[self], expire_ms);
+    }
+
+    /// 是否有任务在等待
+    pub fn is_empty(&self) -> bool {
+        self.queue.lock().is_empty()
+    }
+
+    /// 唤醒队列中的所有任务
+    pub fn wake_all(&self) {
+        let tasks = core::mem::take(&mut *self.queue.lock());
+        for task in tasks {
+            wakeup_task(task);
+        }
+    }
+}
+
+/// 同时在多个等待队列上阻塞当前任务，被其中任一队列或定时器唤醒后返回
+pub fn wait_any(queues: &[&WaitQueue], expire_ms: Option<usize>) {
+    let task = current_task().unwrap();
+    let timer = expire_ms.map(|expire_ms| add_timer(expire_ms, task.clone()));
+    for queue in queues {
+        queue.queue.lock().push_back(task.clone());
+    }
+    block_current_and_run_next();
+    // 被唤醒时自己仍可能在其他队列中，定时器也可能还未到期，移除以免之后被重复唤醒
+    for queue in queues {
+        queue.queue.lock().retain(|t| !Arc::ptr_eq(t, &task));
+    }
+    if let Some(timer) = timer {
+        cancel_timer(timer);
+    }
+}
+
+/// 定时器的标识，按到期时刻和创建顺序排列
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimerId(usize, usize);
+
+lazy_static! {
+    static ref TIMERS: Mutex<BTreeMap<TimerId, Arc<TaskControlBlock>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+static NEXT_TIMER: AtomicUsize = AtomicUsize::new(0);
+
+/// 在expire_ms时刻唤醒task，返回的标识可以用于提前取消
+pub fn add_timer(expire_ms: usize, task: Arc<TaskControlBlock>) -> TimerId {
+    let id = TimerId(expire_ms, NEXT_TIMER.fetch_add(1, Ordering::Relaxed));
+    TIMERS.lock().insert(id, task);
+    id
+}
+
+/// 取消还未到期的定时器，已到期的不受影响
+pub fn cancel_timer(id: TimerId) {
+    TIMERS.lock().remove(&id);
+}
+
+/// 唤醒所有已到期定时器对应的任务，在时钟中断和空闲时调用
+pub fn check_timer() {
+    let current_ms = get_time_ms();
+    let mut timers = TIMERS.lock();
+    while let Some((&id, _)) = timers.iter().next() {
+        if id.0 > current_ms {
+            break;
+        }
+        let task = timers.remove(&id).unwrap();
+        wakeup_task(task);
+    }
+}
+
+/// 是否还有等待到期的定时器
+pub fn has_timer() -> bool {
+    !TIMERS.lock().is_empty()
+}
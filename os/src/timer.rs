@@ -7,6 +7,18 @@ use riscv::register::time;
 pub const USEC_PER_SEC: usize = 1000000;
 pub const TICKS_PER_SEC: usize = 100;
 pub const MSEC_PER_SEC: usize = 1000;
+pub const NSEC_PER_SEC: usize = 1000000000;
+
+/// 没有RTC，开机时刻假定为2022-01-01 00:00:00 UTC
+pub const BOOT_EPOCH_SEC: usize = 1640995200;
+
+/// 用户态的timespec结构
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
 ///get current time
 pub fn get_time() -> usize {
     time::read()
@@ -20,6 +32,15 @@ pub fn get_time_us() -> usize {
     time::read() * 1000 / (CLOCK_FREQ / MSEC_PER_SEC)
 }
 
+/// 墙上时间，即开机时刻加上开机以来经过的时间
+pub fn get_wall_time() -> TimeSpec {
+    let us = get_time_us();
+    TimeSpec {
+        sec: BOOT_EPOCH_SEC + us / USEC_PER_SEC,
+        nsec: us % USEC_PER_SEC * 1000,
+    }
+}
+
 /// set the next timer interrupt
 pub fn set_next_trigger() {
     set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
@@ -14,12 +14,18 @@
 mod context;
 
 use crate::config::{TRAMPOLINE, TRAP_CONTEXT};
+use crate::drivers::irq_counts;
+use crate::fs::{periodic_writeback, TTY};
 use crate::syscall::syscall;
 use crate::task::{
-    current_trap_cx, current_user_token, exit_current_and_run_next, suspend_current_and_run_next,
+    check_timer, current_trap_cx, current_user_token, exit_current_and_run_next, handle_signals,
+    suspend_current_and_run_next,
 };
-use crate::timer::set_next_trigger;
+use crate::timer::{get_time, set_next_trigger};
+use alloc::vec;
+use alloc::vec::Vec;
 use core::arch::{asm, global_asm};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
@@ -27,6 +33,16 @@ use riscv::register::{
 };
 
 global_asm!(include_str!("trap.S"));
+
+/// 时钟中断的次数
+static TIMER_INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+
+/// 各中断的(中断号, 发生次数, 名称)，时钟中断为scause中的编号，外部中断为PLIC的中断号
+pub fn interrupt_counts() -> Vec<(usize, usize, &'static str)> {
+    let mut counts = vec![(5, TIMER_INTERRUPTS.load(Ordering::Relaxed), "riscv-timer")];
+    counts.extend(irq_counts());
+    counts
+}
 /// initialize CSR `stvec` as the entry of `__alltraps`
 pub fn init() {
     set_kernel_trap_entry();
@@ -62,7 +78,10 @@ pub fn trap_handler() -> ! {
             let mut cx = current_trap_cx();
             cx.sepc += 4;
             // get system call return value
-            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]);
+            let result = syscall(
+                cx.x[17],
+                [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]],
+            );
             // cx is changed during sys_exec, so we have to call it again
             cx = current_trap_cx();
             cx.x[10] = result as usize;
@@ -87,8 +106,16 @@ pub fn trap_handler() -> ! {
             // illegal instruction exit code
             exit_current_and_run_next(-3);
         }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            crate::board::irq_handler();
+        }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
+            TIMER_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+            crate::random::add_entropy(get_time() as u64);
             set_next_trigger();
+            check_timer();
+            periodic_writeback();
+            TTY.receive();
             suspend_current_and_run_next();
         }
         _ => {
@@ -99,6 +126,7 @@ pub fn trap_handler() -> ! {
             );
         }
     }
+    handle_signals();
     trap_return();
 }
 